@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE email_recipients DROP COLUMN auto_replied;
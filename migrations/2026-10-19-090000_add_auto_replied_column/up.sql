@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE email_recipients ADD COLUMN auto_replied BOOLEAN NOT NULL DEFAULT FALSE;
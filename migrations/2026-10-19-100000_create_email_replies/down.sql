@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS email_reply_attachments;
+DROP TABLE IF EXISTS email_replies;
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS email_replies (
+    id INTEGER NOT NULL PRIMARY KEY,
+    email_recipient_id INTEGER NOT NULL REFERENCES email_recipients(id),
+    message_id TEXT,
+    sender TEXT NOT NULL,
+    received_at TIMESTAMP NOT NULL,
+    subject TEXT,
+    body TEXT NOT NULL,
+    is_auto_reply BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS email_reply_attachments (
+    id INTEGER NOT NULL PRIMARY KEY,
+    reply_id INTEGER NOT NULL REFERENCES email_replies(id),
+    name TEXT,
+    mime TEXT,
+    size INTEGER NOT NULL
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE hubs DROP COLUMN imap_sent_all;
+ALTER TABLE hubs DROP COLUMN imap_sent_folder;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE hubs ADD COLUMN imap_sent_folder TEXT;
+ALTER TABLE hubs ADD COLUMN imap_sent_all BOOLEAN NOT NULL DEFAULT FALSE;
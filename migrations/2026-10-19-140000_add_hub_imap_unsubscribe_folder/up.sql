@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE hubs ADD COLUMN imap_unsubscribe_folder TEXT;
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE api_keys (
+    id INTEGER NOT NULL PRIMARY KEY,
+    hub_id INTEGER NOT NULL REFERENCES hubs(id),
+    `name` VARCHAR(255) NOT NULL,
+    prefix VARCHAR(32) NOT NULL UNIQUE,
+    key_hash VARCHAR(255) NOT NULL,
+    scopes VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP,
+    revoked_at TIMESTAMP
+);
+CREATE INDEX api_keys_hub_id ON api_keys(hub_id);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE emails DROP COLUMN is_transactional;
+DROP TABLE message_templates;
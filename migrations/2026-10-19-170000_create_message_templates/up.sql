@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE message_templates (
+    id INTEGER NOT NULL PRIMARY KEY,
+    hub_id INTEGER NOT NULL REFERENCES hubs(id),
+    name VARCHAR(255) NOT NULL,
+    subject TEXT NOT NULL,
+    body TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (hub_id, name)
+);
+
+ALTER TABLE emails ADD COLUMN is_transactional BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX emails_hub_id_created_at;
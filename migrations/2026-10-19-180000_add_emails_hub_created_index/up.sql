@@ -0,0 +1,2 @@
+-- Your SQL goes here
+CREATE INDEX emails_hub_id_created_at ON emails(hub_id, created_at);
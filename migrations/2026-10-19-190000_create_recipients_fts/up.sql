@@ -0,0 +1,18 @@
+-- Your SQL goes here
+CREATE VIRTUAL TABLE recipients_fts USING fts5(
+    name,
+    email,
+    fields,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+INSERT INTO recipients_fts (rowid, name, email, fields)
+SELECT
+    recipients.id,
+    recipients.name,
+    recipients.email,
+    COALESCE(
+        (SELECT group_concat(value, ' ') FROM recipient_fields WHERE recipient_id = recipients.id),
+        ''
+    )
+FROM recipients;
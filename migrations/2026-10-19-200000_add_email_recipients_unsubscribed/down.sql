@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX email_recipients_address;
+ALTER TABLE email_recipients DROP COLUMN unsubscribed;
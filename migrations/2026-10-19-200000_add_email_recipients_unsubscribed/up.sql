@@ -0,0 +1,22 @@
+-- Your SQL goes here
+ALTER TABLE email_recipients ADD COLUMN unsubscribed BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Attribute past unsubscribes to the last campaign the recipient got before
+UPDATE email_recipients SET unsubscribed = TRUE WHERE id IN (
+    SELECT (
+        SELECT email_recipients.id
+        FROM email_recipients
+        JOIN emails ON emails.id = email_recipients.email_id
+        WHERE emails.hub_id = recipients.hub_id
+            AND NOT emails.is_transactional
+            AND email_recipients.address = recipients.email
+            AND email_recipients.is_sent
+            AND emails.created_at <= recipients.unsubscribed_at
+        ORDER BY emails.created_at DESC
+        LIMIT 1
+    )
+    FROM recipients
+    WHERE recipients.unsubscribed_at IS NOT NULL
+);
+
+CREATE INDEX email_recipients_address ON email_recipients(`address`);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE email_recipients DROP COLUMN replied_at;
+ALTER TABLE email_recipients DROP COLUMN opened_at;
+ALTER TABLE email_recipients DROP COLUMN sent_at;
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+ALTER TABLE email_recipients ADD COLUMN sent_at TIMESTAMP;
+ALTER TABLE email_recipients ADD COLUMN opened_at TIMESTAMP;
+ALTER TABLE email_recipients ADD COLUMN replied_at TIMESTAMP;
+
+-- Earlier sends and opens were not timed, replies keep their date
+UPDATE email_recipients SET replied_at = (
+    SELECT MIN(email_replies.received_at)
+    FROM email_replies
+    WHERE email_replies.email_recipient_id = email_recipients.id
+        AND NOT email_replies.is_auto_reply
+)
+WHERE replied;
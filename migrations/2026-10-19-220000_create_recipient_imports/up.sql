@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE recipient_imports (
+    id INTEGER NOT NULL PRIMARY KEY,
+    hub_id INTEGER NOT NULL REFERENCES hubs(id),
+    file_name VARCHAR(255) NOT NULL,
+    content BLOB NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX recipient_imports_hub_id ON recipient_imports(hub_id);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE recipient_imports DROP COLUMN column_map;
+ALTER TABLE recipient_imports DROP COLUMN delimiter;
+ALTER TABLE recipient_imports DROP COLUMN encoding;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE recipient_imports ADD COLUMN encoding VARCHAR(32);
+ALTER TABLE recipient_imports ADD COLUMN delimiter VARCHAR(8);
+ALTER TABLE recipient_imports ADD COLUMN column_map TEXT;
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE recipient_imports ADD COLUMN sheet VARCHAR(255);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE recipient_imports DROP COLUMN assign_group;
+ALTER TABLE recipient_imports DROP COLUMN mode;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE recipient_imports ADD COLUMN mode VARCHAR(16);
+ALTER TABLE recipient_imports ADD COLUMN assign_group VARCHAR(255);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+-- The addresses are not restored, the normalized ones stay valid.
+DROP VIEW recipient_duplicate_emails;
@@ -0,0 +1,68 @@
+-- Your SQL goes here
+-- Trims the addresses and lowercases their domains. International domains are converted
+-- to punycode by the application when the recipient is saved again.
+-- Addresses equal to another one of the hub but for the case are left as they are and
+-- listed by recipient_duplicate_emails.
+CREATE TEMP TABLE normalized_recipients AS
+SELECT
+    id,
+    hub_id,
+    email,
+    substr(trim(email), 1, instr(trim(email), '@')) ||
+        lower(substr(trim(email), instr(trim(email), '@') + 1)) AS normalized
+FROM recipients
+-- Quoted local parts may contain another `@`, those are left to the application
+WHERE instr(email, '@') > 0 AND instr(substr(email, instr(email, '@') + 1), '@') = 0;
+
+UPDATE recipients
+SET email = (SELECT normalized FROM normalized_recipients WHERE normalized_recipients.id = recipients.id)
+WHERE id IN (
+    SELECT id FROM normalized_recipients AS n
+    WHERE n.normalized != n.email
+    AND NOT EXISTS (
+        SELECT 1 FROM normalized_recipients AS other
+        WHERE other.hub_id = n.hub_id
+        AND other.id != n.id
+        AND lower(other.normalized) = lower(n.normalized)
+    )
+);
+
+DROP TABLE normalized_recipients;
+
+UPDATE recipients_fts
+SET email = (SELECT email FROM recipients WHERE recipients.id = recipients_fts.rowid);
+
+CREATE TEMP TABLE normalized_addresses AS
+SELECT
+    id,
+    email_id,
+    address,
+    substr(trim(address), 1, instr(trim(address), '@')) ||
+        lower(substr(trim(address), instr(trim(address), '@') + 1)) AS normalized
+FROM email_recipients
+WHERE instr(address, '@') > 0 AND instr(substr(address, instr(address, '@') + 1), '@') = 0;
+
+UPDATE email_recipients
+SET address = (SELECT normalized FROM normalized_addresses WHERE normalized_addresses.id = email_recipients.id)
+WHERE id IN (
+    SELECT id FROM normalized_addresses AS n
+    WHERE n.normalized != n.address
+    AND NOT EXISTS (
+        SELECT 1 FROM normalized_addresses AS other
+        WHERE other.email_id = n.email_id
+        AND other.id != n.id
+        AND lower(other.normalized) = lower(n.normalized)
+    )
+);
+
+DROP TABLE normalized_addresses;
+
+CREATE VIEW recipient_duplicate_emails AS
+SELECT
+    hub_id,
+    lower(trim(email)) AS address,
+    COUNT(*) AS num_recipients,
+    group_concat(id) AS recipient_ids
+FROM recipients
+GROUP BY hub_id, lower(trim(email))
+HAVING COUNT(*) > 1;
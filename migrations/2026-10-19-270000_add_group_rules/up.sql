@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- Condition of a smart group, members are found by it when an email is sent
+ALTER TABLE groups ADD COLUMN rules TEXT;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- First time a tracked link of the message was followed
+ALTER TABLE email_recipients ADD COLUMN clicked_at TIMESTAMP;
@@ -0,0 +1,309 @@
+use log::{error, info};
+use mail_parser::MessageParser;
+
+use pushkind_emailer::db::DbConnection;
+use pushkind_emailer::models::hub::Hub;
+use pushkind_emailer::repository::email::get_hub_email_recipient_since;
+use pushkind_emailer::repository::hub::{get_hub_imap_folder, save_hub_imap_folder};
+use pushkind_emailer::utils::{ImapSession, connect_imap_server};
+
+use crate::replies::{process_email_reply, referenced_recipient_ids};
+use crate::unsubscribe::{
+    is_unsubscribe_body, is_unsubscribe_subject, process_unsubscribe, subscribed_sender,
+};
+
+/// Settings the checker needs from a hub, used to notice when they change.
+#[derive(Clone, PartialEq)]
+pub struct ImapSettings {
+    pub server: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    pub folders: Vec<String>,
+    pub unsubscribe_folder: Option<String>,
+}
+
+impl ImapSettings {
+    pub fn from_hub(hub: &Hub) -> Option<Self> {
+        match (&hub.imap_server, hub.imap_port, &hub.login, &hub.password) {
+            (Some(server), Some(port), Some(username), Some(password)) => Some(Self {
+                server: server.clone(),
+                port: port as u16,
+                username: username.clone(),
+                password: password.clone(),
+                folders: hub.get_imap_folders(),
+                unsubscribe_folder: hub
+                    .imap_unsubscribe_folder
+                    .clone()
+                    .filter(|folder| !folder.is_empty()),
+            }),
+            _ => None,
+        }
+    }
+}
+
+pub fn connect(settings: &ImapSettings) -> imap::error::Result<ImapSession> {
+    connect_imap_server(
+        &settings.server,
+        settings.port,
+        &settings.username,
+        &settings.password,
+    )
+}
+
+fn uid_set(uids: &[u32]) -> String {
+    uids.iter()
+        .map(|uid| uid.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Moves messages out of the selected folder, emulating MOVE on servers without it.
+fn move_messages(session: &mut ImapSession, uids: &[u32], folder: &str) -> imap::error::Result<()> {
+    let uids = uid_set(uids);
+    let capabilities = session.capabilities()?;
+    let (has_move, has_uidplus) = (
+        capabilities.has_str("MOVE"),
+        capabilities.has_str("UIDPLUS"),
+    );
+
+    if has_move {
+        return session.uid_mv(&uids, folder);
+    }
+
+    session.uid_copy(&uids, folder)?;
+    session.uid_store(&uids, "+FLAGS (\\Deleted)")?;
+    // Without UIDPLUS only the whole folder can be expunged, which also removes messages
+    // flagged for deletion elsewhere, as any client would on closing the folder
+    if has_uidplus {
+        session.uid_expunge(&uids)?;
+    } else {
+        session.expunge()?;
+    }
+
+    Ok(())
+}
+
+/// Processes messages that arrived in `folder` since the previous scan.
+///
+/// Only IMAP failures are returned, so the caller can decide whether to reconnect;
+/// database failures are logged and end the scan early.
+pub fn scan_folder(
+    db_conn: &mut DbConnection,
+    session: &mut ImapSession,
+    hub_id: i32,
+    folder: &str,
+    unsubscribe_folder: Option<&str>,
+    domain: &str,
+    max_age: chrono::Duration,
+) -> imap::error::Result<()> {
+    // A missing folder should not break the connection for the remaining ones
+    let mailbox = match session.select(folder) {
+        Ok(mailbox) => mailbox,
+        Err(imap::error::Error::No(e)) => {
+            error!("Cannot select {}: {}", folder, e);
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+    info!("Selected {}", folder);
+
+    let since = chrono::Utc::now().naive_utc() - max_age;
+    let uid_validity = mailbox.uid_validity.unwrap_or_default() as i64;
+
+    let last_uid = match get_hub_imap_folder(db_conn, hub_id, folder) {
+        Ok(Some(state)) if state.uid_validity == uid_validity => Some(state.last_uid as u32),
+        Ok(_) => None,
+        Err(e) => {
+            error!("Cannot get imap folder state: {}", e);
+            return Ok(());
+        }
+    };
+
+    // Without a usable position (first run or UIDVALIDITY changed) start from the age cutoff
+    let query = match last_uid {
+        Some(last_uid) => format!("UID {}:*", last_uid + 1),
+        None => format!("SINCE {}", since.format("%d-%b-%Y")),
+    };
+    let mut uids = session
+        .uid_search(&query)?
+        .into_iter()
+        .filter(|uid| last_uid.is_none_or(|last_uid| *uid > last_uid))
+        .collect::<Vec<_>>();
+    uids.sort_unstable();
+
+    info!("Found {} new emails in {}", uids.len(), folder);
+
+    let mut candidates = Vec::new();
+    // Messages that could not be processed, the checkpoint stays before the first of them
+    let mut failed = Vec::new();
+
+    if !uids.is_empty() {
+        let fetched = session.uid_fetch(uid_set(&uids), "(UID BODY.PEEK[HEADER])")?;
+
+        let parser = MessageParser::default();
+        let mut parsed = Vec::new();
+        for fetch in fetched.iter() {
+            let Some(uid) = fetch.uid else {
+                continue;
+            };
+            match fetch
+                .header()
+                .and_then(|header| parser.parse_headers(header))
+            {
+                Some(message) => parsed.push((uid, message)),
+                None => error!("Cannot parse headers of email {}", uid),
+            }
+        }
+        failed.extend(
+            uids.iter()
+                .filter(|uid| parsed.iter().all(|(parsed, _)| parsed != *uid)),
+        );
+
+        for (uid, message) in parsed {
+            let mut reply = None;
+            let mut lookup_failed = false;
+            for recipient_id in referenced_recipient_ids(&message, domain) {
+                match get_hub_email_recipient_since(db_conn, hub_id, recipient_id, &since) {
+                    Ok(Some(recipient)) => {
+                        reply = Some(recipient);
+                        break;
+                    }
+                    Ok(None) => continue,
+                    Err(e) => {
+                        error!("Cannot get email recipient: {}", e);
+                        lookup_failed = true;
+                    }
+                }
+            }
+            if reply.is_none() && lookup_failed {
+                failed.push(uid);
+                continue;
+            }
+
+            // Bodies are only checked for unsubscribe keywords when the sender is a recipient
+            let unsubscribe_subject = is_unsubscribe_subject(&message);
+            if reply.is_some()
+                || unsubscribe_subject
+                || subscribed_sender(db_conn, hub_id, &message).is_some()
+            {
+                candidates.push((uid, reply, unsubscribe_subject));
+            }
+        }
+    }
+
+    let mut unsubscribes = Vec::new();
+
+    for (uid, reply, unsubscribe_subject) in candidates {
+        let fetched = session.uid_fetch(uid.to_string(), "BODY.PEEK[]")?;
+
+        let parser = MessageParser::default();
+        let Some(message) = fetched
+            .iter()
+            .find_map(|fetch| fetch.body().and_then(|body| parser.parse(body)))
+        else {
+            error!("Cannot parse email {}", uid);
+            failed.push(uid);
+            continue;
+        };
+
+        if let Some(recipient) = &reply {
+            info!(
+                "Found reply {} for email_id: {}, recipient: {}",
+                uid, recipient.email_id, recipient.address
+            );
+            process_email_reply(db_conn, recipient, &message);
+        }
+
+        if (unsubscribe_subject || is_unsubscribe_body(&message))
+            && process_unsubscribe(db_conn, hub_id, &message)
+        {
+            unsubscribes.push(uid);
+        }
+    }
+
+    if let Some(target) = unsubscribe_folder
+        && !unsubscribes.is_empty()
+    {
+        match move_messages(session, &unsubscribes, target) {
+            Ok(()) => info!(
+                "Moved {} unsubscribe requests to {}",
+                unsubscribes.len(),
+                target
+            ),
+            Err(imap::error::Error::No(e)) => error!("Cannot move messages to {}: {}", target, e),
+            Err(e) => return Err(e),
+        }
+    }
+
+    // A failed message is retried on the next scan, replies already stored are not duplicated
+    let last_uid = match failed.iter().min() {
+        Some(&first_failed) => {
+            error!(
+                "Scan of {} stopped before email {}, it is retried next time",
+                folder, first_failed
+            );
+            uids.iter()
+                .copied()
+                .filter(|uid| *uid < first_failed)
+                .max()
+                .or(last_uid)
+        }
+        None => uids
+            .last()
+            .copied()
+            .or(last_uid)
+            .or(mailbox.uid_next.map(|uid_next| uid_next.saturating_sub(1))),
+    };
+    // Without any position the next scan starts from the age cutoff again
+    if let Some(last_uid) = last_uid
+        && let Err(e) = save_hub_imap_folder(db_conn, hub_id, folder, uid_validity, last_uid as i64)
+    {
+        error!("Cannot save imap folder state: {}", e);
+    }
+
+    Ok(())
+}
+
+pub fn check_hub_email_replied(
+    db_conn: &mut DbConnection,
+    hub: &Hub,
+    domain: &str,
+    max_age: chrono::Duration,
+) {
+    let settings = match ImapSettings::from_hub(hub) {
+        Some(settings) => settings,
+        None => {
+            error!("Cannot get imap server and port for the hub");
+            return;
+        }
+    };
+
+    let mut session = match connect(&settings) {
+        Ok(session) => session,
+        Err(e) => {
+            error!("Cannot connect to imap server: {}", e);
+            return;
+        }
+    };
+
+    for folder in &settings.folders {
+        if let Err(e) = scan_folder(
+            db_conn,
+            &mut session,
+            hub.id,
+            folder,
+            settings.unsubscribe_folder.as_deref(),
+            domain,
+            max_age,
+        ) {
+            error!("Cannot check {}: {}", folder, e);
+            break;
+        }
+    }
+
+    match session.logout() {
+        Ok(_) => info!("Logged out"),
+        Err(e) => error!("Cannot logout: {}", e),
+    }
+}
@@ -0,0 +1,74 @@
+use std::env;
+use std::time::Duration;
+
+use dotenvy::dotenv;
+use log::{error, info};
+
+use pushkind_emailer::db::{establish_connection_pool, get_db_connection};
+use pushkind_emailer::repository::hub::list_hubs;
+
+use crate::mailbox::check_hub_email_replied;
+use crate::watcher::WatcherConfig;
+
+mod mailbox;
+mod replies;
+mod unsubscribe;
+mod watcher;
+
+fn env_number(name: &str, default: u64) -> u64 {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+fn main() {
+    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
+    dotenv().ok(); // Load .env file
+
+    let database_url = env::var("DATABASE_URL").unwrap_or_else(|_| "app.db".to_string());
+    let domain = env::var("DOMAIN").unwrap_or_default();
+    let max_age = chrono::Duration::days(env_number("REPLY_MAX_AGE_DAYS", 30) as i64);
+
+    let db_pool = match establish_connection_pool(database_url) {
+        Ok(pool) => pool,
+        Err(e) => {
+            error!("Cannot establish db connection: {}", e);
+            return;
+        }
+    };
+
+    if env::args().any(|arg| arg == "--daemon") {
+        let config = WatcherConfig {
+            domain,
+            max_age,
+            poll_interval: Duration::from_secs(env_number("REPLY_POLL_INTERVAL", 60)),
+            reload_interval: Duration::from_secs(env_number("REPLY_RELOAD_INTERVAL", 60)),
+        };
+
+        info!("Starting reply watcher");
+        watcher::run(db_pool, config);
+        return;
+    }
+
+    let mut db_conn = match get_db_connection(&db_pool) {
+        Some(conn) => conn,
+        None => {
+            error!("Cannot get db connection");
+            return;
+        }
+    };
+
+    let hubs = match list_hubs(&mut db_conn) {
+        Ok(hub) => hub,
+        Err(e) => {
+            error!("Cannot get hub: {}", e);
+            return;
+        }
+    };
+
+    for hub in hubs {
+        info!("Checking hub: {}", hub.id);
+        check_hub_email_replied(&mut db_conn, &hub, &domain, max_age);
+    }
+}
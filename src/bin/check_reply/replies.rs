@@ -0,0 +1,217 @@
+use log::{error, info};
+use mail_parser::{Message, MimeHeaders};
+
+use pushkind_emailer::db::DbConnection;
+use pushkind_emailer::models::email::{EmailRecipient, NewEmailReply, NewEmailReplyAttachment};
+use pushkind_emailer::models::webhook::WebhookEvent;
+use pushkind_emailer::repository::email::{
+    create_email_reply, email_reply_exists, set_email_recipient_auto_replied_status,
+    set_email_recipient_replied_status, update_email_num_replied,
+};
+use pushkind_emailer::repository::webhook::queue_email_recipient_event;
+
+/// Subject prefixes put by common auto-responders, compared in lowercase. Only the start of
+/// the subject is checked, a person replying to a vacation notice writes `Re:` first.
+const AUTO_REPLY_SUBJECTS: [&str; 11] = [
+    "auto:",
+    "autoreply",
+    "auto-reply",
+    "auto reply",
+    "automatic reply",
+    "out of office:",
+    "out of the office:",
+    "автоответ",
+    "автоматический ответ",
+    "нет на месте:",
+    "вне офиса:",
+];
+
+fn header_text<'a>(message: &'a Message, name: &'static str) -> Option<&'a str> {
+    message.header_raw(name).map(str::trim)
+}
+
+/// Tells vacation notices and other auto-responders apart from replies written by a person.
+fn is_auto_reply(message: &Message) -> bool {
+    if header_text(message, "Auto-Submitted").is_some_and(|value| !value.eq_ignore_ascii_case("no"))
+    {
+        return true;
+    }
+
+    if header_text(message, "X-Autoreply").is_some()
+        || header_text(message, "X-Autorespond").is_some()
+    {
+        return true;
+    }
+
+    if header_text(message, "Precedence")
+        .is_some_and(|value| value.eq_ignore_ascii_case("auto_reply"))
+    {
+        return true;
+    }
+
+    let subject = message.subject().unwrap_or_default().trim().to_lowercase();
+    AUTO_REPLY_SUBJECTS
+        .iter()
+        .any(|pattern| subject.starts_with(pattern))
+}
+
+fn save_email_reply(
+    db_conn: &mut DbConnection,
+    email_recipient_id: i32,
+    message: &Message,
+    is_auto_reply: bool,
+) {
+    let received_at = message
+        .date()
+        .and_then(|date| chrono::DateTime::from_timestamp(date.to_timestamp(), 0))
+        .map(|date| date.naive_utc())
+        .unwrap_or_else(|| chrono::Utc::now().naive_utc());
+    let message_id = message.message_id();
+
+    match email_reply_exists(db_conn, email_recipient_id, message_id, &received_at) {
+        Ok(false) => (),
+        Ok(true) => return,
+        Err(e) => {
+            error!("Cannot check stored replies: {}", e);
+            return;
+        }
+    }
+
+    let sender = message
+        .from()
+        .and_then(|from| from.first())
+        .map(|from| match (from.name(), from.address()) {
+            (Some(name), Some(address)) => format!("{} <{}>", name, address),
+            (_, address) => address.unwrap_or_default().to_string(),
+        })
+        .unwrap_or_default();
+    let body = message.body_text(0).unwrap_or_default();
+
+    let mimes = message
+        .attachments()
+        .map(|attachment| {
+            attachment.content_type().map(|ct| match ct.subtype() {
+                Some(subtype) => format!("{}/{}", ct.ctype(), subtype),
+                None => ct.ctype().to_string(),
+            })
+        })
+        .collect::<Vec<_>>();
+    let attachments = message
+        .attachments()
+        .zip(&mimes)
+        .map(|(attachment, mime)| NewEmailReplyAttachment {
+            reply_id: 0, // assigned by create_email_reply
+            name: attachment.attachment_name(),
+            mime: mime.as_deref(),
+            size: attachment.len() as i32,
+        })
+        .collect();
+
+    let reply = NewEmailReply {
+        email_recipient_id,
+        message_id,
+        sender: &sender,
+        received_at: &received_at,
+        subject: message.subject(),
+        body: &body,
+        is_auto_reply,
+    };
+
+    match create_email_reply(db_conn, &reply, attachments) {
+        Ok(reply) => info!("Reply {} stored", reply.id),
+        Err(e) => error!("Cannot store reply: {}", e),
+    }
+}
+
+/// Recipient ids referenced by In-Reply-To and References that point at messages we sent.
+pub fn referenced_recipient_ids(message: &Message, domain: &str) -> Vec<i32> {
+    [message.in_reply_to(), message.references()]
+        .into_iter()
+        .filter_map(|value| value.as_text_list())
+        .flatten()
+        .filter_map(|message_id| EmailRecipient::id_from_message_id(message_id, domain))
+        .collect()
+}
+
+pub fn process_email_reply(
+    db_conn: &mut DbConnection,
+    recipient: &EmailRecipient,
+    message: &Message,
+) {
+    let is_auto = is_auto_reply(message);
+
+    save_email_reply(db_conn, recipient.id, message, is_auto);
+
+    // Once a person has answered, further auto-replies do not change the status
+    if recipient.replied {
+        return;
+    }
+
+    if !is_auto {
+        match set_email_recipient_replied_status(db_conn, recipient.email_id, recipient.id) {
+            Ok(_) => info!("Email recipient replied status set"),
+            Err(e) => error!("Cannot set email recipient replied status: {}", e),
+        }
+
+        if let Err(e) = update_email_num_replied(db_conn, recipient.email_id) {
+            error!("Failed to update email num_sent for: {}", e);
+        }
+
+        if let Err(e) = queue_email_recipient_event(db_conn, recipient, WebhookEvent::Replied) {
+            error!("Cannot queue replied webhook: {}", e);
+        }
+    } else if !recipient.auto_replied {
+        match set_email_recipient_auto_replied_status(db_conn, recipient.email_id, recipient.id) {
+            Ok(_) => info!("Email recipient auto-replied status set"),
+            Err(e) => error!("Cannot set email recipient auto-replied status: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mail_parser::MessageParser;
+
+    use super::*;
+
+    fn auto_reply(headers: &str, subject: &str) -> bool {
+        let raw = format!(
+            "From: a@example.com\r\n{}Subject: {}\r\n\r\nbody\r\n",
+            headers, subject
+        );
+        let message = MessageParser::default().parse(raw.as_bytes()).unwrap();
+        is_auto_reply(&message)
+    }
+
+    #[test]
+    fn auto_submitted_header() {
+        assert!(auto_reply("Auto-Submitted: auto-replied\r\n", "Re: Offer"));
+        assert!(!auto_reply("Auto-Submitted: no\r\n", "Re: Offer"));
+    }
+
+    #[test]
+    fn autoreply_headers() {
+        assert!(auto_reply("X-Autoreply: yes\r\n", "Re: Offer"));
+        assert!(auto_reply("X-Autorespond: yes\r\n", "Re: Offer"));
+        assert!(auto_reply("Precedence: auto_reply\r\n", "Re: Offer"));
+        assert!(!auto_reply("Precedence: bulk\r\n", "Re: Offer"));
+    }
+
+    #[test]
+    fn auto_reply_subjects() {
+        assert!(auto_reply("", "Automatic reply: Offer"));
+        assert!(auto_reply("", "Out of Office: Offer"));
+        assert!(auto_reply("", "Auto: Offer"));
+        assert!(auto_reply("", "Автоответ: Предложение"));
+        assert!(auto_reply("", "Нет на месте: Предложение"));
+    }
+
+    #[test]
+    fn human_replies() {
+        assert!(!auto_reply("", "Re: Offer"));
+        assert!(!auto_reply("", "Re: Automatic reply: Offer"));
+        assert!(!auto_reply("", "Re: auto: parts price list"));
+        assert!(!auto_reply("", "Я в отпуске, отвечу позже"));
+        assert!(!auto_reply("", "Out of office supplies"));
+    }
+}
@@ -0,0 +1,194 @@
+use log::{error, info};
+use mail_parser::Message;
+
+use pushkind_emailer::db::DbConnection;
+use pushkind_emailer::models::recipient::Recipient;
+use pushkind_emailer::repository::recipient::{get_hub_recipient_by_email, unsubscribe_recipient};
+
+/// Words asking to stop the mailing, compared in lowercase.
+const UNSUBSCRIBE_KEYWORDS: [&str; 5] =
+    ["unsubscribe", "отписать", "отпишите", "отписка", "отписки"];
+
+/// Lines that start the quoted original message in a reply.
+const QUOTE_MARKERS: [&str; 5] = [
+    "wrote:",
+    "писал:",
+    "писал(а):",
+    "пишет:",
+    "-----original message",
+];
+
+/// Words that turn a request around, as in "please don't unsubscribe me".
+const NEGATIONS: [&str; 6] = ["not", "don't", "dont", "never", "не", "нет"];
+
+/// Longest own text of a message still treated as an unsubscribe request.
+const MAX_REQUEST_LENGTH: usize = 200;
+
+/// A request starts its clause: "please unsubscribe me" or "прошу отписать меня".
+const MAX_WORDS_BEFORE_KEYWORD: usize = 4;
+
+/// Whether a clause of the text asks to unsubscribe: the keyword is among its leading words
+/// and no negation comes before it. Mentioning the mailing further on does not count.
+fn is_request(text: &str) -> bool {
+    let text = text.to_lowercase().replace('\u{2019}', "'");
+    text.split(['.', ',', ';', '!', '?', ':', '\n'])
+        .any(|clause| {
+            let words = clause
+                .split(|c: char| !c.is_alphanumeric() && c != '\'')
+                .filter(|word| !word.is_empty())
+                .collect::<Vec<_>>();
+            let Some(position) = words.iter().position(|word| {
+                UNSUBSCRIBE_KEYWORDS
+                    .iter()
+                    .any(|keyword| word.starts_with(keyword))
+            }) else {
+                return false;
+            };
+
+            position <= MAX_WORDS_BEFORE_KEYWORD
+                && !words[..position]
+                    .iter()
+                    .any(|word| NEGATIONS.contains(word))
+        })
+}
+
+pub fn is_unsubscribe_subject(message: &Message) -> bool {
+    message.subject().is_some_and(is_request)
+}
+
+/// Checks the text written by the sender, skipping quotes so our own footer does not count.
+pub fn is_unsubscribe_body(message: &Message) -> bool {
+    let body = message.body_text(0).unwrap_or_default();
+    let text = body
+        .lines()
+        .map(str::trim)
+        .take_while(|line| {
+            let line = line.to_lowercase();
+            !line.starts_with('>')
+                && !QUOTE_MARKERS
+                    .iter()
+                    .any(|marker| line.starts_with(marker) || line.ends_with(marker))
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    text.chars().count() <= MAX_REQUEST_LENGTH && is_request(&text)
+}
+
+fn sender_address<'a>(message: &'a Message) -> Option<&'a str> {
+    message.from()?.first()?.address()
+}
+
+/// Hub recipient that sent the message and has not unsubscribed yet.
+pub fn subscribed_sender(
+    db_conn: &mut DbConnection,
+    hub_id: i32,
+    message: &Message,
+) -> Option<Recipient> {
+    let address = sender_address(message)?;
+
+    match get_hub_recipient_by_email(db_conn, hub_id, address) {
+        Ok(recipient) => recipient.filter(|recipient| recipient.unsubscribed_at.is_none()),
+        Err(e) => {
+            error!("Cannot get recipient {}: {}", address, e);
+            None
+        }
+    }
+}
+
+/// Unsubscribes the sender of the message, returns whether the request was handled.
+pub fn process_unsubscribe(db_conn: &mut DbConnection, hub_id: i32, message: &Message) -> bool {
+    let Some(address) = sender_address(message) else {
+        return false;
+    };
+
+    let recipient = match get_hub_recipient_by_email(db_conn, hub_id, address) {
+        Ok(Some(recipient)) => recipient,
+        Ok(None) => {
+            info!(
+                "Unsubscribe request from unknown address {} in hub {}",
+                address, hub_id
+            );
+            return false;
+        }
+        Err(e) => {
+            error!("Cannot get recipient {}: {}", address, e);
+            return false;
+        }
+    };
+
+    match unsubscribe_recipient(db_conn, recipient.id) {
+        Ok(0) => info!(
+            "Recipient {} ({}) in hub {} is already unsubscribed",
+            recipient.id, address, hub_id
+        ),
+        Ok(_) => info!(
+            "Recipient {} ({}) in hub {} unsubscribed by email",
+            recipient.id, address, hub_id
+        ),
+        Err(e) => {
+            error!("Cannot unsubscribe recipient {}: {}", recipient.id, e);
+            return false;
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use mail_parser::MessageParser;
+
+    use super::*;
+
+    fn unsubscribe_body(body: &str) -> bool {
+        let raw = format!(
+            "From: a@example.com\r\nSubject: Re: Offer\r\n\r\n{}\r\n",
+            body
+        );
+        let message = MessageParser::default().parse(raw.as_bytes()).unwrap();
+        is_unsubscribe_body(&message)
+    }
+
+    #[test]
+    fn requests() {
+        assert!(is_request("Unsubscribe"));
+        assert!(is_request("Please unsubscribe me"));
+        assert!(is_request("Hi, please unsubscribe me from this list."));
+        assert!(is_request("I would like to unsubscribe"));
+        assert!(is_request("Прошу отписать меня от рассылки"));
+        assert!(is_request("Здравствуйте! Отпишите меня, пожалуйста"));
+        assert!(is_request("Я не просил эти письма, отпишите"));
+    }
+
+    #[test]
+    fn negated_requests() {
+        assert!(!is_request("Please don't unsubscribe me"));
+        assert!(!is_request("Please don\u{2019}t unsubscribe me"));
+        assert!(!is_request("Do not unsubscribe me, I need these emails"));
+        assert!(!is_request("Не отписка, а вопрос по заказу"));
+    }
+
+    #[test]
+    fn keyword_not_leading() {
+        assert!(!is_request(
+            "I forwarded the offer to my colleague so that he can unsubscribe later"
+        ));
+        assert!(!is_request("Re: Offer"));
+    }
+
+    #[test]
+    fn bodies() {
+        assert!(unsubscribe_body(
+            "Hello,\r\nplease unsubscribe me.\r\n\r\n> Offer text"
+        ));
+        assert!(!unsubscribe_body("Please don't unsubscribe me."));
+        // The quoted original has our footer with the keyword
+        assert!(!unsubscribe_body(
+            "Thanks, sounds good\r\n\r\nOn Monday Shop wrote:\r\n> Unsubscribe: reply with unsubscribe"
+        ));
+        let long = format!("Please unsubscribe me. {}", "Long story. ".repeat(30));
+        assert!(!unsubscribe_body(&long));
+    }
+}
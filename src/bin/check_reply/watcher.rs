@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+
+use pushkind_emailer::db::{DbPool, get_db_connection};
+use pushkind_emailer::repository::hub::list_hubs;
+use pushkind_emailer::utils::ImapSession;
+
+use crate::mailbox::{ImapSettings, connect, scan_folder};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Clone)]
+pub struct WatcherConfig {
+    pub domain: String,
+    pub max_age: chrono::Duration,
+    /// How long to wait for IDLE (or sleep without it) before scanning again.
+    pub poll_interval: Duration,
+    /// How often hub settings are reloaded from the database.
+    pub reload_interval: Duration,
+}
+
+struct Watcher {
+    settings: ImapSettings,
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl Watcher {
+    fn spawn(pool: DbPool, hub_id: i32, settings: ImapSettings, config: WatcherConfig) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let settings = settings.clone();
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || watch_hub(&pool, hub_id, &settings, &config, &stop))
+        };
+
+        Self {
+            settings,
+            stop,
+            handle,
+        }
+    }
+
+    /// Asks the thread to stop without waiting for it, it finishes once the current IMAP
+    /// command or IDLE wait returns.
+    fn shutdown(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the thread ended, after a shutdown or a panic.
+    fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+}
+
+/// Sleeps for `duration`, waking up early when `stop` is raised.
+fn sleep_unless_stopped(duration: Duration, stop: &AtomicBool) {
+    let deadline = Instant::now() + duration;
+    while !stop.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        thread::sleep((deadline - now).min(Duration::from_secs(1)));
+    }
+}
+
+/// Scans the hub folders and waits for changes until stopped or the connection fails.
+fn watch_session(
+    pool: &DbPool,
+    session: &mut ImapSession,
+    hub_id: i32,
+    settings: &ImapSettings,
+    config: &WatcherConfig,
+    stop: &AtomicBool,
+) -> imap::error::Result<()> {
+    let supports_idle = session.capabilities()?.has_str("IDLE");
+    if !supports_idle {
+        warn!(
+            "Hub {} server does not support IDLE, polling every {:?}",
+            hub_id, config.poll_interval
+        );
+    }
+
+    while !stop.load(Ordering::Relaxed) {
+        match get_db_connection(pool) {
+            Some(mut db_conn) => {
+                for folder in &settings.folders {
+                    scan_folder(
+                        &mut db_conn,
+                        session,
+                        hub_id,
+                        folder,
+                        settings.unsubscribe_folder.as_deref(),
+                        &config.domain,
+                        config.max_age,
+                    )?;
+                }
+            }
+            None => error!("Cannot get db connection"),
+        }
+
+        if supports_idle {
+            // IDLE only reports changes in the selected folder, the others are
+            // picked up when the wait times out after `poll_interval`.
+            if settings.folders.len() > 1 {
+                match session.select(&settings.folders[0]) {
+                    Ok(_) | Err(imap::error::Error::No(_)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            session.idle()?.wait_with_timeout(config.poll_interval)?;
+        } else {
+            session.noop()?;
+            sleep_unless_stopped(config.poll_interval, stop);
+        }
+    }
+
+    Ok(())
+}
+
+/// Keeps a connection to one hub open, reconnecting with exponential backoff.
+fn watch_hub(
+    pool: &DbPool,
+    hub_id: i32,
+    settings: &ImapSettings,
+    config: &WatcherConfig,
+    stop: &AtomicBool,
+) {
+    let mut backoff = MIN_BACKOFF;
+
+    while !stop.load(Ordering::Relaxed) {
+        let mut session = match connect(settings) {
+            Ok(session) => session,
+            Err(e) => {
+                error!(
+                    "Cannot connect to imap server of hub {}, retrying in {:?}: {}",
+                    hub_id, backoff, e
+                );
+                sleep_unless_stopped(backoff, stop);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                continue;
+            }
+        };
+
+        info!("Watching hub {}", hub_id);
+        backoff = MIN_BACKOFF;
+
+        match watch_session(pool, &mut session, hub_id, settings, config, stop) {
+            Ok(()) => {
+                if let Err(e) = session.logout() {
+                    error!("Cannot logout: {}", e);
+                }
+            }
+            Err(e) => {
+                error!(
+                    "Lost connection to imap server of hub {}, reconnecting in {:?}: {}",
+                    hub_id, backoff, e
+                );
+                sleep_unless_stopped(backoff, stop);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+
+    info!("Stopped watching hub {}", hub_id);
+}
+
+/// Runs one watcher per hub with IMAP configured, restarting them when hub settings change.
+///
+/// A replaced watcher may still be inside a scan, the new one starts only after it has ended so
+/// the two never race on the UID checkpoint. Other hubs do not wait for it.
+pub fn run(pool: DbPool, config: WatcherConfig) {
+    let mut watchers: HashMap<i32, Watcher> = HashMap::new();
+    let mut stopping: HashMap<i32, Watcher> = HashMap::new();
+
+    loop {
+        let hubs = match get_db_connection(&pool).map(|mut conn| list_hubs(&mut conn)) {
+            Some(Ok(hubs)) => hubs,
+            Some(Err(e)) => {
+                error!("Cannot get hubs: {}", e);
+                thread::sleep(config.reload_interval);
+                continue;
+            }
+            None => {
+                error!("Cannot get db connection");
+                thread::sleep(config.reload_interval);
+                continue;
+            }
+        };
+
+        let hub_settings = hubs
+            .iter()
+            .filter_map(|hub| ImapSettings::from_hub(hub).map(|settings| (hub.id, settings)))
+            .collect::<HashMap<_, _>>();
+
+        let changed = watchers
+            .iter()
+            .filter(|(hub_id, watcher)| hub_settings.get(hub_id) != Some(&watcher.settings))
+            .map(|(hub_id, _)| *hub_id)
+            .collect::<Vec<_>>();
+        for hub_id in changed {
+            if let Some(watcher) = watchers.remove(&hub_id) {
+                info!("IMAP settings of hub {} changed", hub_id);
+                watcher.shutdown();
+                stopping.insert(hub_id, watcher);
+            }
+        }
+
+        let stopped = stopping
+            .iter()
+            .filter(|(_, watcher)| watcher.is_finished())
+            .map(|(hub_id, _)| *hub_id)
+            .collect::<Vec<_>>();
+        for hub_id in stopped {
+            if let Some(watcher) = stopping.remove(&hub_id)
+                && watcher.handle.join().is_err()
+            {
+                error!("Watcher for hub {} panicked while stopping", hub_id);
+            }
+        }
+        for hub_id in stopping.keys() {
+            info!("Waiting for the previous watcher of hub {} to stop", hub_id);
+        }
+
+        let finished = watchers
+            .iter()
+            .filter(|(_, watcher)| watcher.is_finished())
+            .map(|(hub_id, _)| *hub_id)
+            .collect::<Vec<_>>();
+        for hub_id in finished {
+            if let Some(watcher) = watchers.remove(&hub_id)
+                && watcher.handle.join().is_err()
+            {
+                error!("Watcher for hub {} panicked, restarting it", hub_id);
+            }
+        }
+
+        for (hub_id, settings) in hub_settings {
+            if stopping.contains_key(&hub_id) {
+                continue;
+            }
+            watchers
+                .entry(hub_id)
+                .or_insert_with(|| Watcher::spawn(pool.clone(), hub_id, settings, config.clone()));
+        }
+
+        thread::sleep(config.reload_interval);
+    }
+}
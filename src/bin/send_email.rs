@@ -3,6 +3,7 @@ use std::error::Error;
 use std::sync::Arc;
 
 use dotenvy::dotenv;
+use imap::types::Flag;
 use log::{error, info};
 use mail_send::SmtpClientBuilder;
 use mail_send::mail_builder::{
@@ -11,8 +12,8 @@ use mail_send::mail_builder::{
 };
 use pushkind_emailer::models::email::{Email, EmailRecipient};
 use pushkind_emailer::models::hub::Hub;
+use pushkind_emailer::models::webhook::WebhookEvent;
 use tokio::sync::Mutex;
-use zmq;
 
 use pushkind_emailer::db::{DbPool, establish_connection_pool, get_db_connection};
 use pushkind_emailer::repository::email::{
@@ -20,59 +21,123 @@ use pushkind_emailer::repository::email::{
     update_email_num_sent,
 };
 use pushkind_emailer::repository::hub::get_hub;
+use pushkind_emailer::repository::webhook::queue_email_recipient_event;
+use pushkind_emailer::utils::connect_imap;
+
+/// Points the web links of the body at the click tracker, which redirects to the original.
+/// The unsubscribe link is left as is, following it is not a click on the content.
+fn track_links(body: &str, tracker: &str, unsubscribe_url: &str) -> String {
+    const HREF: &str = "href=\"";
+
+    let mut tracked = String::with_capacity(body.len());
+    let mut rest = body;
+    while let Some(start) = rest.find(HREF) {
+        let start = start + HREF.len();
+        let Some(length) = rest[start..].find('"') else {
+            break;
+        };
+        let link = &rest[start..start + length];
+
+        tracked.push_str(&rest[..start]);
+        match serde_html_form::to_string([("url", link)]) {
+            Ok(query)
+                if (link.starts_with("http://") || link.starts_with("https://"))
+                    && link != unsubscribe_url =>
+            {
+                tracked.push_str(&format!("{}?{}", tracker, query));
+            }
+            _ => tracked.push_str(link),
+        }
+        rest = &rest[start + length..];
+    }
+    tracked.push_str(rest);
+
+    tracked
+}
+
+fn build_message<'a>(
+    hub: &'a Hub,
+    email: &'a Email,
+    recipient: &'a EmailRecipient,
+    domain: &str,
+    body: &'a str,
+    unsubscribe_url: &'a str,
+) -> MessageBuilder<'a> {
+    let recipient_address = vec![("", recipient.address.as_str())];
+    let sender_email = hub.sender.as_deref().unwrap_or_default();
+    let sender_login = hub.login.as_deref().unwrap_or_default();
+    let subject = email.subject.as_deref().unwrap_or_default();
+
+    let mut message = MessageBuilder::new()
+        .from((sender_email, sender_login))
+        .to(recipient_address)
+        .subject(subject)
+        .html_body(body)
+        .text_body(body)
+        .message_id(recipient.message_id(domain));
+
+    if !email.is_transactional {
+        message = message.header(
+            "List-Unsubscribe",
+            HeaderType::from(URL::new(unsubscribe_url)),
+        );
+    }
+
+    if let (Some(mime), Some(name), Some(content)) = (
+        email.attachment_mime.as_deref(),
+        email.attachment_name.as_deref(),
+        email.attachment.as_deref(),
+    ) && !name.is_empty()
+        && !content.is_empty()
+    {
+        message = message.attachment(mime, name, content);
+    }
+
+    message
+}
 
 async fn send_smtp_message(
     hub: &Hub,
     email: &Email,
     recipient: &EmailRecipient,
     domain: &str,
-) -> Result<(), mail_send::Error> {
+    keep_copy: bool,
+) -> Result<Option<Vec<u8>>, mail_send::Error> {
     let template = hub.email_template.as_deref().unwrap_or_default();
 
     let unsubscribe_url = hub.get_usubscribe_url();
-    let mut body: String;
+    let body: String;
 
     let template = template.replace("{unsubscribe_url}", &unsubscribe_url);
 
-    if template.contains("{message}") {
+    // Transactional messages are complete as rendered and cannot be unsubscribed from
+    if email.is_transactional {
+        body = email.message.clone();
+    } else if template.contains("{message}") {
         body = template.replace("{message}", &email.message);
     } else {
         body = format!("{}{}", &email.message, template);
     }
 
-    body.push_str(&format!(
+    let mut tracked_body = track_links(
+        &body,
+        &format!("https://mail.{domain}/click/{}", recipient.id),
+        &unsubscribe_url,
+    );
+
+    tracked_body.push_str(&format!(
         r#"<img height="1" width="1" border="0" src="https://mail.{domain}/track/{}">"#,
         recipient.id
     ));
 
-    let message_id = format!("{}@{}", recipient.id, domain);
-
-    let recipient_address = vec![("", recipient.address.as_str())];
-    let sender_email = hub.sender.as_deref().unwrap_or_default();
-    let sender_login = hub.login.as_deref().unwrap_or_default();
-    let subject = email.subject.as_deref().unwrap_or_default();
-
-    let mut message = MessageBuilder::new()
-        .from((sender_email, sender_login))
-        .to(recipient_address)
-        .subject(subject)
-        .html_body(&body)
-        .text_body(&body)
-        .message_id(message_id)
-        .header(
-            "List-Unsubscribe",
-            HeaderType::from(URL::new(&unsubscribe_url)),
-        );
-
-    if let (Some(mime), Some(name), Some(content)) = (
-        email.attachment_mime.as_deref(),
-        email.attachment_name.as_deref(),
-        email.attachment.as_deref(),
-    ) {
-        if !name.is_empty() && !content.is_empty() {
-            message = message.attachment(mime, name, content);
-        }
-    }
+    let message = build_message(
+        hub,
+        email,
+        recipient,
+        domain,
+        &tracked_body,
+        &unsubscribe_url,
+    );
 
     let smtp_server = hub.smtp_server.as_deref().unwrap_or_default();
     let smtp_port = hub.smtp_port.unwrap_or(25) as u16; // assume smtp_port is Option<u16>?
@@ -82,33 +147,77 @@ async fn send_smtp_message(
         hub.password.as_deref().unwrap_or_default(),
     );
 
+    // The copy is read by the sender, opening it must not count as an open or a click
+    let copy = match keep_copy {
+        true => Some(
+            build_message(hub, email, recipient, domain, &body, &unsubscribe_url).write_to_vec()?,
+        ),
+        false => None,
+    };
+
     SmtpClientBuilder::new(smtp_server, smtp_port)
         .implicit_tls(true)
         .credentials(credentials)
         .connect()
         .await?
         .send(message)
-        .await
+        .await?;
+
+    Ok(copy)
+}
+
+/// Stores copies of sent messages in the hub IMAP folder, marked as seen.
+fn append_to_sent_folder(hub: &Hub, folder: &str, messages: &[Vec<u8>]) -> imap::error::Result<()> {
+    let mut session = connect_imap(hub)?;
+
+    for message in messages {
+        session.append_with_flags(folder, message, &[Flag::Seen])?;
+    }
+
+    session.logout()
 }
 
 async fn send_email(
     email_id: i32,
-    db_pool: Arc<Mutex<DbPool>>,
+    db_pool: DbPool,
+    campaign_lock: Arc<Mutex<()>>,
     domain: &str,
 ) -> Result<(), Box<dyn Error>> {
-    let pool = db_pool.lock().await;
-    let mut conn = get_db_connection(&pool).ok_or("Cannot get connection from the pool")?;
+    let mut conn = get_db_connection(&db_pool).ok_or("Cannot get connection from the pool")?;
 
     let email = get_email(&mut conn, email_id)?;
+
+    // Campaigns are sent one at a time, transactional messages do not wait for them
+    let _campaign_guard = match email.is_transactional {
+        true => None,
+        false => Some(campaign_lock.lock().await),
+    };
+
     let recipients = get_email_recipients(&mut conn, email_id)?;
     let hub = get_hub(&mut conn, email.hub_id)?;
 
     info!("Sending email for email_id {} via hub {}", email_id, hub.id);
 
+    let sent_folder = hub
+        .imap_sent_folder
+        .clone()
+        .filter(|folder| !folder.is_empty());
+    let mut sent_copies = Vec::new();
+
     for recipient in recipients {
-        if let Err(e) = send_smtp_message(&hub, &email, &recipient, &domain).await {
-            error!("Failed to send email to {}: {}", recipient.address, e);
-            continue;
+        let keep_copy = sent_folder.is_some() && (hub.imap_sent_all || sent_copies.is_empty());
+
+        match send_smtp_message(&hub, &email, &recipient, domain, keep_copy).await {
+            Ok(copy) => sent_copies.extend(copy),
+            Err(e) => {
+                error!("Failed to send email to {}: {}", recipient.address, e);
+                if let Err(e) =
+                    queue_email_recipient_event(&mut conn, &recipient, WebhookEvent::Failed)
+                {
+                    error!("Failed to queue failed webhook: {}", e);
+                }
+                continue;
+            }
         }
 
         info!("Email sent successfully to {}", recipient.address);
@@ -119,6 +228,11 @@ async fn send_email(
                 recipient.id, e
             );
         }
+
+        if let Err(e) = queue_email_recipient_event(&mut conn, &recipient, WebhookEvent::Delivered)
+        {
+            error!("Failed to queue delivered webhook: {}", e);
+        }
     }
 
     if let Err(e) = set_email_sent_status(&mut conn, email_id, true) {
@@ -135,6 +249,24 @@ async fn send_email(
         );
     }
 
+    if let Some(folder) = sent_folder
+        && !sent_copies.is_empty()
+    {
+        let copies = sent_copies.len();
+        let result =
+            tokio::task::spawn_blocking(move || append_to_sent_folder(&hub, &folder, &sent_copies))
+                .await;
+
+        match result {
+            Ok(Ok(())) => info!(
+                "Saved {} copies of email {} to sent folder",
+                copies, email_id
+            ),
+            Ok(Err(e)) => error!("Failed to save email {} to sent folder: {}", email_id, e),
+            Err(e) => error!("Failed to save email {} to sent folder: {}", email_id, e),
+        }
+    }
+
     info!("Finished processing email_id: {}", email_id);
 
     Ok(())
@@ -165,7 +297,7 @@ async fn main() {
         }
     };
 
-    let pool = Arc::new(Mutex::new(pool));
+    let campaign_lock = Arc::new(Mutex::new(()));
 
     info!("Starting email worker");
 
@@ -174,11 +306,12 @@ async fn main() {
         match responder.recv_into(&mut buffer, 0) {
             Ok(_) => {
                 let email_id = i32::from_be_bytes(buffer);
-                let pool_clone = Arc::clone(&pool);
+                let pool_clone = pool.clone();
+                let campaign_lock = Arc::clone(&campaign_lock);
                 let domain = Arc::clone(&domain);
 
                 tokio::spawn(async move {
-                    if let Err(e) = send_email(email_id, pool_clone, &domain).await {
+                    if let Err(e) = send_email(email_id, pool_clone, campaign_lock, &domain).await {
                         error!("Error sending email message: {}", e);
                     }
                 });
@@ -190,3 +323,27 @@ async fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_point_at_tracker() {
+        let body = r#"<a href="https://shop.ru/a?x=1&amp;y=2">Shop</a> <a href="mailto:a@shop.ru">Mail</a> <a href="https://shop.ru/unsubscribe">Off</a>"#;
+        assert_eq!(
+            track_links(
+                body,
+                "https://mail.shop.ru/click/7",
+                "https://shop.ru/unsubscribe"
+            ),
+            r#"<a href="https://mail.shop.ru/click/7?url=https%3A%2F%2Fshop.ru%2Fa%3Fx%3D1%26amp%3By%3D2">Shop</a> <a href="mailto:a@shop.ru">Mail</a> <a href="https://shop.ru/unsubscribe">Off</a>"#
+        );
+    }
+
+    #[test]
+    fn unterminated_href_is_kept() {
+        let body = r#"<a href="https://shop.ru"#;
+        assert_eq!(track_links(body, "https://mail.shop.ru/click/7", ""), body);
+    }
+}
@@ -0,0 +1,170 @@
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+use dotenvy::dotenv;
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use sha2::Sha256;
+
+use pushkind_emailer::db::{DbConnection, establish_connection_pool, get_db_connection};
+use pushkind_emailer::models::webhook::{Webhook, WebhookDelivery, resolve_public};
+use pushkind_emailer::repository::webhook::{
+    get_due_webhook_deliveries, set_webhook_delivery_delivered, set_webhook_delivery_error,
+};
+
+/// Deliveries sent per poll, the rest waits for the next round.
+const BATCH_SIZE: i64 = 100;
+/// First retry delay, doubled after every failed attempt.
+const RETRY_BASE: i64 = 30;
+const RETRY_MAX: i64 = 6 * 60 * 60;
+
+fn env_number(name: &str, default: u64) -> u64 {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Hex HMAC-SHA256 of `timestamp.body`, sent as `X-Pushkind-Signature: sha256=...`.
+fn sign_payload(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn retry_delay(attempts: i32) -> chrono::Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 20) as u32;
+    chrono::Duration::seconds(RETRY_BASE.saturating_mul(1 << exponent).min(RETRY_MAX))
+}
+
+/// Posts the delivery, returns the response status or the status and error of the failure.
+fn post_delivery(
+    agent: &ureq::Agent,
+    delivery: &WebhookDelivery,
+    webhook: &Webhook,
+) -> Result<u16, (Option<u16>, String)> {
+    let timestamp = chrono::Utc::now().timestamp();
+    let signature = sign_payload(&webhook.secret, timestamp, &delivery.payload);
+
+    let result = agent
+        .post(&webhook.url)
+        .set("Content-Type", "application/json")
+        .set("User-Agent", "pushkind-emailer")
+        .set("X-Pushkind-Event", &delivery.event)
+        .set("X-Pushkind-Delivery", &delivery.id.to_string())
+        .set("X-Pushkind-Timestamp", &timestamp.to_string())
+        .set("X-Pushkind-Signature", &format!("sha256={signature}"))
+        .send_string(&delivery.payload);
+
+    match result {
+        Ok(response) => Ok(response.status()),
+        Err(ureq::Error::Status(status, response)) => Err((
+            Some(status),
+            format!("HTTP {} {}", status, response.status_text()),
+        )),
+        Err(ureq::Error::Transport(err)) => Err((None, err.to_string())),
+    }
+}
+
+fn process_delivery(
+    db_conn: &mut DbConnection,
+    agent: &ureq::Agent,
+    delivery: &WebhookDelivery,
+    webhook: &Webhook,
+    max_attempts: i32,
+) {
+    let attempts = delivery.attempts + 1;
+
+    let result = match post_delivery(agent, delivery, webhook) {
+        Ok(status) => {
+            info!(
+                "Webhook delivery {} to {} succeeded with {}",
+                delivery.id, webhook.url, status
+            );
+            set_webhook_delivery_delivered(db_conn, delivery.id, attempts, status as i32)
+        }
+        Err((status, message)) => {
+            let next_attempt_at = (attempts < max_attempts)
+                .then(|| chrono::Utc::now().naive_utc() + retry_delay(attempts));
+            match next_attempt_at {
+                Some(next_attempt_at) => warn!(
+                    "Webhook delivery {} to {} failed: {}, retrying at {}",
+                    delivery.id, webhook.url, message, next_attempt_at
+                ),
+                None => error!(
+                    "Webhook delivery {} to {} failed after {} attempts: {}",
+                    delivery.id, webhook.url, attempts, message
+                ),
+            }
+            set_webhook_delivery_error(
+                db_conn,
+                delivery.id,
+                attempts,
+                status.map(i32::from),
+                &message,
+                next_attempt_at,
+            )
+        }
+    };
+
+    if let Err(e) = result {
+        error!("Cannot update webhook delivery {}: {}", delivery.id, e);
+    }
+}
+
+fn main() {
+    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
+    dotenv().ok(); // Load .env file
+
+    let database_url = env::var("DATABASE_URL").unwrap_or_else(|_| "app.db".to_string());
+    let poll_interval = Duration::from_secs(env_number("WEBHOOK_POLL_INTERVAL", 5));
+    let max_attempts = env_number("WEBHOOK_MAX_ATTEMPTS", 8).max(1) as i32;
+    let timeout = Duration::from_secs(env_number("WEBHOOK_TIMEOUT", 10));
+
+    let db_pool = match establish_connection_pool(database_url) {
+        Ok(pool) => pool,
+        Err(e) => {
+            error!("Cannot establish db connection: {}", e);
+            return;
+        }
+    };
+
+    // Hosts are resolved again on every delivery, internal addresses are refused
+    let agent = ureq::AgentBuilder::new()
+        .timeout(timeout)
+        .redirects(0)
+        .resolver(resolve_public)
+        .build();
+
+    info!("Starting webhook dispatcher");
+
+    loop {
+        let deliveries = match get_db_connection(&db_pool) {
+            Some(mut db_conn) => match get_due_webhook_deliveries(&mut db_conn, BATCH_SIZE) {
+                Ok(deliveries) => {
+                    for (delivery, webhook) in &deliveries {
+                        process_delivery(&mut db_conn, &agent, delivery, webhook, max_attempts);
+                    }
+                    deliveries.len()
+                }
+                Err(e) => {
+                    error!("Cannot get webhook deliveries: {}", e);
+                    0
+                }
+            },
+            None => {
+                error!("Cannot get db connection");
+                0
+            }
+        };
+
+        // A full batch means more deliveries are probably due already
+        if deliveries < BATCH_SIZE as usize {
+            thread::sleep(poll_interval);
+        }
+    }
+}
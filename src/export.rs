@@ -0,0 +1,117 @@
+use std::borrow::Cow;
+use std::error::Error;
+
+use rust_xlsxwriter::{Format, Workbook, XlsxError};
+
+/// File formats of the downloads.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Xlsx,
+}
+
+impl ExportFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "csv" => Some(ExportFormat::Csv),
+            "xlsx" => Some(ExportFormat::Xlsx),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Xlsx => "xlsx",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Xlsx => {
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+            }
+        }
+    }
+}
+
+/// Characters that make a spreadsheet read a cell as a formula.
+const FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+/// Cell text with `'` in front when a spreadsheet would take it for a formula, the import
+/// drops the quote with [`unescape_formula`].
+pub fn escape_formula(value: &str) -> Cow<'_, str> {
+    match value.starts_with(FORMULA_PREFIXES) {
+        true => Cow::Owned(format!("'{}", value)),
+        false => Cow::Borrowed(value),
+    }
+}
+
+pub fn unescape_formula(value: &str) -> &str {
+    match value.strip_prefix('\'') {
+        Some(rest) if rest.starts_with(FORMULA_PREFIXES) => rest,
+        _ => value,
+    }
+}
+
+/// Rows of text cells under a header row, values that look like formulas are written as text.
+#[derive(Default)]
+pub struct ExportTable {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl ExportTable {
+    pub fn new(headers: Vec<String>) -> Self {
+        Self {
+            headers,
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn write(&self, format: ExportFormat, sheet: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        match format {
+            ExportFormat::Csv => self.to_csv(),
+            ExportFormat::Xlsx => Ok(self.to_xlsx(sheet)?),
+        }
+    }
+
+    /// UTF-8 with a byte order mark, Excel takes the file for a local code page otherwise.
+    pub fn to_csv(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut writer = csv::Writer::from_writer(b"\xEF\xBB\xBF".to_vec());
+
+        writer.write_record(
+            self.headers
+                .iter()
+                .map(|header| escape_formula(header).into_owned()),
+        )?;
+        for row in &self.rows {
+            writer.write_record(row.iter().map(|value| escape_formula(value).into_owned()))?;
+        }
+
+        Ok(writer.into_inner()?)
+    }
+
+    pub fn to_xlsx(&self, sheet: &str) -> Result<Vec<u8>, XlsxError> {
+        let mut workbook = Workbook::new();
+        let worksheet = workbook.add_worksheet();
+        worksheet.set_name(sheet)?;
+
+        let bold = Format::new().set_bold();
+        for (col, header) in self.headers.iter().enumerate() {
+            worksheet.write_string_with_format(0, col as u16, escape_formula(header), &bold)?;
+        }
+        for (row, values) in self.rows.iter().enumerate() {
+            for (col, value) in values.iter().enumerate() {
+                worksheet.write_string(row as u32 + 1, col as u16, escape_formula(value))?;
+            }
+        }
+
+        worksheet.set_freeze_panes(1, 0)?;
+        worksheet.autofit();
+
+        workbook.save_to_buffer()
+    }
+}
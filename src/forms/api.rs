@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+fn default_active() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+pub struct RecipientPayload {
+    pub name: String,
+    pub email: String,
+    #[serde(default = "default_active")]
+    pub active: bool,
+    #[serde(default)]
+    pub groups: Vec<i32>,
+    #[serde(default)]
+    pub fields: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+pub struct GroupPayload {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct EmailPayload {
+    pub subject: Option<String>,
+    pub message: String,
+    /// Recipient email addresses or group ids, as in the send form.
+    pub recipients: Vec<String>,
+    /// Addresses, group ids or `email:<id>` for everyone an earlier email went to.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// Audience of an email that is not created yet, see [`EmailPayload`].
+#[derive(Deserialize)]
+pub struct AudiencePayload {
+    pub recipients: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TemplatePayload {
+    pub name: String,
+    pub subject: String,
+    pub body: String,
+}
+
+fn default_data() -> serde_json::Value {
+    serde_json::Value::Object(Default::default())
+}
+
+#[derive(Deserialize)]
+pub struct MessagePayload {
+    pub template_id: i32,
+    /// Address the message is sent to, it does not have to be a hub recipient.
+    pub to: String,
+    /// Values available in the template subject and body.
+    #[serde(default = "default_data")]
+    pub data: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+pub struct RecipientSearchQuery {
+    pub q: Option<String>,
+    pub group: Option<i32>,
+    pub active: Option<bool>,
+    /// `name`, `email` or `created`, prefixed with `-` for descending order.
+    pub sort: Option<String>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct EmailListQuery {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct StatsQuery {
+    /// First day of the campaigns taken into account, `YYYY-MM-DD`.
+    pub since: Option<String>,
+    /// Last day of the campaigns taken into account, `YYYY-MM-DD`.
+    pub until: Option<String>,
+    /// `day`, `week` or `month`, the size of the timeline buckets.
+    pub interval: Option<String>,
+}
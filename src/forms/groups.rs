@@ -3,6 +3,21 @@ use serde::Deserialize;
 #[derive(Deserialize)]
 pub struct AddGroupForm {
     pub name: String,
+    /// Makes the group a smart one when not empty.
+    #[serde(default)]
+    pub rules: String,
+}
+
+#[derive(Deserialize)]
+pub struct GroupRulesForm {
+    pub id: i32,
+    pub rules: String,
+}
+
+#[derive(Deserialize)]
+pub struct PreviewGroupRulesForm {
+    #[serde(default)]
+    pub rules: String,
 }
 
 #[derive(Deserialize)]
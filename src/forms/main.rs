@@ -8,6 +8,17 @@ pub struct SendEmailForm {
     #[multipart(limit = "10MB")]
     pub attachment: Option<TempFile>,
     pub recipients: MpJson<Vec<String>>,
+    /// Addresses, group ids and `email:<id>` entries left out of the recipients.
+    pub exclude: Option<MpJson<Vec<String>>>,
+}
+
+/// Entries of the compose form the audience preview is asked for.
+#[derive(Deserialize)]
+pub struct AudiencePreviewPayload {
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
 }
 
 #[derive(Deserialize)]
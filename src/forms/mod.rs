@@ -1,3 +1,4 @@
+pub mod api;
 pub mod files;
 pub mod groups;
 pub mod main;
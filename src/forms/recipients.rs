@@ -1,4 +1,4 @@
-use actix_multipart::form::{MultipartForm, tempfile::TempFile};
+use actix_multipart::form::{MultipartForm, tempfile::TempFile, text::Text};
 use serde::Deserialize;
 
 #[derive(Deserialize)]
@@ -12,10 +12,41 @@ pub struct DeleteRecipientForm {
     pub id: i32,
 }
 
+/// Recipients of a duplicate set, all but `keep` are merged into it.
+#[derive(Deserialize)]
+pub struct MergeRecipientsForm {
+    pub keep: i32,
+    #[serde(default)]
+    pub ids: Vec<i32>,
+}
+
 #[derive(MultipartForm)]
 pub struct UploadRecipientsForm {
     #[multipart(limit = "10MB")]
     pub csv: TempFile,
+    /// `auto` or one of the import encodings.
+    pub encoding: Option<Text<String>>,
+    /// `auto` or one of the import delimiters.
+    pub delimiter: Option<Text<String>>,
+    pub mode: Option<Text<String>>,
+    pub assign_group: Option<Text<String>>,
+}
+
+/// Reading settings of an uploaded file, `header` and `target` go in pairs.
+#[derive(Deserialize)]
+pub struct ImportOptionsForm {
+    #[serde(default)]
+    pub encoding: String,
+    #[serde(default)]
+    pub delimiter: String,
+    pub sheet: Option<String>,
+    pub mode: String,
+    #[serde(default)]
+    pub assign_group: String,
+    #[serde(default)]
+    pub header: Vec<String>,
+    #[serde(default)]
+    pub target: Vec<String>,
 }
 
 #[derive(Deserialize)]
@@ -24,6 +24,11 @@ pub struct SaveHubForm {
     pub imap_port: Option<i32>,
     pub created_at: Option<chrono::NaiveDateTime>,
     pub message: Option<String>,
+    pub imap_folders: Option<String>,
+    pub imap_sent_folder: Option<String>,
+    #[serde(default)]
+    pub imap_sent_all: bool,
+    pub imap_unsubscribe_folder: Option<String>,
 }
 
 impl From<SaveHubForm> for Hub {
@@ -40,6 +45,20 @@ impl From<SaveHubForm> for Hub {
             created_at: val.created_at,
             updated_at: Some(chrono::Utc::now().naive_utc()),
             email_template: val.message,
+            imap_folders: val.imap_folders.map(|folders| {
+                let mut unique = Vec::new();
+                for folder in folders.lines().map(str::trim) {
+                    if !folder.is_empty() && !unique.contains(&folder) {
+                        unique.push(folder);
+                    }
+                }
+                unique.join("\n")
+            }),
+            imap_sent_folder: val.imap_sent_folder.map(|folder| folder.trim().to_string()),
+            imap_sent_all: val.imap_sent_all,
+            imap_unsubscribe_folder: val
+                .imap_unsubscribe_folder
+                .map(|folder| folder.trim().to_string()),
         }
     }
 }
@@ -48,3 +67,27 @@ impl From<SaveHubForm> for Hub {
 pub struct DeleteHubForm {
     pub id: i32,
 }
+
+#[derive(Deserialize)]
+pub struct AddApiKeyForm {
+    pub name: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RevokeApiKeyForm {
+    pub id: i32,
+}
+
+#[derive(Deserialize)]
+pub struct AddWebhookForm {
+    pub url: String,
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteWebhookForm {
+    pub id: i32,
+}
@@ -0,0 +1,734 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Cursor;
+
+use calamine::{Data, DataType, Reader};
+use encoding_rs::{Encoding, KOI8_R, UTF_8, WINDOWS_1251};
+use serde::{Deserialize, Serialize};
+
+use crate::export::unescape_formula;
+use crate::models::recipient::{RecipientImport, normalize_email};
+
+/// Columns of the recipients CSV, any other column is a custom field.
+pub const RECIPIENT_CSV_COLUMNS: [&str; 3] = ["name", "email", "groups"];
+
+/// Encodings of the uploaded files, Russian Excel saves CSV in Windows-1251.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum ImportEncoding {
+    Utf8,
+    Utf8Bom,
+    Windows1251,
+    Koi8R,
+}
+
+impl ImportEncoding {
+    pub const ALL: [ImportEncoding; 4] = [
+        ImportEncoding::Utf8,
+        ImportEncoding::Utf8Bom,
+        ImportEncoding::Windows1251,
+        ImportEncoding::Koi8R,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportEncoding::Utf8 => "utf-8",
+            ImportEncoding::Utf8Bom => "utf-8-bom",
+            ImportEncoding::Windows1251 => "windows-1251",
+            ImportEncoding::Koi8R => "koi8-r",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|encoding| encoding.as_str() == value)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ImportEncoding::Utf8 => "UTF-8",
+            ImportEncoding::Utf8Bom => "UTF-8 с BOM",
+            ImportEncoding::Windows1251 => "Windows-1251",
+            ImportEncoding::Koi8R => "KOI8-R",
+        }
+    }
+
+    fn encoding(&self) -> &'static Encoding {
+        match self {
+            ImportEncoding::Utf8 | ImportEncoding::Utf8Bom => UTF_8,
+            ImportEncoding::Windows1251 => WINDOWS_1251,
+            ImportEncoding::Koi8R => KOI8_R,
+        }
+    }
+
+    /// UTF-8 when the content is valid UTF-8, otherwise the Cyrillic code page giving more
+    /// lowercase letters: read in the wrong one, Russian text comes out mostly in capitals.
+    pub fn detect(content: &[u8]) -> Self {
+        if content.starts_with(b"\xEF\xBB\xBF") {
+            return ImportEncoding::Utf8Bom;
+        }
+        if std::str::from_utf8(content).is_ok() {
+            return ImportEncoding::Utf8;
+        }
+
+        let score = |encoding: ImportEncoding| {
+            encoding
+                .decode(content)
+                .chars()
+                .map(|c| match c {
+                    'а'..='я' | 'ё' => 1,
+                    'А'..='Я' | 'Ё' => -1,
+                    _ => 0,
+                })
+                .sum::<i64>()
+        };
+
+        match score(ImportEncoding::Koi8R) > score(ImportEncoding::Windows1251) {
+            true => ImportEncoding::Koi8R,
+            false => ImportEncoding::Windows1251,
+        }
+    }
+
+    /// Malformed sequences are replaced, the byte order mark is dropped.
+    pub fn decode(&self, content: &[u8]) -> String {
+        let content = content.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(content);
+        let (text, _) = self.encoding().decode_without_bom_handling(content);
+        text.into_owned()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum ImportDelimiter {
+    Comma,
+    Semicolon,
+    Tab,
+    Pipe,
+}
+
+impl ImportDelimiter {
+    pub const ALL: [ImportDelimiter; 4] = [
+        ImportDelimiter::Comma,
+        ImportDelimiter::Semicolon,
+        ImportDelimiter::Tab,
+        ImportDelimiter::Pipe,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportDelimiter::Comma => "comma",
+            ImportDelimiter::Semicolon => "semicolon",
+            ImportDelimiter::Tab => "tab",
+            ImportDelimiter::Pipe => "pipe",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|delimiter| delimiter.as_str() == value)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ImportDelimiter::Comma => "Запятая",
+            ImportDelimiter::Semicolon => "Точка с запятой",
+            ImportDelimiter::Tab => "Табуляция",
+            ImportDelimiter::Pipe => "Вертикальная черта",
+        }
+    }
+
+    pub fn byte(&self) -> u8 {
+        match self {
+            ImportDelimiter::Comma => b',',
+            ImportDelimiter::Semicolon => b';',
+            ImportDelimiter::Tab => b'\t',
+            ImportDelimiter::Pipe => b'|',
+        }
+    }
+
+    /// The delimiter found most often outside quotes in the header line, a comma by default.
+    pub fn detect(text: &str) -> Self {
+        let mut counts = [0usize; 4];
+        let mut quoted = false;
+
+        for c in text.chars() {
+            match c {
+                '"' => quoted = !quoted,
+                '\n' | '\r' if !quoted => break,
+                c if !quoted => {
+                    if let Some(i) = Self::ALL
+                        .iter()
+                        .position(|delimiter| delimiter.byte() as char == c)
+                    {
+                        counts[i] += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Self::ALL
+            .into_iter()
+            .zip(counts)
+            .filter(|(_, count)| *count > 0)
+            .max_by_key(|(_, count)| *count)
+            .map(|(delimiter, _)| delimiter)
+            .unwrap_or(ImportDelimiter::Comma)
+    }
+}
+
+/// Recipient column filled from a column of the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnTarget {
+    Name,
+    Email,
+    Groups,
+    /// Custom field named after the header.
+    Field,
+    Skip,
+}
+
+impl ColumnTarget {
+    pub const ALL: [ColumnTarget; 5] = [
+        ColumnTarget::Name,
+        ColumnTarget::Email,
+        ColumnTarget::Groups,
+        ColumnTarget::Field,
+        ColumnTarget::Skip,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColumnTarget::Name => "name",
+            ColumnTarget::Email => "email",
+            ColumnTarget::Groups => "groups",
+            ColumnTarget::Field => "field",
+            ColumnTarget::Skip => "skip",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|target| target.as_str() == value)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ColumnTarget::Name => "Имя",
+            ColumnTarget::Email => "Адрес",
+            ColumnTarget::Groups => "Группы",
+            ColumnTarget::Field => "Произвольное поле",
+            ColumnTarget::Skip => "Не загружать",
+        }
+    }
+
+    /// Recognizes the usual English and Russian headers, anything else is a custom field.
+    pub fn guess(header: &str) -> Self {
+        match header.trim().to_lowercase().as_str() {
+            "name" | "имя" | "фио" | "получатель" | "контакт" => {
+                ColumnTarget::Name
+            }
+            "email"
+            | "e-mail"
+            | "mail"
+            | "почта"
+            | "эл. почта"
+            | "электронная почта"
+            | "адрес"
+            | "email адрес" => ColumnTarget::Email,
+            "groups" | "group" | "группы" | "группа" => ColumnTarget::Groups,
+            "" => ColumnTarget::Skip,
+            _ => ColumnTarget::Field,
+        }
+    }
+}
+
+/// What the import does with the recipients already in the hub.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum ImportMode {
+    /// Groups and fields of the file replace the current ones.
+    Replace,
+    /// Groups of the file are added, fields of the file overwrite the ones with the same name.
+    #[default]
+    Merge,
+    /// Recipients already in the hub are left alone.
+    NewOnly,
+}
+
+impl ImportMode {
+    pub const ALL: [ImportMode; 3] = [ImportMode::Merge, ImportMode::Replace, ImportMode::NewOnly];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportMode::Replace => "replace",
+            ImportMode::Merge => "merge",
+            ImportMode::NewOnly => "new",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.as_str() == value)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ImportMode::Replace => "Заменить группы и поля",
+            ImportMode::Merge => "Дополнить группы и поля",
+            ImportMode::NewOnly => "Только новые получатели",
+        }
+    }
+}
+
+/// How the uploaded file is read and applied, anything not set is detected.
+#[derive(Default)]
+pub struct ImportOptions {
+    pub encoding: Option<ImportEncoding>,
+    pub delimiter: Option<ImportDelimiter>,
+    /// Sheet of a spreadsheet, the first one when not set.
+    pub sheet: Option<String>,
+    /// Targets chosen for the headers, the others are guessed.
+    pub columns: HashMap<String, ColumnTarget>,
+    pub mode: ImportMode,
+    /// Group every imported recipient is added to, created when missing.
+    pub assign_group: Option<String>,
+}
+
+impl ImportOptions {
+    pub fn of(import: &RecipientImport) -> Self {
+        Self {
+            encoding: import.encoding.as_deref().and_then(ImportEncoding::parse),
+            delimiter: import.delimiter.as_deref().and_then(ImportDelimiter::parse),
+            sheet: import.sheet.clone(),
+            columns: import
+                .column_map
+                .as_deref()
+                .and_then(|columns| serde_json::from_str(columns).ok())
+                .unwrap_or_default(),
+            mode: import
+                .mode
+                .as_deref()
+                .and_then(ImportMode::parse)
+                .unwrap_or_default(),
+            assign_group: import.assign_group.clone(),
+        }
+    }
+}
+
+/// What the import does with a row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    New,
+    Updated,
+    Unchanged,
+    /// Skipped, see the row errors.
+    Invalid,
+}
+
+/// Recipient read from one line of the file.
+#[derive(Debug, Serialize)]
+pub struct ImportRow {
+    /// Line of the file, the header is line 1.
+    pub line: u64,
+    pub name: String,
+    pub email: String,
+    pub groups: Vec<String>,
+    pub fields: HashMap<String, String>,
+    pub status: ImportStatus,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportColumn {
+    pub header: String,
+    pub target: ColumnTarget,
+}
+
+/// Rows of the file together with the settings used to read it.
+#[derive(Default)]
+pub struct ParsedImport {
+    /// Set for CSV files only.
+    pub encoding: Option<ImportEncoding>,
+    pub delimiter: Option<ImportDelimiter>,
+    /// Set for spreadsheets only.
+    pub sheet: Option<String>,
+    pub sheets: Vec<String>,
+    pub columns: Vec<ImportColumn>,
+    pub rows: Vec<ImportRow>,
+}
+
+impl ParsedImport {
+    pub fn has_column(&self, target: ColumnTarget) -> bool {
+        self.columns.iter().any(|column| column.target == target)
+    }
+}
+
+/// XLSX and ODS files are zip archives, XLS files are OLE documents.
+pub fn is_spreadsheet(content: &[u8]) -> bool {
+    content.starts_with(b"PK\x03\x04") || content.starts_with(b"\xD0\xCF\x11\xE0")
+}
+
+/// Reads the rows of a CSV file or a spreadsheet, problems with single rows are reported as
+/// row errors.
+pub fn parse_recipients_file(
+    content: &[u8],
+    options: &ImportOptions,
+) -> Result<ParsedImport, Box<dyn Error>> {
+    let mut parsed = match is_spreadsheet(content) {
+        true => parse_recipients_spreadsheet(content, options)?,
+        false => parse_recipients_csv(content, options)?,
+    };
+
+    validate_rows(&mut parsed.rows);
+
+    Ok(parsed)
+}
+
+fn parse_recipients_csv(
+    content: &[u8],
+    options: &ImportOptions,
+) -> Result<ParsedImport, Box<dyn Error>> {
+    let encoding = options
+        .encoding
+        .unwrap_or_else(|| ImportEncoding::detect(content));
+    let text = encoding.decode(content);
+    let delimiter = options
+        .delimiter
+        .unwrap_or_else(|| ImportDelimiter::detect(&text));
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(delimiter.byte())
+        .from_reader(text.as_bytes());
+
+    let columns = map_columns(rdr.headers()?.iter(), options);
+    if columns.is_empty() {
+        return Err("файл пуст".into());
+    }
+
+    let mut rows = Vec::new();
+    let mut record = csv::StringRecord::new();
+
+    loop {
+        let line = rdr.position().line();
+        match rdr.read_record(&mut record) {
+            Ok(false) => break,
+            Ok(true) => {
+                let line = record
+                    .position()
+                    .map(|position| position.line())
+                    .unwrap_or(line);
+                rows.push(read_row(line, &columns, record.iter()));
+            }
+            Err(err) => {
+                let line = err
+                    .position()
+                    .map(|position| position.line())
+                    .unwrap_or(line);
+                rows.push(ImportRow {
+                    line,
+                    name: String::new(),
+                    email: String::new(),
+                    groups: Vec::new(),
+                    fields: HashMap::new(),
+                    status: ImportStatus::Invalid,
+                    errors: vec![format!("Ошибка разбора строки: {}", err)],
+                });
+            }
+        }
+    }
+
+    Ok(ParsedImport {
+        encoding: Some(encoding),
+        delimiter: Some(delimiter),
+        columns,
+        rows,
+        ..Default::default()
+    })
+}
+
+fn parse_recipients_spreadsheet(
+    content: &[u8],
+    options: &ImportOptions,
+) -> Result<ParsedImport, Box<dyn Error>> {
+    let mut workbook = calamine::open_workbook_auto_from_rs(Cursor::new(content))?;
+
+    let sheets = workbook.sheet_names();
+    let sheet = match options
+        .sheet
+        .as_ref()
+        .filter(|sheet| sheets.contains(sheet))
+    {
+        Some(sheet) => sheet.clone(),
+        // The first sheet with any data, workbooks often start with an empty one
+        None => sheets
+            .iter()
+            .find(|sheet| {
+                workbook
+                    .worksheet_range(sheet)
+                    .is_ok_and(|range| !range.is_empty())
+            })
+            .or(sheets.first())
+            .cloned()
+            .ok_or("в книге нет листов")?,
+    };
+    let range = workbook.worksheet_range(&sheet)?;
+
+    // Lines are counted from the top of the sheet, as the spreadsheet shows them.
+    let first_line = range.start().map_or(0, |(row, _)| row as u64) + 1;
+    let mut cells = range.rows();
+
+    let headers = cells
+        .next()
+        .map(|cells| cells.iter().map(cell_text).collect::<Vec<_>>())
+        .unwrap_or_default();
+    // An empty sheet is shown as such, so that another one can be chosen
+    let columns = map_columns(headers.iter().map(String::as_str), options);
+
+    let rows = cells
+        .enumerate()
+        .map(|(i, cells)| (first_line + i as u64 + 1, cells))
+        .filter(|(_, cells)| cells.iter().any(|cell| !cell.is_empty()))
+        .map(|(line, cells)| {
+            let values = cells.iter().map(cell_text).collect::<Vec<_>>();
+            read_row(line, &columns, values.iter().map(String::as_str))
+        })
+        .collect();
+
+    Ok(ParsedImport {
+        sheet: Some(sheet),
+        sheets,
+        columns,
+        rows,
+        ..Default::default()
+    })
+}
+
+/// Cell value as it reads in the spreadsheet: whole numbers without a fraction, so phone
+/// numbers stay intact, and dates as `YYYY-MM-DD`.
+fn cell_text(cell: &Data) -> String {
+    match cell {
+        Data::DateTime(_) | Data::DateTimeIso(_) => match cell.as_datetime() {
+            Some(datetime) if datetime.time() == chrono::NaiveTime::MIN => {
+                datetime.date().to_string()
+            }
+            Some(datetime) => datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
+            None => cell.to_string(),
+        },
+        Data::Error(_) => String::new(),
+        cell => cell.to_string(),
+    }
+}
+
+fn map_columns<'a>(
+    headers: impl Iterator<Item = &'a str>,
+    options: &ImportOptions,
+) -> Vec<ImportColumn> {
+    headers
+        .map(|header| {
+            let header = unescape_formula(header.trim()).to_string();
+            let target = options
+                .columns
+                .get(&header)
+                .copied()
+                .unwrap_or_else(|| ColumnTarget::guess(&header));
+            ImportColumn { header, target }
+        })
+        .collect()
+}
+
+/// Group names of a `groups` cell. Names are separated by commas, a comma or a backslash inside
+/// a name is escaped with a backslash.
+pub fn split_group_names(cell: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut name = String::new();
+    let mut chars = cell.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => name.extend(chars.next()),
+            ',' => names.push(std::mem::take(&mut name)),
+            c => name.push(c),
+        }
+    }
+    names.push(name);
+
+    names
+        .into_iter()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Writes group names into one `groups` cell that [`split_group_names`] reads back.
+pub fn join_group_names(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| name.replace('\\', "\\\\").replace(',', "\\,"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn read_row<'a>(
+    line: u64,
+    columns: &[ImportColumn],
+    values: impl Iterator<Item = &'a str>,
+) -> ImportRow {
+    let mut row = ImportRow {
+        line,
+        name: String::new(),
+        email: String::new(),
+        groups: Vec::new(),
+        fields: HashMap::new(),
+        status: ImportStatus::New,
+        errors: Vec::new(),
+    };
+
+    for (column, value) in columns.iter().zip(values) {
+        let value = unescape_formula(value);
+        match column.target {
+            ColumnTarget::Name => row.name = value.trim().to_string(),
+            ColumnTarget::Email => row.email = value.trim().to_string(),
+            ColumnTarget::Groups => row.groups = split_group_names(value),
+            ColumnTarget::Field => {
+                if value.is_empty() || column.header.is_empty() {
+                    continue;
+                }
+                row.fields.insert(column.header.clone(), value.to_string());
+            }
+            ColumnTarget::Skip => continue,
+        }
+    }
+
+    row
+}
+
+/// Checks the rows on their own and against the earlier rows of the file. The name is only
+/// needed for new recipients, it is checked in the preview.
+fn validate_rows(rows: &mut [ImportRow]) {
+    let mut seen: HashMap<String, u64> = HashMap::new();
+
+    for row in rows.iter_mut() {
+        if row.status == ImportStatus::Invalid {
+            continue;
+        }
+
+        if row.email.is_empty() {
+            row.errors.push("Не указан адрес".to_string());
+        } else if let Some(email) = normalize_email(&row.email) {
+            row.email = email;
+            if let Some(line) = seen.get(&row.email.to_lowercase()) {
+                row.errors
+                    .push(format!("Адрес уже встречается в строке {}", line));
+            } else {
+                seen.insert(row.email.to_lowercase(), row.line);
+            }
+        } else {
+            row.errors.push(format!("Некорректный адрес {}", row.email));
+        }
+
+        if !row.errors.is_empty() {
+            row.status = ImportStatus::Invalid;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_names_round_trip() {
+        let names = vec![
+            "Клиенты, опт".to_string(),
+            "VIP".to_string(),
+            "C:\\Shares".to_string(),
+        ];
+        let cell = join_group_names(&names);
+        assert_eq!(cell, "Клиенты\\, опт,VIP,C:\\\\Shares");
+        assert_eq!(split_group_names(&cell), names);
+    }
+
+    #[test]
+    fn formula_cells_round_trip() {
+        let mut table = crate::export::ExportTable::new(
+            RECIPIENT_CSV_COLUMNS
+                .into_iter()
+                .chain(["-note"])
+                .map(String::from)
+                .collect(),
+        );
+        table.rows.push(vec![
+            "=HYPERLINK(\"http://example.com\")".to_string(),
+            "ivan@example.com".to_string(),
+            join_group_names(&["+VIP".to_string(), "Клиенты".to_string()]),
+            "-5".to_string(),
+        ]);
+        let content = table.to_csv().unwrap();
+
+        let text = String::from_utf8_lossy(&content);
+        assert!(text.contains("'=HYPERLINK"));
+        assert!(text.contains("'+VIP"));
+        assert!(text.contains("'-note"));
+
+        let rows = parse_recipients_file(&content, &ImportOptions::default())
+            .unwrap()
+            .rows;
+        assert_eq!(rows[0].name, "=HYPERLINK(\"http://example.com\")");
+        assert_eq!(rows[0].groups, ["+VIP", "Клиенты"]);
+        assert_eq!(rows[0].fields["-note"], "-5");
+        assert_eq!(rows[0].status, ImportStatus::New);
+    }
+
+    #[test]
+    fn detect_encoding() {
+        let text = "name,email\nИванов Иван,ivan@example.com\n";
+        let (cp1251, _, _) = WINDOWS_1251.encode(text);
+        let (koi8, _, _) = KOI8_R.encode(text);
+
+        assert_eq!(
+            ImportEncoding::detect(text.as_bytes()),
+            ImportEncoding::Utf8
+        );
+        assert_eq!(
+            ImportEncoding::detect(&[b"\xEF\xBB\xBF", text.as_bytes()].concat()),
+            ImportEncoding::Utf8Bom
+        );
+        assert_eq!(ImportEncoding::detect(&cp1251), ImportEncoding::Windows1251);
+        assert_eq!(ImportEncoding::detect(&koi8), ImportEncoding::Koi8R);
+        assert_eq!(ImportEncoding::Windows1251.decode(&cp1251), text);
+        assert_eq!(ImportEncoding::Koi8R.decode(&koi8), text);
+    }
+
+    #[test]
+    fn detect_delimiter() {
+        assert_eq!(
+            ImportDelimiter::detect("name,email,groups\na,b,c"),
+            ImportDelimiter::Comma
+        );
+        assert_eq!(
+            ImportDelimiter::detect("name;email;groups\n\"a,b\";c;d"),
+            ImportDelimiter::Semicolon
+        );
+        assert_eq!(
+            ImportDelimiter::detect("name\temail\n"),
+            ImportDelimiter::Tab
+        );
+        assert_eq!(
+            ImportDelimiter::detect("name|email|city\n"),
+            ImportDelimiter::Pipe
+        );
+        // Delimiters inside quoted headers do not count
+        assert_eq!(
+            ImportDelimiter::detect("\"Имя; Фамилия\",email\n"),
+            ImportDelimiter::Comma
+        );
+        assert_eq!(ImportDelimiter::detect("email\n"), ImportDelimiter::Comma);
+    }
+
+    #[test]
+    fn group_names_typed_by_hand() {
+        assert_eq!(split_group_names(" VIP , Опт,, "), vec!["VIP", "Опт"]);
+        assert!(split_group_names("").is_empty());
+    }
+}
@@ -1,8 +1,11 @@
 pub mod db;
+pub mod export;
 pub mod forms;
+pub mod import;
 pub mod middleware;
 pub mod models;
 pub mod repository;
 pub mod routes;
+pub mod rules;
 pub mod schema;
 pub mod utils;
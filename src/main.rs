@@ -2,7 +2,6 @@ use std::env;
 
 use actix_files::Files;
 use actix_identity::IdentityMiddleware;
-use actix_session::config::CookieContentSecurity;
 use actix_session::{SessionMiddleware, storage::CookieSessionStore};
 use actix_web::cookie::Key;
 use actix_web::{App, HttpServer, middleware, web};
@@ -13,17 +12,43 @@ use log::error;
 use pushkind_emailer::db::establish_connection_pool;
 use pushkind_emailer::middleware::RedirectUnauthorized;
 use pushkind_emailer::models::config::ServerConfig;
+use pushkind_emailer::routes::api::emails::{
+    api_emails, api_emails_create, api_emails_delete, api_emails_get, api_emails_preview,
+    api_emails_retry,
+};
+use pushkind_emailer::routes::api::groups::{
+    api_groups, api_groups_create, api_groups_delete, api_groups_get, api_groups_update,
+};
+use pushkind_emailer::routes::api::json_error_handler;
+use pushkind_emailer::routes::api::messages::{api_messages_get, api_messages_send};
+use pushkind_emailer::routes::api::recipients::{
+    api_recipients, api_recipients_create, api_recipients_delete, api_recipients_get,
+    api_recipients_search, api_recipients_update,
+};
+use pushkind_emailer::routes::api::stats::api_stats;
+use pushkind_emailer::routes::api::templates::{
+    api_templates, api_templates_create, api_templates_delete, api_templates_get,
+    api_templates_update,
+};
 use pushkind_emailer::routes::groups::{
-    groups, groups_add, groups_assign, groups_delete, groups_unassign,
+    groups, groups_add, groups_assign, groups_delete, groups_members, groups_rules,
+    groups_rules_preview, groups_unassign,
 };
 use pushkind_emailer::routes::main::{
-    delete_email, index, logout, not_assigned, retry_email, send_email, track_email,
+    click_email, conversation, delete_email, email_details, export_email, index, logout,
+    not_assigned, retry_email, send_email, send_email_preview, track_email,
 };
 use pushkind_emailer::routes::recipients::{
-    recipients, recipients_add, recipients_clean, recipients_delete, recipients_modal,
+    recipients, recipients_add, recipients_clean, recipients_delete, recipients_duplicates,
+    recipients_export, recipients_import, recipients_import_cancel, recipients_import_options,
+    recipients_import_preview, recipients_list, recipients_merge, recipients_modal,
     recipients_save, recipients_upload,
 };
-use pushkind_emailer::routes::settings::{settings, settings_save};
+use pushkind_emailer::routes::settings::{
+    settings, settings_api_keys_add, settings_api_keys_revoke, settings_imap_folders,
+    settings_save, settings_webhooks_add, settings_webhooks_delete,
+};
+use pushkind_emailer::routes::stats::stats;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -82,30 +107,82 @@ async fn main() -> std::io::Result<()> {
             .wrap(middleware::Compress::default())
             .wrap(middleware::Logger::default())
             .service(Files::new("/assets", "./assets"))
+            .service(
+                web::scope("/api/v1")
+                    .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+                    .service(api_recipients)
+                    .service(api_recipients_create)
+                    // Before `/recipients/{id}`, which would take `search` as an id
+                    .service(api_recipients_search)
+                    .service(api_recipients_get)
+                    .service(api_recipients_update)
+                    .service(api_recipients_delete)
+                    .service(api_groups)
+                    .service(api_groups_create)
+                    .service(api_groups_get)
+                    .service(api_groups_update)
+                    .service(api_groups_delete)
+                    .service(api_emails)
+                    .service(api_emails_create)
+                    .service(api_emails_preview)
+                    .service(api_emails_get)
+                    .service(api_emails_retry)
+                    .service(api_emails_delete)
+                    .service(api_templates)
+                    .service(api_templates_create)
+                    .service(api_templates_get)
+                    .service(api_templates_update)
+                    .service(api_templates_delete)
+                    .service(api_messages_send)
+                    .service(api_messages_get)
+                    .service(api_stats),
+            )
             .service(
                 web::scope("")
                     .wrap(RedirectUnauthorized)
                     .service(not_assigned)
                     .service(logout)
                     .service(index)
+                    .service(email_details)
+                    .service(export_email)
                     .service(send_email)
+                    .service(send_email_preview)
                     .service(delete_email)
                     .service(retry_email)
                     .service(track_email)
+                    .service(click_email)
+                    .service(conversation)
                     .service(settings)
                     .service(settings_save)
+                    .service(settings_imap_folders)
+                    .service(settings_api_keys_add)
+                    .service(settings_api_keys_revoke)
+                    .service(settings_webhooks_add)
+                    .service(settings_webhooks_delete)
                     .service(recipients)
+                    .service(recipients_list)
+                    .service(recipients_export)
                     .service(recipients_add)
                     .service(recipients_delete)
                     .service(recipients_clean)
                     .service(recipients_upload)
+                    .service(recipients_import_preview)
+                    .service(recipients_import_options)
+                    .service(recipients_import)
+                    .service(recipients_import_cancel)
+                    .service(recipients_duplicates)
+                    .service(recipients_merge)
                     .service(recipients_modal)
                     .service(recipients_save)
                     .service(groups)
+                    .service(groups_members)
                     .service(groups_add)
                     .service(groups_delete)
                     .service(groups_assign)
-                    .service(groups_unassign),
+                    .service(groups_unassign)
+                    .service(groups_rules)
+                    .service(groups_rules_preview)
+                    .service(stats),
             )
             .app_data(web::Data::new(pool.clone()))
             .app_data(web::Data::new(server_config.clone()))
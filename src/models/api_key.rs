@@ -0,0 +1,84 @@
+use diesel::prelude::*;
+use serde::Serialize;
+
+use crate::models::hub::Hub;
+
+const KEY_PREFIX: &str = "pke_";
+
+/// What an API key is allowed to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiScope {
+    /// Read recipients, groups and emails.
+    Read,
+    /// Create, retry and delete emails.
+    Send,
+    /// Create, change and delete recipients and groups.
+    Recipients,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 3] = [ApiScope::Read, ApiScope::Send, ApiScope::Recipients];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::Read => "read",
+            ApiScope::Send => "send",
+            ApiScope::Recipients => "recipients",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == value.trim())
+    }
+}
+
+#[derive(Queryable, Selectable, Serialize, Identifiable, Associations)]
+#[diesel(belongs_to(Hub, foreign_key = hub_id))]
+#[diesel(table_name = crate::schema::api_keys)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ApiKey {
+    pub id: i32,
+    pub hub_id: i32,
+    pub name: String,
+    pub prefix: String,
+    #[serde(skip)]
+    pub key_hash: String,
+    pub scopes: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+}
+
+impl ApiKey {
+    pub fn get_scopes(&self) -> Vec<ApiScope> {
+        self.scopes.split(',').filter_map(ApiScope::parse).collect()
+    }
+
+    /// Generates a new key as `(prefix, secret, token)`, the token is what clients send.
+    pub fn generate() -> (String, String, String) {
+        let prefix = uuid::Uuid::new_v4().simple().to_string()[..12].to_string();
+        let secret = uuid::Uuid::new_v4().simple().to_string();
+        let token = format!("{KEY_PREFIX}{prefix}_{secret}");
+        (prefix, secret, token)
+    }
+
+    /// Splits a token produced by [`ApiKey::generate`] into prefix and secret.
+    pub fn parse_token(token: &str) -> Option<(&str, &str)> {
+        token.trim().strip_prefix(KEY_PREFIX)?.split_once('_')
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::api_keys)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewApiKey<'a> {
+    pub hub_id: i32,
+    pub name: &'a str,
+    pub prefix: &'a str,
+    pub key_hash: &'a str,
+    pub scopes: &'a str,
+    pub created_at: chrono::NaiveDateTime,
+}
@@ -20,6 +20,23 @@ pub struct Email {
     pub num_opened: i32,
     pub num_replied: i32,
     pub hub_id: i32,
+    /// Single message sent through the API, not shown among campaigns.
+    pub is_transactional: bool,
+}
+
+/// Campaign list row, without the message and the attachment content.
+#[derive(Queryable, Selectable, Serialize)]
+#[diesel(table_name = crate::schema::emails)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct EmailSummary {
+    pub id: i32,
+    pub created_at: chrono::NaiveDateTime,
+    pub is_sent: bool,
+    pub subject: Option<String>,
+    pub attachment_name: Option<String>,
+    pub num_sent: i32,
+    pub num_opened: i32,
+    pub num_replied: i32,
 }
 
 #[derive(Insertable)]
@@ -34,6 +51,7 @@ pub struct NewEmail<'a> {
     pub attachment_name: Option<&'a str>,
     pub attachment_mime: Option<&'a str>,
     pub hub_id: i32,
+    pub is_transactional: bool,
 }
 
 #[derive(Queryable, Selectable, Serialize, Identifiable, Associations)]
@@ -48,6 +66,36 @@ pub struct EmailRecipient {
     pub updated_at: chrono::NaiveDateTime,
     pub is_sent: bool,
     pub replied: bool,
+    pub auto_replied: bool,
+    /// The recipient unsubscribed after receiving this campaign.
+    pub unsubscribed: bool,
+    pub sent_at: Option<chrono::NaiveDateTime>,
+    /// First time the tracking pixel was loaded.
+    pub opened_at: Option<chrono::NaiveDateTime>,
+    /// First reply that was not an auto-reply.
+    pub replied_at: Option<chrono::NaiveDateTime>,
+    /// First time a tracked link was followed.
+    pub clicked_at: Option<chrono::NaiveDateTime>,
+}
+
+impl EmailRecipient {
+    /// Message-ID the worker puts on the message sent to this recipient.
+    pub fn message_id(&self, domain: &str) -> String {
+        format!("{}@{}", self.id, domain)
+    }
+
+    /// Extracts the recipient id from a Message-ID produced by [`EmailRecipient::message_id`].
+    pub fn id_from_message_id(message_id: &str, domain: &str) -> Option<i32> {
+        let message_id = message_id
+            .trim()
+            .trim_start_matches('<')
+            .trim_end_matches('>');
+        let (id, message_domain) = message_id.split_once('@')?;
+        if !message_domain.eq_ignore_ascii_case(domain) {
+            return None;
+        }
+        id.parse().ok()
+    }
 }
 
 #[derive(Insertable)]
@@ -60,4 +108,56 @@ pub struct NewEmailRecipient<'a> {
     pub updated_at: &'a chrono::NaiveDateTime,
     pub is_sent: bool,
     pub replied: bool,
+    pub auto_replied: bool,
+}
+
+#[derive(Queryable, Selectable, Serialize, Identifiable, Associations)]
+#[diesel(belongs_to(EmailRecipient, foreign_key = email_recipient_id))]
+#[diesel(table_name = crate::schema::email_replies)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct EmailReply {
+    pub id: i32,
+    pub email_recipient_id: i32,
+    pub message_id: Option<String>,
+    pub sender: String,
+    pub received_at: chrono::NaiveDateTime,
+    pub subject: Option<String>,
+    pub body: String,
+    pub is_auto_reply: bool,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::email_replies)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewEmailReply<'a> {
+    pub email_recipient_id: i32,
+    pub message_id: Option<&'a str>,
+    pub sender: &'a str,
+    pub received_at: &'a chrono::NaiveDateTime,
+    pub subject: Option<&'a str>,
+    pub body: &'a str,
+    pub is_auto_reply: bool,
+}
+
+#[derive(Queryable, Selectable, Serialize, Identifiable, Associations)]
+#[diesel(belongs_to(EmailReply, foreign_key = reply_id))]
+#[diesel(table_name = crate::schema::email_reply_attachments)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct EmailReplyAttachment {
+    pub id: i32,
+    pub reply_id: i32,
+    pub name: Option<String>,
+    pub mime: Option<String>,
+    pub size: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::email_reply_attachments)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewEmailReplyAttachment<'a> {
+    pub reply_id: i32,
+    pub name: Option<&'a str>,
+    pub mime: Option<&'a str>,
+    pub size: i32,
 }
@@ -16,6 +16,14 @@ pub struct Hub {
     pub imap_server: Option<String>,
     pub imap_port: Option<i32>,
     pub email_template: Option<String>,
+    /// Folders the reply checker scans, one per line, in order.
+    pub imap_folders: Option<String>,
+    /// Folder sent messages are copied to, copying is off when empty.
+    pub imap_sent_folder: Option<String>,
+    /// Copy every sent message instead of one per campaign.
+    pub imap_sent_all: bool,
+    /// Folder processed unsubscribe requests are moved to, left in place when empty.
+    pub imap_unsubscribe_folder: Option<String>,
 }
 
 impl Hub {
@@ -32,6 +40,28 @@ impl Hub {
             imap_server: None,
             imap_port: None,
             email_template: None,
+            imap_folders: None,
+            imap_sent_folder: None,
+            imap_sent_all: false,
+            imap_unsubscribe_folder: None,
+        }
+    }
+    /// Folders to scan for replies, falling back to INBOX when none are configured.
+    pub fn get_imap_folders(&self) -> Vec<String> {
+        let folders = self
+            .imap_folders
+            .as_deref()
+            .unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|folder| !folder.is_empty())
+            .map(String::from)
+            .collect::<Vec<_>>();
+
+        if folders.is_empty() {
+            vec![String::from("INBOX")]
+        } else {
+            folders
         }
     }
     pub fn get_usubscribe_url(&self) -> String {
@@ -41,3 +71,15 @@ impl Hub {
         }
     }
 }
+
+/// Position of the reply checker in one IMAP folder of a hub.
+#[derive(Queryable, Selectable, Insertable, Serialize)]
+#[diesel(table_name = crate::schema::hub_imap_folders)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct HubImapFolder {
+    pub hub_id: i32,
+    pub folder: String,
+    pub uid_validity: i64,
+    pub last_uid: i64,
+    pub updated_at: chrono::NaiveDateTime,
+}
@@ -1,5 +1,9 @@
+pub mod api_key;
 pub mod auth;
 pub mod config;
 pub mod email;
 pub mod hub;
 pub mod recipient;
+pub mod stats;
+pub mod template;
+pub mod webhook;
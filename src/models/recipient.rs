@@ -2,8 +2,9 @@ use diesel::prelude::*;
 use serde::Serialize;
 
 use crate::models::hub::Hub;
+use crate::rules::Rule;
 
-#[derive(Queryable, Selectable, Serialize, Identifiable, Associations)]
+#[derive(Queryable, Selectable, Serialize, Identifiable, Associations, Clone)]
 #[diesel(table_name = crate::schema::recipients)]
 #[diesel(belongs_to(Hub, foreign_key = hub_id))]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
@@ -36,6 +37,17 @@ pub struct Group {
     pub hub_id: i32,
     pub created_at: Option<chrono::NaiveDateTime>,
     pub updated_at: Option<chrono::NaiveDateTime>,
+    /// Condition of a smart group, see [`crate::rules::Rule`]. Its members are not stored.
+    pub rules: Option<String>,
+}
+
+impl Group {
+    /// Parsed condition of a smart group, `None` for a group with stored members.
+    pub fn rule(&self) -> Option<Rule> {
+        self.rules
+            .as_deref()
+            .and_then(|rules| Rule::parse(rules).ok())
+    }
 }
 
 #[derive(Insertable)]
@@ -67,3 +79,192 @@ pub struct RecipientField {
     pub field: String,
     pub value: String,
 }
+
+/// Uploaded recipients file waiting for the confirmation of the preview.
+#[derive(Queryable, Selectable, Identifiable, Associations)]
+#[diesel(table_name = crate::schema::recipient_imports)]
+#[diesel(belongs_to(Hub, foreign_key = hub_id))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct RecipientImport {
+    pub id: i32,
+    pub hub_id: i32,
+    pub file_name: String,
+    pub content: Vec<u8>,
+    pub created_at: chrono::NaiveDateTime,
+    /// Chosen by the user, detected from the content when not set.
+    pub encoding: Option<String>,
+    pub delimiter: Option<String>,
+    /// JSON object mapping file headers to recipient columns.
+    pub column_map: Option<String>,
+    /// Sheet of a spreadsheet upload, the first one when not set.
+    pub sheet: Option<String>,
+    pub mode: Option<String>,
+    /// Group every imported recipient is added to.
+    pub assign_group: Option<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::recipient_imports)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewRecipientImport<'a> {
+    pub hub_id: i32,
+    pub file_name: &'a str,
+    pub content: &'a [u8],
+    pub created_at: chrono::NaiveDateTime,
+    pub encoding: Option<&'a str>,
+    pub delimiter: Option<&'a str>,
+    pub mode: Option<&'a str>,
+    pub assign_group: Option<&'a str>,
+}
+
+/// Address shared by several recipients of a hub, ignoring case.
+#[derive(QueryableByName, Serialize)]
+pub struct DuplicateEmail {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub address: String,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub num_recipients: i64,
+    /// Comma separated.
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub recipient_ids: String,
+}
+
+/// Address in the form it is stored and compared in: trimmed, with the domain lowercased and
+/// converted to punycode. `None` when the address does not follow the RFC 5321 syntax.
+pub fn normalize_email(address: &str) -> Option<String> {
+    let (local, domain) = address.trim().rsplit_once('@')?;
+
+    if !is_valid_local_part(local) {
+        return None;
+    }
+
+    // Lowercases the domain and turns international names into `xn--` labels
+    let domain = idna::domain_to_ascii(domain).ok()?;
+    if !is_valid_domain(&domain) {
+        return None;
+    }
+
+    let address = format!("{}@{}", local, domain);
+    (address.len() <= 254).then_some(address)
+}
+
+pub fn is_valid_email(address: &str) -> bool {
+    normalize_email(address).is_some()
+}
+
+/// Dot-atom or quoted string of at most 64 characters.
+fn is_valid_local_part(local: &str) -> bool {
+    if local.is_empty() || local.len() > 64 {
+        return false;
+    }
+
+    if let Some(quoted) = local
+        .strip_prefix('"')
+        .and_then(|local| local.strip_suffix('"'))
+    {
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.next().is_some_and(|c| (' '..='~').contains(&c)) => {}
+                '\\' | '"' => return false,
+                c if (' '..='~').contains(&c) => {}
+                _ => return false,
+            }
+        }
+        return true;
+    }
+
+    local.split('.').all(|atom| {
+        !atom.is_empty()
+            && atom
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c))
+    })
+}
+
+/// Dotted host name of letters, digits and hyphens with a non-numeric top-level label.
+fn is_valid_domain(domain: &str) -> bool {
+    let labels = domain.split('.').collect::<Vec<_>>();
+
+    domain.len() <= 253
+        && labels.len() > 1
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        && labels
+            .last()
+            .is_some_and(|label| !label.chars().all(|c| c.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_addresses() {
+        assert_eq!(
+            normalize_email("  Ivan.Petrov@Example.COM \t").as_deref(),
+            Some("Ivan.Petrov@example.com")
+        );
+        assert_eq!(
+            normalize_email("info@пример.рф").as_deref(),
+            Some("info@xn--e1afmkfd.xn--p1ai")
+        );
+        assert_eq!(
+            normalize_email("info@xn--e1afmkfd.xn--p1ai").as_deref(),
+            Some("info@xn--e1afmkfd.xn--p1ai")
+        );
+    }
+
+    #[test]
+    fn local_parts() {
+        assert!(is_valid_email("first.last+tag@example.com"));
+        assert!(is_valid_email("o'brien@example.com"));
+        assert!(is_valid_email("\"john doe\"@example.com"));
+        assert!(is_valid_email("\"a@b\"@example.com"));
+        assert!(is_valid_email("\"quote\\\"inside\"@example.com"));
+        assert!(!is_valid_email("\"unterminated@example.com"));
+        assert!(!is_valid_email("\"bad\"quote\"@example.com"));
+        assert!(!is_valid_email(".start@example.com"));
+        assert!(!is_valid_email("double..dot@example.com"));
+        assert!(!is_valid_email("space inside@example.com"));
+        assert!(!is_valid_email("@example.com"));
+        assert!(!is_valid_email("иван@example.com"));
+    }
+
+    #[test]
+    fn domains() {
+        assert!(is_valid_email("a@mail.example.co.uk"));
+        assert!(is_valid_email("a@1example.com"));
+        assert!(!is_valid_email("a@localhost"));
+        assert!(!is_valid_email("a@example"));
+        assert!(!is_valid_email("a@192.168.0.1"));
+        assert!(!is_valid_email("a@example.123"));
+        assert!(!is_valid_email("a@-example.com"));
+        assert!(!is_valid_email("a@example-.com"));
+        assert!(!is_valid_email("a@exa_mple.com"));
+        assert!(!is_valid_email("a@example..com"));
+        assert!(!is_valid_email("no-at-sign.example.com"));
+    }
+
+    #[test]
+    fn length_limits() {
+        let local = "a".repeat(64);
+        assert!(is_valid_email(&format!("{}@example.com", local)));
+        assert!(!is_valid_email(&format!("a{}@example.com", local)));
+
+        let label = "b".repeat(63);
+        assert!(is_valid_email(&format!("a@{}.com", label)));
+        assert!(!is_valid_email(&format!("a@b{}.com", label)));
+
+        // 64 + 1 + 189 = 254 characters is the longest address
+        let domain = format!("{}.{}.{}.com", label, label, "c".repeat(57));
+        assert_eq!(domain.len(), 189);
+        assert!(is_valid_email(&format!("{}@{}", local, domain)));
+        assert!(!is_valid_email(&format!("{}@c{}", local, domain)));
+    }
+}
@@ -0,0 +1,114 @@
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Integer, Nullable, Text, Timestamp};
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+
+/// Recipient counters of a set of campaigns, serialized together with the rates.
+#[derive(QueryableByName, Clone, Copy, Default)]
+pub struct StatCounts {
+    #[diesel(sql_type = BigInt)]
+    pub num_recipients: i64,
+    #[diesel(sql_type = BigInt)]
+    pub num_sent: i64,
+    /// Refused by the SMTP server when the campaign was sent.
+    #[diesel(sql_type = BigInt)]
+    pub num_bounced: i64,
+    #[diesel(sql_type = BigInt)]
+    pub num_opened: i64,
+    #[diesel(sql_type = BigInt)]
+    pub num_replied: i64,
+    #[diesel(sql_type = BigInt)]
+    pub num_unsubscribed: i64,
+}
+
+fn percent(part: i64, total: i64) -> f64 {
+    match total {
+        0 => 0.0,
+        total => (part as f64 * 1000.0 / total as f64).round() / 10.0,
+    }
+}
+
+impl StatCounts {
+    pub fn open_rate(&self) -> f64 {
+        percent(self.num_opened, self.num_sent)
+    }
+
+    pub fn reply_rate(&self) -> f64 {
+        percent(self.num_replied, self.num_sent)
+    }
+
+    /// Share of the attempted deliveries, sent and bounced.
+    pub fn bounce_rate(&self) -> f64 {
+        percent(self.num_bounced, self.num_sent + self.num_bounced)
+    }
+
+    pub fn unsubscribe_rate(&self) -> f64 {
+        percent(self.num_unsubscribed, self.num_sent)
+    }
+}
+
+impl Serialize for StatCounts {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("StatCounts", 10)?;
+        state.serialize_field("num_recipients", &self.num_recipients)?;
+        state.serialize_field("num_sent", &self.num_sent)?;
+        state.serialize_field("num_bounced", &self.num_bounced)?;
+        state.serialize_field("num_opened", &self.num_opened)?;
+        state.serialize_field("num_replied", &self.num_replied)?;
+        state.serialize_field("num_unsubscribed", &self.num_unsubscribed)?;
+        state.serialize_field("open_rate", &self.open_rate())?;
+        state.serialize_field("reply_rate", &self.reply_rate())?;
+        state.serialize_field("bounce_rate", &self.bounce_rate())?;
+        state.serialize_field("unsubscribe_rate", &self.unsubscribe_rate())?;
+        state.end()
+    }
+}
+
+#[derive(QueryableByName, Serialize)]
+pub struct CampaignStats {
+    #[diesel(sql_type = Integer)]
+    pub id: i32,
+    #[diesel(sql_type = Timestamp)]
+    pub created_at: chrono::NaiveDateTime,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub subject: Option<String>,
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub counts: StatCounts,
+}
+
+/// Campaigns created within one day, week or month.
+#[derive(QueryableByName, Serialize)]
+pub struct PeriodStats {
+    /// `YYYY-MM-DD`, `YYYY-Www` or `YYYY-MM` depending on the interval.
+    #[diesel(sql_type = Text)]
+    pub period: String,
+    #[diesel(sql_type = BigInt)]
+    pub num_campaigns: i64,
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub counts: StatCounts,
+}
+
+/// Campaign results of the current group members.
+#[derive(QueryableByName, Serialize)]
+pub struct GroupStats {
+    #[diesel(sql_type = Integer)]
+    pub id: i32,
+    #[diesel(sql_type = Text)]
+    pub name: String,
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub counts: StatCounts,
+}
+
+#[derive(QueryableByName, Serialize)]
+pub struct SubjectStats {
+    #[diesel(sql_type = Nullable<Text>)]
+    pub subject: Option<String>,
+    #[diesel(sql_type = BigInt)]
+    pub num_campaigns: i64,
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub counts: StatCounts,
+}
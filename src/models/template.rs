@@ -0,0 +1,62 @@
+use diesel::prelude::*;
+use serde::Serialize;
+use tera::{Context, Tera};
+
+use crate::models::hub::Hub;
+
+/// Stored subject and body of a transactional message, both are Tera templates.
+#[derive(Queryable, Selectable, Serialize, Identifiable, Associations)]
+#[diesel(belongs_to(Hub, foreign_key = hub_id))]
+#[diesel(table_name = crate::schema::message_templates)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct MessageTemplate {
+    pub id: i32,
+    pub hub_id: i32,
+    pub name: String,
+    pub subject: String,
+    pub body: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl MessageTemplate {
+    /// Checks that subject and body are valid templates.
+    pub fn validate(subject: &str, body: &str) -> tera::Result<()> {
+        let mut tera = Tera::default();
+        tera.add_raw_template("subject", subject)?;
+        tera.add_raw_template("body", body)?;
+        Ok(())
+    }
+
+    /// Renders `(subject, body)` with the data, values in the HTML body are escaped.
+    pub fn render(&self, data: &serde_json::Value) -> tera::Result<(String, String)> {
+        let context = Context::from_value(data.clone())?;
+        let subject = Tera::one_off(&self.subject, &context, false)?;
+        let body = Tera::one_off(&self.body, &context, true)?;
+        Ok((subject.trim().to_string(), body))
+    }
+}
+
+/// Tera error with its causes, the top level message alone rarely says what is wrong.
+pub fn describe_error(err: &tera::Error) -> String {
+    let mut message = err.to_string();
+    let mut source = std::error::Error::source(err);
+    while let Some(err) = source {
+        message.push_str(": ");
+        message.push_str(&err.to_string());
+        source = err.source();
+    }
+    message
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::message_templates)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewMessageTemplate<'a> {
+    pub hub_id: i32,
+    pub name: &'a str,
+    pub subject: &'a str,
+    pub body: &'a str,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
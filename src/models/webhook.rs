@@ -0,0 +1,243 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+use diesel::prelude::*;
+use serde::Serialize;
+
+use crate::models::hub::Hub;
+
+/// Events a webhook can subscribe to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookEvent {
+    /// The worker handed the message to the SMTP server.
+    Delivered,
+    /// The SMTP server refused the message.
+    Failed,
+    /// The tracking pixel was loaded for the first time.
+    Opened,
+    /// A link of the message was followed for the first time.
+    Clicked,
+    /// The recipient answered, auto-replies are not reported.
+    Replied,
+    /// The recipient asked to be removed from the mailing list.
+    Unsubscribed,
+}
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 6] = [
+        WebhookEvent::Delivered,
+        WebhookEvent::Failed,
+        WebhookEvent::Opened,
+        WebhookEvent::Clicked,
+        WebhookEvent::Replied,
+        WebhookEvent::Unsubscribed,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::Delivered => "delivered",
+            WebhookEvent::Failed => "failed",
+            WebhookEvent::Opened => "opened",
+            WebhookEvent::Clicked => "clicked",
+            WebhookEvent::Replied => "replied",
+            WebhookEvent::Unsubscribed => "unsubscribed",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|event| event.as_str() == value.trim())
+    }
+}
+
+#[derive(Queryable, Selectable, Serialize, Identifiable, Associations)]
+#[diesel(belongs_to(Hub, foreign_key = hub_id))]
+#[diesel(table_name = crate::schema::webhooks)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Webhook {
+    pub id: i32,
+    pub hub_id: i32,
+    pub url: String,
+    /// Key for the `X-Pushkind-Signature` HMAC, shown to the hub admin.
+    pub secret: String,
+    pub events: String,
+    pub is_active: bool,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+impl Webhook {
+    pub fn get_events(&self) -> Vec<WebhookEvent> {
+        self.events
+            .split(',')
+            .filter_map(WebhookEvent::parse)
+            .collect()
+    }
+
+    /// Checks that the URL is http(s) and its host resolves to public addresses only, so hub
+    /// users cannot make the worker call the server's own network. The error is a message for
+    /// the user.
+    pub fn check_url(url: &str) -> Result<(), String> {
+        let (rest, default_port) = match url.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("https") => (rest, 443),
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => (rest, 80),
+            _ => return Err("Адрес вебхука должен начинаться с http:// или https://.".to_string()),
+        };
+
+        let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+        if authority.is_empty() || authority.contains('@') {
+            return Err("Неверный адрес вебхука.".to_string());
+        }
+        let netloc = match authority.ends_with(']') || !authority.contains(':') {
+            true => format!("{}:{}", authority, default_port),
+            false => authority.to_string(),
+        };
+
+        resolve_public(&netloc)
+            .map(|_| ())
+            .map_err(|e| format!("Адрес вебхука недоступен: {}.", e))
+    }
+
+    pub fn generate_secret() -> String {
+        format!(
+            "{}{}",
+            uuid::Uuid::new_v4().simple(),
+            uuid::Uuid::new_v4().simple()
+        )
+    }
+}
+
+/// Whether the address is reachable from the internet: loopback, private, link-local, shared,
+/// documentation, multicast and unspecified addresses are not.
+pub fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_address(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+/// Resolves `host:port` keeping only the public addresses, an error when none is left.
+///
+/// The webhook worker resolves through this on every delivery, so a host that later points at
+/// an internal address is refused too.
+pub fn resolve_public(netloc: &str) -> io::Result<Vec<SocketAddr>> {
+    let addresses = netloc
+        .to_socket_addrs()?
+        .filter(|address| is_public_address(address.ip()))
+        .collect::<Vec<_>>();
+
+    match addresses.is_empty() {
+        true => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "the host has no public address",
+        )),
+        false => Ok(addresses),
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::webhooks)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewWebhook<'a> {
+    pub hub_id: i32,
+    pub url: &'a str,
+    pub secret: &'a str,
+    pub events: &'a str,
+    pub is_active: bool,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+pub const DELIVERY_PENDING: &str = "pending";
+pub const DELIVERY_DELIVERED: &str = "delivered";
+pub const DELIVERY_FAILED: &str = "failed";
+
+#[derive(Queryable, Selectable, Serialize, Identifiable, Associations)]
+#[diesel(belongs_to(Webhook, foreign_key = webhook_id))]
+#[diesel(table_name = crate::schema::webhook_deliveries)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub event: String,
+    /// JSON body sent to the endpoint, kept as is so retries are signed identically.
+    pub payload: String,
+    /// One of `pending`, `delivered` or `failed`.
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: chrono::NaiveDateTime,
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub delivered_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::webhook_deliveries)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewWebhookDelivery<'a> {
+    pub webhook_id: i32,
+    pub event: &'a str,
+    pub payload: &'a str,
+    pub status: &'a str,
+    pub attempts: i32,
+    pub next_attempt_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_address(ip.parse().unwrap()), "{}", ip);
+        }
+        assert!(is_public_address("93.184.216.34".parse().unwrap()));
+        assert!(is_public_address("2606:2800:220:1::".parse().unwrap()));
+    }
+
+    #[test]
+    fn webhook_urls() {
+        assert!(Webhook::check_url("ftp://93.184.216.34/hook").is_err());
+        assert!(Webhook::check_url("http://127.0.0.1:8080/hook").is_err());
+        assert!(Webhook::check_url("https://[::1]/hook").is_err());
+        assert!(Webhook::check_url("http://169.254.169.254/latest").is_err());
+        assert!(Webhook::check_url("https://user@93.184.216.34/hook").is_err());
+        assert!(Webhook::check_url("https://93.184.216.34/hook?x=1").is_ok());
+        assert!(Webhook::check_url("HTTP://93.184.216.34:8080").is_ok());
+    }
+}
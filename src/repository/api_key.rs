@@ -0,0 +1,62 @@
+use diesel::prelude::*;
+
+use crate::models::api_key::{ApiKey, NewApiKey};
+
+pub fn create_api_key(conn: &mut SqliteConnection, api_key: &NewApiKey) -> QueryResult<ApiKey> {
+    use crate::schema::api_keys;
+
+    diesel::insert_into(api_keys::table)
+        .values(api_key)
+        .returning(ApiKey::as_returning())
+        .get_result(conn)
+}
+
+pub fn get_hub_api_keys(conn: &mut SqliteConnection, hub_id: i32) -> QueryResult<Vec<ApiKey>> {
+    use crate::schema::api_keys;
+
+    api_keys::table
+        .filter(api_keys::hub_id.eq(hub_id))
+        .order(api_keys::created_at.desc())
+        .select(ApiKey::as_select())
+        .load(conn)
+}
+
+/// Finds a key that has not been revoked by its public prefix.
+pub fn get_active_api_key(
+    conn: &mut SqliteConnection,
+    prefix: &str,
+) -> QueryResult<Option<ApiKey>> {
+    use crate::schema::api_keys;
+
+    api_keys::table
+        .filter(api_keys::prefix.eq(prefix))
+        .filter(api_keys::revoked_at.is_null())
+        .select(ApiKey::as_select())
+        .first(conn)
+        .optional()
+}
+
+pub fn set_api_key_used(conn: &mut SqliteConnection, api_key_id: i32) -> QueryResult<usize> {
+    use crate::schema::api_keys;
+
+    diesel::update(api_keys::table.filter(api_keys::id.eq(api_key_id)))
+        .set(api_keys::last_used_at.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)
+}
+
+pub fn revoke_api_key(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    api_key_id: i32,
+) -> QueryResult<usize> {
+    use crate::schema::api_keys;
+
+    diesel::update(
+        api_keys::table
+            .filter(api_keys::id.eq(api_key_id))
+            .filter(api_keys::hub_id.eq(hub_id))
+            .filter(api_keys::revoked_at.is_null()),
+    )
+    .set(api_keys::revoked_at.eq(chrono::Utc::now().naive_utc()))
+    .execute(conn)
+}
@@ -0,0 +1,188 @@
+use std::collections::HashSet;
+use std::error::Error;
+
+use diesel::prelude::*;
+use serde::Serialize;
+
+use crate::models::recipient::{Recipient, normalize_email};
+use crate::repository::recipient::{get_hub_group, get_hub_recipient_by_email};
+use crate::repository::rules::RuleContext;
+
+/// Prefix of the compose entries standing for everyone a previous email was sent to.
+pub const EMAIL_ENTRY_PREFIX: &str = "email:";
+
+/// Why a selected address does not get the email.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Not an email address.
+    Invalid,
+    /// No hub recipient has the address.
+    Unknown,
+    Unsubscribed,
+}
+
+#[derive(Serialize)]
+pub struct SkippedAddress {
+    pub address: String,
+    pub reason: SkipReason,
+}
+
+/// What happened to the selected addresses, shown before and after sending.
+#[derive(Default, Serialize)]
+pub struct AudienceSummary {
+    pub num_recipients: usize,
+    /// Addresses selected more than once, by overlapping groups or by hand.
+    pub num_duplicates: usize,
+    /// Addresses selected for the email but dropped by the exclusions.
+    pub num_excluded: usize,
+    pub skipped: Vec<SkippedAddress>,
+}
+
+/// Addresses an email goes to, each once.
+#[derive(Default)]
+pub struct Audience {
+    pub addresses: Vec<String>,
+    pub summary: AudienceSummary,
+}
+
+struct AudienceBuilder {
+    audience: Audience,
+    /// Lowercased addresses already added, skipped or excluded.
+    seen: HashSet<String>,
+    excluded: HashSet<String>,
+}
+
+impl AudienceBuilder {
+    /// Adds the hub recipient with the address, if there is one, unless the address was already
+    /// seen, is excluded or cannot be sent to.
+    fn add(&mut self, address: &str, recipient: Option<Recipient>, reason: SkipReason) {
+        let key = address.to_lowercase();
+        if !self.seen.insert(key.clone()) {
+            self.audience.summary.num_duplicates += 1;
+            return;
+        }
+        if self.excluded.contains(&key) {
+            self.audience.summary.num_excluded += 1;
+            return;
+        }
+
+        let reason = match recipient {
+            Some(recipient) if recipient.unsubscribed_at.is_none() => {
+                self.audience.addresses.push(recipient.email);
+                self.audience.summary.num_recipients += 1;
+                return;
+            }
+            Some(_) => SkipReason::Unsubscribed,
+            None => reason,
+        };
+        self.audience.summary.skipped.push(SkippedAddress {
+            address: address.to_string(),
+            reason,
+        });
+    }
+}
+
+/// Expands the addresses and group ids of the compose form into the addresses to send to.
+///
+/// Every address is taken once, the excluded ones are dropped and those the email cannot be
+/// sent to are reported with the reason. Only entries that are neither an address nor a group
+/// id are an error.
+pub fn resolve_audience(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    recipients: &[String],
+    exclude: &[String],
+) -> Result<Audience, Box<dyn Error>> {
+    // Smart groups are evaluated now, so the email reaches who matches at send time
+    let mut rule_context = None;
+
+    let mut builder = AudienceBuilder {
+        audience: Audience::default(),
+        seen: HashSet::new(),
+        excluded: get_excluded_addresses(conn, hub_id, exclude, &mut rule_context)?,
+    };
+
+    for entry in recipients {
+        if entry.contains('@') {
+            match normalize_email(entry) {
+                Some(address) => {
+                    let recipient = get_hub_recipient_by_email(conn, hub_id, &address)?;
+                    builder.add(&address, recipient, SkipReason::Unknown);
+                }
+                None => builder.add(entry.trim(), None, SkipReason::Invalid),
+            }
+        } else {
+            let group_id = entry.parse::<i32>()?;
+            for member in get_group_members(conn, hub_id, group_id, &mut rule_context)? {
+                let address = member.email.clone();
+                builder.add(&address, Some(member), SkipReason::Unknown);
+            }
+        }
+    }
+
+    Ok(builder.audience)
+}
+
+/// Members of a hub group, smart groups are evaluated with the rule context loaded on first use.
+fn get_group_members(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    group_id: i32,
+    rule_context: &mut Option<RuleContext>,
+) -> QueryResult<Vec<Recipient>> {
+    use crate::schema::{groups_recipients, recipients};
+
+    let rule = get_hub_group(conn, hub_id, group_id)
+        .optional()?
+        .and_then(|group| group.rule());
+
+    match rule {
+        Some(rule) => Ok(RuleContext::get_or_load(rule_context, conn, hub_id)?
+            .members(&rule)
+            .map(|(member, _, _)| member.clone())
+            .collect()),
+        None => groups_recipients::table
+            .filter(groups_recipients::group_id.eq(group_id))
+            .inner_join(recipients::table.on(groups_recipients::recipient_id.eq(recipients::id)))
+            .filter(recipients::hub_id.eq(hub_id))
+            .select(Recipient::as_select())
+            .load(conn),
+    }
+}
+
+/// Lowercased addresses the compose exclusions stand for: addresses, group ids and
+/// `email:<id>` for the recipients of an earlier email of the hub.
+fn get_excluded_addresses(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    exclude: &[String],
+    rule_context: &mut Option<RuleContext>,
+) -> Result<HashSet<String>, Box<dyn Error>> {
+    use crate::schema::{email_recipients, emails};
+
+    let mut excluded = HashSet::new();
+
+    for entry in exclude {
+        if let Some(email_id) = entry.strip_prefix(EMAIL_ENTRY_PREFIX) {
+            let email_id = email_id.parse::<i32>()?;
+            let addresses = email_recipients::table
+                .inner_join(emails::table)
+                .filter(emails::id.eq(email_id))
+                .filter(emails::hub_id.eq(hub_id))
+                .select(email_recipients::address)
+                .load::<String>(conn)?;
+            excluded.extend(addresses.iter().map(|address| address.to_lowercase()));
+        } else if entry.contains('@') {
+            // Nobody can be sent to an invalid address, there is nothing to exclude
+            if let Some(address) = normalize_email(entry) {
+                excluded.insert(address.to_lowercase());
+            }
+        } else {
+            let members = get_group_members(conn, hub_id, entry.parse::<i32>()?, rule_context)?;
+            excluded.extend(members.iter().map(|member| member.email.to_lowercase()));
+        }
+    }
+
+    Ok(excluded)
+}
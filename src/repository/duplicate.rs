@@ -0,0 +1,294 @@
+use std::collections::{BTreeMap, HashSet};
+
+use diesel::prelude::*;
+use diesel::sql_types::{Integer, Text};
+use serde::Serialize;
+
+use crate::models::recipient::Recipient;
+use crate::repository::email::{
+    update_email_num_opened, update_email_num_replied, update_email_num_sent,
+};
+use crate::repository::recipient::{
+    RecipientWithFieldsAndGroups, delete_recipient, get_hub_all_recipients, index_recipient,
+};
+
+/// Custom fields holding a phone number, compared by name ignoring case.
+const PHONE_FIELDS: [&str; 5] = ["phone", "mobile", "телефон", "тел", "мобильный"];
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateReason {
+    /// Same address but for the case.
+    Email,
+    /// Same name and phone number.
+    NamePhone,
+}
+
+#[derive(Serialize)]
+pub struct DuplicateRecipient {
+    pub recipient: Recipient,
+    pub fields: BTreeMap<String, String>,
+    pub groups: Vec<String>,
+}
+
+/// Recipients of a hub that probably are the same person.
+#[derive(Serialize)]
+pub struct DuplicateSet {
+    pub reason: DuplicateReason,
+    /// Address or name and phone the recipients share.
+    pub key: String,
+    /// Oldest recipient first.
+    pub recipients: Vec<DuplicateRecipient>,
+}
+
+/// Groups the hub recipients by the lowercased address, then by the name and the digits of a
+/// phone field. Sets with the same recipients as an address set are not repeated.
+pub fn get_hub_duplicate_sets(
+    conn: &mut SqliteConnection,
+    hub: i32,
+) -> QueryResult<Vec<DuplicateSet>> {
+    let mut recipients = get_hub_all_recipients(conn, hub)?;
+    recipients.sort_by_key(|(recipient, _, _)| recipient.id);
+
+    let mut by_email: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    let mut by_name_phone: BTreeMap<(String, String), Vec<usize>> = BTreeMap::new();
+    for (index, (recipient, fields, _)) in recipients.iter().enumerate() {
+        by_email
+            .entry(recipient.email.to_lowercase())
+            .or_default()
+            .push(index);
+
+        let name = recipient.name.trim().to_lowercase();
+        if name.is_empty() {
+            continue;
+        }
+        let phones = fields
+            .iter()
+            .filter(|(field, _)| PHONE_FIELDS.contains(&field.trim().to_lowercase().as_str()))
+            .filter_map(|(_, value)| phone_digits(value))
+            .collect::<HashSet<_>>();
+        for phone in phones {
+            by_name_phone
+                .entry((name.clone(), phone))
+                .or_default()
+                .push(index);
+        }
+    }
+
+    let mut found = HashSet::new();
+    let candidates = by_email
+        .into_iter()
+        .map(|(email, indexes)| (DuplicateReason::Email, email, indexes))
+        .chain(by_name_phone.into_iter().map(|((_, phone), indexes)| {
+            let name = recipients[indexes[0]].0.name.trim().to_string();
+            (
+                DuplicateReason::NamePhone,
+                format!("{}, {}", name, phone),
+                indexes,
+            )
+        }));
+    let sets = candidates
+        .filter(|(_, _, indexes)| indexes.len() > 1 && found.insert(indexes.clone()))
+        .collect::<Vec<_>>();
+
+    Ok(sets
+        .into_iter()
+        .map(|(reason, key, indexes)| DuplicateSet {
+            reason,
+            key,
+            recipients: indexes
+                .into_iter()
+                .map(|index| duplicate_recipient(&recipients[index]))
+                .collect(),
+        })
+        .collect())
+}
+
+fn duplicate_recipient(
+    (recipient, fields, groups): &RecipientWithFieldsAndGroups,
+) -> DuplicateRecipient {
+    let mut groups = groups
+        .iter()
+        .map(|group| group.name.clone())
+        .collect::<Vec<_>>();
+    groups.sort();
+
+    DuplicateRecipient {
+        recipient: recipient.clone(),
+        fields: fields
+            .iter()
+            .map(|(field, value)| (field.clone(), value.clone()))
+            .collect(),
+        groups,
+    }
+}
+
+/// Last ten digits of a phone number, so that `+7` and `8` prefixes compare equal.
+fn phone_digits(value: &str) -> Option<String> {
+    let digits = value
+        .chars()
+        .filter(|c| c.is_ascii_digit())
+        .collect::<Vec<_>>();
+    if digits.len() < 7 {
+        return None;
+    }
+
+    Some(digits[digits.len().saturating_sub(10)..].iter().collect())
+}
+
+/// Combines the recipients into `keep` in one transaction and deletes the others.
+///
+/// The kept recipient gets the groups of all of them and the fields it does not have, and stays
+/// unsubscribed if any of them was. Sent emails addressed to the others are moved to its address,
+/// a campaign that reached several of them keeps one delivery with the flags of all.
+pub fn merge_recipients(
+    conn: &mut SqliteConnection,
+    hub: i32,
+    keep: i32,
+    remove: &[i32],
+) -> QueryResult<usize> {
+    use crate::schema::recipients;
+
+    conn.transaction(|conn| {
+        let keeper = recipients::table
+            .filter(recipients::id.eq(keep))
+            .filter(recipients::hub_id.eq(hub))
+            .select(Recipient::as_select())
+            .first(conn)?;
+        let losers = recipients::table
+            .filter(recipients::id.eq_any(remove))
+            .filter(recipients::id.ne(keep))
+            .filter(recipients::hub_id.eq(hub))
+            .select(Recipient::as_select())
+            .load(conn)?;
+
+        let mut unsubscribed_at = keeper.unsubscribed_at;
+        let mut merged_emails = HashSet::new();
+        for loser in &losers {
+            diesel::sql_query(
+                "INSERT OR IGNORE INTO groups_recipients (group_id, recipient_id) \
+                 SELECT group_id, ? FROM groups_recipients WHERE recipient_id = ?",
+            )
+            .bind::<Integer, _>(keeper.id)
+            .bind::<Integer, _>(loser.id)
+            .execute(conn)?;
+            diesel::sql_query(
+                "INSERT OR IGNORE INTO recipient_fields (recipient_id, field, value) \
+                 SELECT ?, field, value FROM recipient_fields WHERE recipient_id = ?",
+            )
+            .bind::<Integer, _>(keeper.id)
+            .bind::<Integer, _>(loser.id)
+            .execute(conn)?;
+
+            unsubscribed_at = match (unsubscribed_at, loser.unsubscribed_at) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+
+            if loser.email != keeper.email {
+                merged_emails.extend(move_email_history(conn, hub, &loser.email, &keeper.email)?);
+            }
+
+            delete_recipient(conn, hub, loser.id)?;
+        }
+
+        diesel::update(recipients::table.filter(recipients::id.eq(keeper.id)))
+            .set(recipients::unsubscribed_at.eq(unsubscribed_at))
+            .execute(conn)?;
+
+        for email_id in merged_emails {
+            update_email_num_sent(conn, email_id)?;
+            update_email_num_opened(conn, email_id)?;
+            update_email_num_replied(conn, email_id)?;
+        }
+
+        index_recipient(conn, keeper.id)?;
+
+        Ok(losers.len())
+    })
+}
+
+#[derive(QueryableByName)]
+struct EmailId {
+    #[diesel(sql_type = Integer)]
+    email_id: i32,
+}
+
+/// Readdresses the hub deliveries from `from` to `to`, returns the emails sent to both where
+/// the deliveries were merged into one.
+fn move_email_history(
+    conn: &mut SqliteConnection,
+    hub: i32,
+    from: &str,
+    to: &str,
+) -> QueryResult<Vec<i32>> {
+    const BOTH: &str = "k.email_id = l.email_id AND k.address = ? AND l.address = ? \
+                        AND l.email_id IN (SELECT id FROM emails WHERE hub_id = ?)";
+
+    let merged = diesel::sql_query(format!(
+        "SELECT l.email_id FROM email_recipients k, email_recipients l WHERE {}",
+        BOTH
+    ))
+    .bind::<Text, _>(to)
+    .bind::<Text, _>(from)
+    .bind::<Integer, _>(hub)
+    .load::<EmailId>(conn)?
+    .into_iter()
+    .map(|row| row.email_id)
+    .collect::<Vec<_>>();
+
+    if !merged.is_empty() {
+        // MIN() of SQLite returns NULL when any argument is NULL
+        diesel::sql_query(format!(
+            "UPDATE email_recipients AS k SET \
+             is_sent = k.is_sent OR l.is_sent, \
+             opened = k.opened OR l.opened, \
+             replied = k.replied OR l.replied, \
+             auto_replied = k.auto_replied OR l.auto_replied, \
+             unsubscribed = k.unsubscribed OR l.unsubscribed, \
+             sent_at = COALESCE(MIN(k.sent_at, l.sent_at), k.sent_at, l.sent_at), \
+             opened_at = COALESCE(MIN(k.opened_at, l.opened_at), k.opened_at, l.opened_at), \
+             replied_at = COALESCE(MIN(k.replied_at, l.replied_at), k.replied_at, l.replied_at), \
+             clicked_at = COALESCE(MIN(k.clicked_at, l.clicked_at), k.clicked_at, l.clicked_at), \
+             updated_at = MAX(k.updated_at, l.updated_at) \
+             FROM email_recipients AS l WHERE {}",
+            BOTH
+        ))
+        .bind::<Text, _>(to)
+        .bind::<Text, _>(from)
+        .bind::<Integer, _>(hub)
+        .execute(conn)?;
+
+        diesel::sql_query(format!(
+            "UPDATE email_replies SET email_recipient_id = k.id \
+             FROM email_recipients AS k, email_recipients AS l \
+             WHERE email_replies.email_recipient_id = l.id AND {}",
+            BOTH
+        ))
+        .bind::<Text, _>(to)
+        .bind::<Text, _>(from)
+        .bind::<Integer, _>(hub)
+        .execute(conn)?;
+
+        diesel::sql_query(format!(
+            "DELETE FROM email_recipients WHERE id IN \
+             (SELECT l.id FROM email_recipients k, email_recipients l WHERE {})",
+            BOTH
+        ))
+        .bind::<Text, _>(to)
+        .bind::<Text, _>(from)
+        .bind::<Integer, _>(hub)
+        .execute(conn)?;
+    }
+
+    diesel::sql_query(
+        "UPDATE email_recipients SET address = ? \
+         WHERE address = ? AND email_id IN (SELECT id FROM emails WHERE hub_id = ?)",
+    )
+    .bind::<Text, _>(to)
+    .bind::<Text, _>(from)
+    .bind::<Integer, _>(hub)
+    .execute(conn)?;
+
+    Ok(merged)
+}
@@ -1,37 +1,135 @@
+use std::collections::HashMap;
 use std::error::Error;
 
 use diesel::prelude::*;
+use diesel::sqlite::Sqlite;
 
 use crate::models::{
-    email::{Email, EmailRecipient, NewEmail, NewEmailRecipient},
-    recipient::Recipient,
+    email::{
+        Email, EmailRecipient, EmailReply, EmailReplyAttachment, EmailSummary, NewEmail,
+        NewEmailRecipient, NewEmailReply, NewEmailReplyAttachment,
+    },
+    recipient::{Recipient, RecipientField},
 };
+use crate::repository::audience::{AudienceSummary, resolve_audience};
+
+pub type EmailReplyWithAttachments = (EmailReply, Vec<EmailReplyAttachment>);
+/// Campaign recipient with the hub recipient of the address, if it still exists, and its fields.
+pub type EmailRecipientWithFields = (EmailRecipient, Option<Recipient>, HashMap<String, String>);
+
+/// Campaign state the list can be narrowed to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EmailStatusFilter {
+    Queued,
+    Sent,
+    Opened,
+    Replied,
+}
 
-pub fn get_hub_all_emails_with_recipients(
-    conn: &mut SqliteConnection,
+impl EmailStatusFilter {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "queued" => Some(Self::Queued),
+            "sent" => Some(Self::Sent),
+            "opened" => Some(Self::Opened),
+            "replied" => Some(Self::Replied),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct EmailFilter<'a> {
+    /// Inclusive lower bound of `created_at`.
+    pub since: Option<chrono::NaiveDateTime>,
+    /// Exclusive upper bound of `created_at`.
+    pub until: Option<chrono::NaiveDateTime>,
+    pub status: Option<EmailStatusFilter>,
+    /// Text the subject contains.
+    pub subject: Option<&'a str>,
+}
+
+fn filtered_hub_emails<'a>(
     hub_id: i32,
-) -> QueryResult<Vec<(Email, Vec<EmailRecipient>)>> {
+    filter: &'a EmailFilter,
+) -> crate::schema::emails::BoxedQuery<'a, Sqlite> {
     use crate::schema::emails;
 
-    // Read all emails for a user sorted by timestamp
-    let all_emails: Vec<Email> = emails::table
+    let mut query = emails::table
         .filter(emails::hub_id.eq(hub_id))
-        .order(emails::created_at.desc())
-        .select(Email::as_select()) // Ensure Diesel knows we're selecting the full Email struct
-        .load(conn)?;
+        .filter(emails::is_transactional.eq(false))
+        .into_boxed();
 
-    // Load all recipients belonging to the fetched emails
-    let email_recipients: Vec<EmailRecipient> = EmailRecipient::belonging_to(&all_emails)
-        .select(EmailRecipient::as_select()) // Ensure Diesel knows we're selecting the full EmailRecipient struct
+    if let Some(since) = filter.since {
+        query = query.filter(emails::created_at.ge(since));
+    }
+    if let Some(until) = filter.until {
+        query = query.filter(emails::created_at.lt(until));
+    }
+    query = match filter.status {
+        Some(EmailStatusFilter::Queued) => query.filter(emails::is_sent.eq(false)),
+        Some(EmailStatusFilter::Sent) => query.filter(emails::is_sent.eq(true)),
+        Some(EmailStatusFilter::Opened) => query.filter(emails::num_opened.gt(0)),
+        Some(EmailStatusFilter::Replied) => query.filter(emails::num_replied.gt(0)),
+        None => query,
+    };
+    if let Some(subject) = filter.subject {
+        let pattern = format!(
+            "%{}%",
+            subject
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        query = query.filter(emails::subject.like(pattern).escape('\\'));
+    }
+
+    query
+}
+
+/// One page of the hub campaigns with their recipient counts, and the number of matching campaigns.
+pub fn get_hub_email_summaries(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    filter: &EmailFilter,
+    page: i64,
+    per_page: i64,
+) -> QueryResult<(Vec<(EmailSummary, i64)>, i64)> {
+    use crate::schema::email_recipients;
+    use diesel::dsl::count_star;
+
+    let total = filtered_hub_emails(hub_id, filter)
+        .count()
+        .get_result::<i64>(conn)?;
+
+    let emails = filtered_hub_emails(hub_id, filter)
+        .order((
+            crate::schema::emails::created_at.desc(),
+            crate::schema::emails::id.desc(),
+        ))
+        .offset((page - 1).max(0) * per_page)
+        .limit(per_page)
+        .select(EmailSummary::as_select())
         .load(conn)?;
 
-    // Group recipients by email and return
-    Ok(email_recipients
-        .grouped_by(&all_emails)
+    let counts = email_recipients::table
+        .filter(email_recipients::email_id.eq_any(emails.iter().map(|email| email.id)))
+        .group_by(email_recipients::email_id)
+        .select((email_recipients::email_id, count_star()))
+        .load::<(i32, i64)>(conn)?;
+
+    let emails = emails
         .into_iter()
-        .zip(all_emails)
-        .map(|(recipients, email)| (email, recipients))
-        .collect())
+        .map(|email| {
+            let count = counts
+                .iter()
+                .find(|(email_id, _)| *email_id == email.id)
+                .map_or(0, |(_, count)| *count);
+            (email, count)
+        })
+        .collect();
+
+    Ok((emails, total))
 }
 
 fn create_email_recipient(
@@ -49,6 +147,7 @@ fn create_email_recipient(
         updated_at,
         is_sent: false,
         replied: false,
+        auto_replied: false,
     };
 
     diesel::insert_into(email_recipients::table)
@@ -61,87 +160,130 @@ fn create_email_recipient(
         .first(conn)
 }
 
+/// Creates a campaign to the resolved audience of `recipients` and `exclude`, both given as in
+/// the send form. Fails when nobody is left to send to.
+#[allow(clippy::too_many_arguments)]
 pub fn create_email(
     conn: &mut SqliteConnection,
     subject: Option<&str>,
     message: &str,
-    recipients: &Vec<String>,
+    recipients: &[String],
+    exclude: &[String],
     attachment: Option<&[u8]>,
     attachment_name: Option<&str>,
     attachment_mime: Option<&str>,
     hub_id: i32,
-) -> Result<Email, Box<dyn Error>> {
+) -> Result<(Email, AudienceSummary), Box<dyn Error>> {
     use crate::schema::emails;
-    use crate::schema::groups_recipients;
-    use crate::schema::recipients;
 
     let created_at = chrono::Utc::now().naive_utc();
 
-    let new_email = NewEmail {
-        hub_id,
-        message,
-        created_at: &created_at,
-        is_sent: false,
-        subject,
-        attachment,
-        attachment_name,
-        attachment_mime,
-    };
-
-    diesel::insert_into(emails::table)
-        .values(&new_email)
-        .execute(conn)?;
+    // A failing recipient must not leave a half-created email behind
+    conn.transaction(|conn| {
+        let audience = resolve_audience(conn, hub_id, recipients, exclude)?;
+        if audience.addresses.is_empty() {
+            return Err("no recipients left to send to".into());
+        }
 
-    let email: Email = emails::table
-        .filter(emails::hub_id.eq(hub_id))
-        .filter(emails::created_at.eq(created_at))
-        .filter(emails::message.eq(&new_email.message))
-        .order(emails::created_at.desc())
-        .first(conn)?;
-
-    for recipient in recipients {
-        // if recipient is an email and exists in the database create a new EmailRecipient
-        // if recipient is not an email but a group id then fetch the group and create a new EmailRecipient for each member
-        if recipient.contains('@') {
-            let recipient = recipient.trim();
-            let recipient: Recipient = recipients::table
-                .filter(recipients::email.eq(recipient))
-                .filter(recipients::unsubscribed_at.is_null())
-                .select(Recipient::as_select())
-                .first(conn)?;
-
-            create_email_recipient(conn, email.id, &recipient.email, &created_at)?;
-        } else {
-            let group_id = recipient.parse::<i32>()?;
-
-            let group_members: Vec<Recipient> = groups_recipients::table
-                .filter(groups_recipients::group_id.eq(group_id))
-                .inner_join(
-                    recipients::table.on(groups_recipients::recipient_id.eq(recipients::id)),
-                )
-                .select(Recipient::as_select())
-                .load(conn)?;
-
-            for member in group_members {
-                create_email_recipient(conn, email.id, &member.email, &created_at)?;
-            }
+        let new_email = NewEmail {
+            hub_id,
+            message,
+            created_at: &created_at,
+            is_sent: false,
+            subject,
+            attachment,
+            attachment_name,
+            attachment_mime,
+            is_transactional: false,
+        };
+
+        diesel::insert_into(emails::table)
+            .values(&new_email)
+            .execute(conn)?;
+
+        let email: Email = emails::table
+            .filter(emails::hub_id.eq(hub_id))
+            .filter(emails::created_at.eq(created_at))
+            .filter(emails::message.eq(&new_email.message))
+            .order(emails::created_at.desc())
+            .first(conn)?;
+
+        for address in &audience.addresses {
+            create_email_recipient(conn, email.id, address, &created_at)?;
         }
-    }
 
-    Ok(email)
+        Ok((email, audience.summary))
+    })
 }
 
+/// Creates a single message to any address, unsubscribed recipients included.
+pub fn create_transactional_email(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    subject: &str,
+    message: &str,
+    address: &str,
+) -> QueryResult<(Email, EmailRecipient)> {
+    use crate::schema::emails;
+
+    let created_at = chrono::Utc::now().naive_utc();
+
+    conn.transaction(|conn| {
+        let new_email = NewEmail {
+            hub_id,
+            message,
+            created_at: &created_at,
+            is_sent: false,
+            subject: Some(subject),
+            attachment: None,
+            attachment_name: None,
+            attachment_mime: None,
+            is_transactional: true,
+        };
+
+        let email = diesel::insert_into(emails::table)
+            .values(&new_email)
+            .returning(Email::as_returning())
+            .get_result(conn)?;
+
+        let recipient = create_email_recipient(conn, email.id, address, &created_at)?;
+
+        Ok((email, recipient))
+    })
+}
+
+/// Removes a campaign with its recipients and replies, transactional messages are not found.
 pub fn remove_email(conn: &mut SqliteConnection, email_id: i32, hub_id: i32) -> QueryResult<usize> {
-    use crate::schema::{email_recipients, emails};
+    use crate::schema::{email_recipients, email_replies, email_reply_attachments, emails};
 
-    diesel::delete(
-        emails::table
+    conn.transaction(|conn| {
+        let email_id: i32 = emails::table
             .filter(emails::id.eq(email_id))
-            .filter(emails::hub_id.eq(hub_id)),
-    )
-    .execute(conn)?;
-    diesel::delete(email_recipients::table.filter(email_recipients::email_id.eq(email_id)))
-        .execute(conn)
+            .filter(emails::hub_id.eq(hub_id))
+            .filter(emails::is_transactional.eq(false))
+            .select(emails::id)
+            .first(conn)?;
+
+        let recipient_ids = email_recipients::table
+            .filter(email_recipients::email_id.eq(email_id))
+            .select(email_recipients::id);
+        let reply_ids = email_replies::table
+            .filter(email_replies::email_recipient_id.eq_any(recipient_ids))
+            .select(email_replies::id);
+
+        diesel::delete(
+            email_reply_attachments::table
+                .filter(email_reply_attachments::reply_id.eq_any(reply_ids)),
+        )
+        .execute(conn)?;
+        diesel::delete(
+            email_replies::table.filter(email_replies::email_recipient_id.eq_any(recipient_ids)),
+        )
+        .execute(conn)?;
+        diesel::delete(email_recipients::table.filter(email_recipients::email_id.eq(email_id)))
+            .execute(conn)?;
+        diesel::delete(emails::table.filter(emails::id.eq(email_id))).execute(conn)
+    })
 }
 
 pub fn get_email(conn: &mut SqliteConnection, email_id: i32) -> QueryResult<Email> {
@@ -161,6 +303,60 @@ pub fn get_email_recipients(
         .load(conn)
 }
 
+/// Campaign recipients in the order they were added, for exports.
+pub fn get_email_recipients_with_fields(
+    conn: &mut SqliteConnection,
+    email: &Email,
+) -> QueryResult<Vec<EmailRecipientWithFields>> {
+    use crate::schema::{email_recipients, recipient_fields, recipients};
+
+    let email_recipients = email_recipients::table
+        .filter(email_recipients::email_id.eq(email.id))
+        .order(email_recipients::id.asc())
+        .select(EmailRecipient::as_select())
+        .load(conn)?;
+
+    let addresses = email_recipients
+        .iter()
+        .map(|recipient| recipient.address.as_str())
+        .collect::<Vec<_>>();
+    let mut recipients = recipients::table
+        .filter(recipients::hub_id.eq(email.hub_id))
+        .filter(recipients::email.eq_any(&addresses))
+        .select(Recipient::as_select())
+        .load::<Recipient>(conn)?
+        .into_iter()
+        .map(|recipient| (recipient.email.clone(), recipient))
+        .collect::<HashMap<_, _>>();
+
+    let mut fields: HashMap<i32, HashMap<String, String>> = HashMap::new();
+    for field in recipient_fields::table
+        .filter(
+            recipient_fields::recipient_id
+                .eq_any(recipients.values().map(|recipient| recipient.id)),
+        )
+        .select(RecipientField::as_select())
+        .load::<RecipientField>(conn)?
+    {
+        fields
+            .entry(field.recipient_id)
+            .or_default()
+            .insert(field.field, field.value);
+    }
+
+    Ok(email_recipients
+        .into_iter()
+        .map(|email_recipient| {
+            let recipient = recipients.remove(&email_recipient.address);
+            let fields = recipient
+                .as_ref()
+                .and_then(|recipient| fields.remove(&recipient.id))
+                .unwrap_or_default();
+            (email_recipient, recipient, fields)
+        })
+        .collect())
+}
+
 pub fn set_email_sent_status(
     conn: &mut SqliteConnection,
     email_id: i32,
@@ -180,8 +376,13 @@ pub fn set_email_recipient_sent_status(
 ) -> QueryResult<usize> {
     use crate::schema::email_recipients;
 
+    let sent_at = status.then(|| chrono::Utc::now().naive_utc());
+
     diesel::update(email_recipients::table.filter(email_recipients::id.eq(recipient_id)))
-        .set(email_recipients::is_sent.eq(status))
+        .set((
+            email_recipients::is_sent.eq(status),
+            email_recipients::sent_at.eq(sent_at),
+        ))
         .execute(conn)
 }
 
@@ -192,11 +393,43 @@ pub fn set_email_recipient_opened_status(
 ) -> QueryResult<usize> {
     use crate::schema::email_recipients;
 
-    diesel::update(email_recipients::table.filter(email_recipients::id.eq(recipient_id)))
-        .set(email_recipients::opened.eq(status))
+    let target = email_recipients::table.filter(email_recipients::id.eq(recipient_id));
+
+    if !status {
+        return diesel::update(target)
+            .set((
+                email_recipients::opened.eq(false),
+                email_recipients::opened_at.eq(None::<chrono::NaiveDateTime>),
+            ))
+            .execute(conn);
+    }
+
+    // Keep the time of the first open
+    diesel::update(target.filter(email_recipients::opened_at.is_null()))
+        .set(email_recipients::opened_at.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)?;
+
+    diesel::update(target)
+        .set(email_recipients::opened.eq(true))
         .execute(conn)
 }
 
+/// Records the first click on a tracked link, a click also proves the message was opened.
+pub fn set_email_recipient_clicked_status(
+    conn: &mut SqliteConnection,
+    recipient_id: i32,
+) -> QueryResult<usize> {
+    use crate::schema::email_recipients;
+
+    let target = email_recipients::table.filter(email_recipients::id.eq(recipient_id));
+
+    diesel::update(target.filter(email_recipients::clicked_at.is_null()))
+        .set(email_recipients::clicked_at.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)?;
+
+    set_email_recipient_opened_status(conn, recipient_id, true)
+}
+
 pub fn reset_email_sent_and_opened_status(
     conn: &mut SqliteConnection,
     email_id: i32,
@@ -209,23 +442,30 @@ pub fn reset_email_sent_and_opened_status(
         .set((
             email_recipients::opened.eq(false),
             email_recipients::is_sent.eq(false),
+            email_recipients::sent_at.eq(None::<chrono::NaiveDateTime>),
+            email_recipients::opened_at.eq(None::<chrono::NaiveDateTime>),
+            email_recipients::clicked_at.eq(None::<chrono::NaiveDateTime>),
         ))
         .execute(conn)
 }
 
-pub fn get_hub_email_recipients_not_replied(
+pub fn get_hub_email_recipient_since(
     conn: &mut SqliteConnection,
     hub_id: i32,
-) -> QueryResult<Vec<EmailRecipient>> {
+    recipient_id: i32,
+    since: &chrono::NaiveDateTime,
+) -> QueryResult<Option<EmailRecipient>> {
     use crate::schema::email_recipients;
     use crate::schema::emails;
 
     email_recipients::table
         .inner_join(emails::table.on(email_recipients::email_id.eq(emails::id)))
         .filter(emails::hub_id.eq(hub_id))
-        .filter(email_recipients::replied.eq(false))
+        .filter(emails::created_at.ge(since))
+        .filter(email_recipients::id.eq(recipient_id))
         .select(EmailRecipient::as_select())
-        .load(conn)
+        .first(conn)
+        .optional()
 }
 
 pub fn set_email_recipient_replied_status(
@@ -236,7 +476,18 @@ pub fn set_email_recipient_replied_status(
     use crate::schema::email_recipients;
     use crate::schema::emails;
 
-    diesel::update(email_recipients::table.filter(email_recipients::id.eq(recipient_id)))
+    let target = email_recipients::table.filter(email_recipients::id.eq(recipient_id));
+    let now = chrono::Utc::now().naive_utc();
+
+    // Keep the time of the first reply, a reply also proves the message was opened
+    diesel::update(target.filter(email_recipients::replied_at.is_null()))
+        .set(email_recipients::replied_at.eq(now))
+        .execute(conn)?;
+    diesel::update(target.filter(email_recipients::opened_at.is_null()))
+        .set(email_recipients::opened_at.eq(now))
+        .execute(conn)?;
+
+    diesel::update(target)
         .set((
             email_recipients::replied.eq(true),
             email_recipients::is_sent.eq(true),
@@ -246,7 +497,27 @@ pub fn set_email_recipient_replied_status(
 
     diesel::update(emails::table.filter(emails::id.eq(email_id)))
         .set(emails::is_sent.eq(true))
-        .execute(conn)
+        .execute(conn)?;
+
+    update_email_num_sent(conn, email_id)
+}
+
+pub fn set_email_recipient_auto_replied_status(
+    conn: &mut SqliteConnection,
+    email_id: i32,
+    recipient_id: i32,
+) -> QueryResult<usize> {
+    use crate::schema::email_recipients;
+
+    // An auto-reply proves delivery but not that the message was read
+    diesel::update(email_recipients::table.filter(email_recipients::id.eq(recipient_id)))
+        .set((
+            email_recipients::auto_replied.eq(true),
+            email_recipients::is_sent.eq(true),
+        ))
+        .execute(conn)?;
+
+    update_email_num_sent(conn, email_id)
 }
 
 pub fn update_email_num_sent(conn: &mut SqliteConnection, email_id: i32) -> QueryResult<usize> {
@@ -307,3 +578,93 @@ pub fn get_email_recipient(
         .filter(email_recipients::id.eq(recipient_id))
         .first(conn)
 }
+
+pub fn email_reply_exists(
+    conn: &mut SqliteConnection,
+    email_recipient_id: i32,
+    message_id: Option<&str>,
+    received_at: &chrono::NaiveDateTime,
+) -> QueryResult<bool> {
+    use crate::schema::email_replies;
+
+    let query = email_replies::table
+        .filter(email_replies::email_recipient_id.eq(email_recipient_id))
+        .into_boxed();
+
+    // Fall back to the timestamp for messages that come without a Message-ID
+    let query = match message_id {
+        Some(message_id) => query.filter(email_replies::message_id.eq(message_id)),
+        None => query.filter(email_replies::received_at.eq(received_at)),
+    };
+
+    diesel::select(diesel::dsl::exists(query)).get_result(conn)
+}
+
+pub fn create_email_reply(
+    conn: &mut SqliteConnection,
+    reply: &NewEmailReply,
+    attachments: Vec<NewEmailReplyAttachment>,
+) -> QueryResult<EmailReply> {
+    use crate::schema::{email_replies, email_reply_attachments};
+
+    conn.transaction(|conn| {
+        let reply: EmailReply = diesel::insert_into(email_replies::table)
+            .values(reply)
+            .returning(EmailReply::as_returning())
+            .get_result(conn)?;
+
+        let attachments = attachments
+            .into_iter()
+            .map(|attachment| NewEmailReplyAttachment {
+                reply_id: reply.id,
+                ..attachment
+            })
+            .collect::<Vec<_>>();
+
+        diesel::insert_into(email_reply_attachments::table)
+            .values(&attachments)
+            .execute(conn)?;
+
+        Ok(reply)
+    })
+}
+
+pub fn get_email_recipient_replies(
+    conn: &mut SqliteConnection,
+    email_recipient_id: i32,
+) -> QueryResult<Vec<EmailReplyWithAttachments>> {
+    use crate::schema::email_replies;
+
+    let replies: Vec<EmailReply> = email_replies::table
+        .filter(email_replies::email_recipient_id.eq(email_recipient_id))
+        .order(email_replies::received_at.asc())
+        .select(EmailReply::as_select())
+        .load(conn)?;
+
+    let attachments: Vec<EmailReplyAttachment> = EmailReplyAttachment::belonging_to(&replies)
+        .select(EmailReplyAttachment::as_select())
+        .load(conn)?;
+
+    Ok(attachments
+        .grouped_by(&replies)
+        .into_iter()
+        .zip(replies)
+        .map(|(attachments, reply)| (reply, attachments))
+        .collect())
+}
+
+pub fn get_recipient_email_history(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    address: &str,
+) -> QueryResult<Vec<(Email, EmailRecipient)>> {
+    use crate::schema::{email_recipients, emails};
+
+    email_recipients::table
+        .inner_join(emails::table.on(email_recipients::email_id.eq(emails::id)))
+        .filter(emails::hub_id.eq(hub_id))
+        .filter(email_recipients::address.eq(address))
+        .order(emails::created_at.desc())
+        .select((Email::as_select(), EmailRecipient::as_select()))
+        .load(conn)
+}
@@ -1,11 +1,13 @@
 use diesel::prelude::*;
 
-use crate::models::hub::Hub;
+use crate::models::hub::{Hub, HubImapFolder};
 
 pub fn update_hub(conn: &mut SqliteConnection, hub: &Hub) -> QueryResult<usize> {
-    use crate::schema::hubs::dsl::hubs;
+    use crate::schema::hubs::dsl::{hubs, id};
 
-    diesel::update(hubs).set(hub).execute(conn)
+    diesel::update(hubs.filter(id.eq(hub.id)))
+        .set(hub)
+        .execute(conn)
 }
 
 pub fn get_hub(conn: &mut SqliteConnection, hub_id: i32) -> QueryResult<Hub> {
@@ -14,9 +16,52 @@ pub fn get_hub(conn: &mut SqliteConnection, hub_id: i32) -> QueryResult<Hub> {
     hubs.filter(id.eq(hub_id)).first(conn)
 }
 
-
 pub fn list_hubs(conn: &mut SqliteConnection) -> QueryResult<Vec<Hub>> {
     use crate::schema::hubs::dsl::hubs;
 
     hubs.load(conn)
-}
\ No newline at end of file
+}
+
+pub fn get_hub_imap_folder(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    folder: &str,
+) -> QueryResult<Option<HubImapFolder>> {
+    use crate::schema::hub_imap_folders;
+
+    hub_imap_folders::table
+        .filter(hub_imap_folders::hub_id.eq(hub_id))
+        .filter(hub_imap_folders::folder.eq(folder))
+        .select(HubImapFolder::as_select())
+        .first(conn)
+        .optional()
+}
+
+pub fn save_hub_imap_folder(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    folder: &str,
+    uid_validity: i64,
+    last_uid: i64,
+) -> QueryResult<usize> {
+    use crate::schema::hub_imap_folders;
+
+    let state = HubImapFolder {
+        hub_id,
+        folder: folder.to_string(),
+        uid_validity,
+        last_uid,
+        updated_at: chrono::Utc::now().naive_utc(),
+    };
+
+    diesel::insert_into(hub_imap_folders::table)
+        .values(&state)
+        .on_conflict((hub_imap_folders::hub_id, hub_imap_folders::folder))
+        .do_update()
+        .set((
+            hub_imap_folders::uid_validity.eq(state.uid_validity),
+            hub_imap_folders::last_uid.eq(state.last_uid),
+            hub_imap_folders::updated_at.eq(state.updated_at),
+        ))
+        .execute(conn)
+}
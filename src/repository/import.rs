@@ -0,0 +1,422 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use diesel::prelude::*;
+use serde::Serialize;
+
+use crate::import::{ImportMode, ImportOptions, ImportRow, ImportStatus};
+use crate::models::recipient::{
+    GroupRecipient, NewGroup, NewRecipient, NewRecipientImport, RecipientImport,
+};
+use crate::repository::recipient::{
+    get_hub_all_recipients, get_hub_recipient_by_email, index_recipient,
+    update_recipient_custom_fields,
+};
+
+/// Uploads not confirmed within this many hours are removed.
+const IMPORT_TTL_HOURS: i64 = 24;
+
+#[derive(Default, Serialize)]
+pub struct ImportSummary {
+    pub num_rows: usize,
+    pub num_new: usize,
+    pub num_updated: usize,
+    pub num_unchanged: usize,
+    pub num_invalid: usize,
+    /// Groups the import creates, by name.
+    pub new_groups: Vec<String>,
+}
+
+pub struct ImportPreview {
+    pub rows: Vec<ImportRow>,
+    pub summary: ImportSummary,
+}
+
+/// Compares the valid rows with the hub recipients and counts what the import changes.
+///
+/// The groups and fields of every row become the ones the recipient ends up with in the
+/// chosen mode, the import then writes them as they are. A row without a name keeps the name
+/// of the existing recipient and is invalid for a new one.
+pub fn preview_recipients_import(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    mut rows: Vec<ImportRow>,
+    options: &ImportOptions,
+) -> QueryResult<ImportPreview> {
+    let existing = get_hub_all_recipients(conn, hub_id)?
+        .into_iter()
+        .map(|(recipient, fields, groups)| {
+            let groups = groups
+                .into_iter()
+                .map(|group| group.name)
+                .collect::<BTreeSet<_>>();
+            (recipient.email.to_lowercase(), (recipient, fields, groups))
+        })
+        .collect::<HashMap<_, _>>();
+    let hub_groups = hub_group_ids(conn, hub_id)?;
+    let smart_groups = hub_smart_group_names(conn, hub_id)?;
+
+    let mut summary = ImportSummary {
+        num_rows: rows.len(),
+        ..Default::default()
+    };
+    let mut new_groups = BTreeSet::new();
+
+    for row in rows.iter_mut() {
+        if row.status == ImportStatus::Invalid {
+            summary.num_invalid += 1;
+            continue;
+        }
+
+        if let Some(group) = &options.assign_group
+            && !row.groups.contains(group)
+        {
+            row.groups.push(group.clone());
+        }
+
+        // Members of a smart group follow from its rule and cannot be assigned
+        if let Some(group) = row
+            .groups
+            .iter()
+            .find(|group| smart_groups.contains(*group))
+        {
+            row.errors.push(format!(
+                "Участники умной группы {} определяются условием",
+                group
+            ));
+            row.status = ImportStatus::Invalid;
+            summary.num_invalid += 1;
+            continue;
+        }
+
+        row.status = match existing.get(&row.email.to_lowercase()) {
+            None if row.name.is_empty() => {
+                row.errors.push("Не указано имя".to_string());
+                row.status = ImportStatus::Invalid;
+                summary.num_invalid += 1;
+                continue;
+            }
+            None => ImportStatus::New,
+            Some(_) if options.mode == ImportMode::NewOnly => ImportStatus::Unchanged,
+            Some((recipient, fields, groups)) => {
+                if row.name.is_empty() {
+                    row.name = recipient.name.clone();
+                }
+
+                if options.mode == ImportMode::Merge {
+                    let added = std::mem::take(&mut row.groups)
+                        .into_iter()
+                        .filter(|group| !groups.contains(group));
+                    row.groups = groups.iter().cloned().chain(added).collect();
+
+                    let mut merged = fields.clone();
+                    merged.extend(std::mem::take(&mut row.fields));
+                    row.fields = merged;
+                }
+
+                let row_groups = row.groups.iter().cloned().collect::<BTreeSet<_>>();
+                match recipient.name == row.name
+                    && recipient.email == row.email
+                    && *fields == row.fields
+                    && *groups == row_groups
+                {
+                    true => ImportStatus::Unchanged,
+                    false => ImportStatus::Updated,
+                }
+            }
+        };
+
+        match row.status {
+            ImportStatus::New => summary.num_new += 1,
+            ImportStatus::Updated => summary.num_updated += 1,
+            _ => {
+                summary.num_unchanged += 1;
+                continue;
+            }
+        }
+
+        new_groups.extend(
+            row.groups
+                .iter()
+                .filter(|group| !hub_groups.contains_key(group.as_str()))
+                .cloned(),
+        );
+    }
+
+    summary.new_groups = new_groups.into_iter().collect();
+
+    Ok(ImportPreview { rows, summary })
+}
+
+/// Applies the valid rows in one transaction, nothing is written when any of them fails.
+pub fn import_recipients(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    rows: Vec<ImportRow>,
+    options: &ImportOptions,
+) -> QueryResult<ImportSummary> {
+    conn.transaction(|conn| {
+        let preview = preview_recipients_import(conn, hub_id, rows, options)?;
+        let mut hub_groups = hub_group_ids(conn, hub_id)?;
+
+        for row in preview.rows {
+            if matches!(row.status, ImportStatus::New | ImportStatus::Updated) {
+                write_import_row(conn, hub_id, &mut hub_groups, row)?;
+            }
+        }
+
+        Ok(preview.summary)
+    })
+}
+
+fn hub_group_ids(conn: &mut SqliteConnection, hub_id: i32) -> QueryResult<HashMap<String, i32>> {
+    use crate::schema::groups;
+
+    Ok(groups::table
+        .filter(groups::hub_id.eq(hub_id))
+        .select((groups::name, groups::id))
+        .load::<(String, i32)>(conn)?
+        .into_iter()
+        .collect())
+}
+
+fn hub_smart_group_names(conn: &mut SqliteConnection, hub_id: i32) -> QueryResult<HashSet<String>> {
+    use crate::schema::groups;
+
+    Ok(groups::table
+        .filter(groups::hub_id.eq(hub_id))
+        .filter(groups::rules.is_not_null())
+        .select(groups::name)
+        .load::<String>(conn)?
+        .into_iter()
+        .collect())
+}
+
+/// Gives the recipient the name, groups and fields of the row.
+fn write_import_row(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    hub_groups: &mut HashMap<String, i32>,
+    row: ImportRow,
+) -> QueryResult<()> {
+    use crate::schema::{groups, groups_recipients, recipients};
+
+    let recipient_id = match get_hub_recipient_by_email(conn, hub_id, &row.email)? {
+        Some(recipient) => {
+            let name = match row.name.is_empty() {
+                true => &recipient.name,
+                false => &row.name,
+            };
+            if recipient.name != *name || recipient.email != row.email {
+                diesel::update(recipients::table.filter(recipients::id.eq(recipient.id)))
+                    .set((recipients::name.eq(name), recipients::email.eq(&row.email)))
+                    .execute(conn)?;
+            }
+            recipient.id
+        }
+        None => diesel::insert_into(recipients::table)
+            .values(&NewRecipient {
+                name: &row.name,
+                email: &row.email,
+                hub_id,
+            })
+            .returning(recipients::id)
+            .get_result::<i32>(conn)?,
+    };
+
+    diesel::delete(
+        groups_recipients::table.filter(groups_recipients::recipient_id.eq(recipient_id)),
+    )
+    .execute(conn)?;
+
+    let mut assigned = HashSet::new();
+    for group_name in &row.groups {
+        let group_id = match hub_groups.get(group_name) {
+            Some(group_id) => *group_id,
+            None => {
+                let group_id = diesel::insert_into(groups::table)
+                    .values(&NewGroup {
+                        name: group_name,
+                        hub_id,
+                    })
+                    .returning(groups::id)
+                    .get_result::<i32>(conn)?;
+                hub_groups.insert(group_name.clone(), group_id);
+                group_id
+            }
+        };
+
+        if assigned.insert(group_id) {
+            diesel::insert_into(groups_recipients::table)
+                .values(&GroupRecipient {
+                    group_id,
+                    recipient_id,
+                })
+                .execute(conn)?;
+        }
+    }
+
+    update_recipient_custom_fields(conn, recipient_id, row.fields)?;
+
+    index_recipient(conn, recipient_id)
+}
+
+/// Keeps the uploaded file until the preview is confirmed, dropping stale uploads of the hub.
+pub fn create_recipient_import(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    file_name: &str,
+    content: &[u8],
+    options: &ImportOptions,
+) -> QueryResult<RecipientImport> {
+    use crate::schema::recipient_imports;
+
+    let now = chrono::Utc::now().naive_utc();
+
+    diesel::delete(
+        recipient_imports::table
+            .filter(recipient_imports::hub_id.eq(hub_id))
+            .filter(
+                recipient_imports::created_at.lt(now - chrono::Duration::hours(IMPORT_TTL_HOURS)),
+            ),
+    )
+    .execute(conn)?;
+
+    diesel::insert_into(recipient_imports::table)
+        .values(&NewRecipientImport {
+            hub_id,
+            file_name,
+            content,
+            created_at: now,
+            encoding: options.encoding.map(|encoding| encoding.as_str()),
+            delimiter: options.delimiter.map(|delimiter| delimiter.as_str()),
+            mode: Some(options.mode.as_str()),
+            assign_group: options.assign_group.as_deref(),
+        })
+        .returning(RecipientImport::as_returning())
+        .get_result(conn)
+}
+
+pub fn get_recipient_import(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    import_id: i32,
+) -> QueryResult<RecipientImport> {
+    use crate::schema::recipient_imports;
+
+    recipient_imports::table
+        .filter(recipient_imports::id.eq(import_id))
+        .filter(recipient_imports::hub_id.eq(hub_id))
+        .select(RecipientImport::as_select())
+        .first(conn)
+}
+
+/// Replaces the reading settings of the upload, only the chosen column targets are kept.
+pub fn update_recipient_import_options(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    import_id: i32,
+    options: &ImportOptions,
+) -> QueryResult<usize> {
+    use crate::schema::recipient_imports;
+
+    let column_map = serde_json::to_string(&options.columns).ok();
+
+    diesel::update(
+        recipient_imports::table
+            .filter(recipient_imports::id.eq(import_id))
+            .filter(recipient_imports::hub_id.eq(hub_id)),
+    )
+    .set((
+        recipient_imports::encoding.eq(options.encoding.map(|encoding| encoding.as_str())),
+        recipient_imports::delimiter.eq(options.delimiter.map(|delimiter| delimiter.as_str())),
+        recipient_imports::sheet.eq(options.sheet.as_deref()),
+        recipient_imports::mode.eq(options.mode.as_str()),
+        recipient_imports::assign_group.eq(options.assign_group.as_deref()),
+        recipient_imports::column_map.eq(column_map),
+    ))
+    .execute(conn)
+}
+
+pub fn delete_recipient_import(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    import_id: i32,
+) -> QueryResult<usize> {
+    use crate::schema::recipient_imports;
+
+    diesel::delete(
+        recipient_imports::table
+            .filter(recipient_imports::id.eq(import_id))
+            .filter(recipient_imports::hub_id.eq(hub_id)),
+    )
+    .execute(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::connection::SimpleConnection;
+
+    use super::*;
+    use crate::import::parse_recipients_file;
+    use crate::repository::recipient::{assign_recipient_to_group, create_group, create_recipient};
+
+    /// In-memory database with every migration applied.
+    fn test_connection() -> SqliteConnection {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations");
+        let mut migrations = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_dir())
+            .collect::<Vec<_>>();
+        migrations.sort();
+        for migration in migrations {
+            let sql = std::fs::read_to_string(migration.join("up.sql")).unwrap();
+            conn.batch_execute(&sql).unwrap();
+        }
+        conn.batch_execute("INSERT INTO hubs (id) VALUES (1)")
+            .unwrap();
+        conn
+    }
+
+    #[test]
+    fn merge_without_names() {
+        let mut conn = test_connection();
+        let ivan = create_recipient(&mut conn, 1, "Иван Петров", "ivan@example.com").unwrap();
+        create_recipient(&mut conn, 1, "Анна Смирнова", "anna@example.com").unwrap();
+        let group = create_group(&mut conn, 1, "Клиенты").unwrap();
+        assign_recipient_to_group(&mut conn, ivan.id, group.id).unwrap();
+
+        let file = "email,groups\nivan@example.com,VIP\nanna@example.com,\nnew@example.com,VIP\n";
+        let options = ImportOptions::default();
+        let rows = parse_recipients_file(file.as_bytes(), &options)
+            .unwrap()
+            .rows;
+        let summary = import_recipients(&mut conn, 1, rows, &options).unwrap();
+
+        assert_eq!(summary.num_updated, 1);
+        assert_eq!(summary.num_unchanged, 1);
+        // A new recipient still needs a name
+        assert_eq!(summary.num_invalid, 1);
+        assert_eq!(summary.num_new, 0);
+
+        let recipients = get_hub_all_recipients(&mut conn, 1).unwrap();
+        assert_eq!(recipients.len(), 2);
+        for (recipient, _, groups) in recipients {
+            let groups = groups
+                .into_iter()
+                .map(|group| group.name)
+                .collect::<Vec<_>>();
+            match recipient.email.as_str() {
+                "ivan@example.com" => {
+                    assert_eq!(recipient.name, "Иван Петров");
+                    assert_eq!(groups, ["Клиенты", "VIP"]);
+                }
+                _ => {
+                    assert_eq!(recipient.name, "Анна Смирнова");
+                    assert!(groups.is_empty());
+                }
+            }
+        }
+    }
+}
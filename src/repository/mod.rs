@@ -1,3 +1,11 @@
+pub mod api_key;
+pub mod audience;
+pub mod duplicate;
 pub mod email;
 pub mod hub;
+pub mod import;
 pub mod recipient;
+pub mod rules;
+pub mod stats;
+pub mod template;
+pub mod webhook;
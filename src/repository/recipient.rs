@@ -1,18 +1,27 @@
 use std::collections::{HashMap, HashSet};
 
+use diesel::dsl::sql;
 use diesel::prelude::*;
 use diesel::result::Error;
-use serde::Deserialize;
+use diesel::sql_types::{Bool, Integer, Text};
+use diesel::sqlite::Sqlite;
 
 use crate::models::recipient::{
-    Group, GroupRecipient, NewGroup, NewRecipient, Recipient, RecipientField,
+    DuplicateEmail, Group, GroupRecipient, NewGroup, NewRecipient, Recipient, RecipientField,
 };
+use crate::models::webhook::WebhookEvent;
+use crate::repository::rules::RuleContext;
+use crate::repository::webhook::queue_recipient_event;
+
+define_sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
+pub type RecipientWithFieldsAndGroups = (Recipient, HashMap<String, String>, Vec<Group>);
 
 pub fn get_hub_all_recipients(
     conn: &mut SqliteConnection,
     hub: i32,
-) -> QueryResult<Vec<(Recipient, HashMap<String, String>, Vec<Group>)>> {
-    use crate::schema::{groups, recipients};
+) -> QueryResult<Vec<RecipientWithFieldsAndGroups>> {
+    use crate::schema::recipients;
 
     // Load recipients
     let recipients = recipients::table
@@ -21,6 +30,15 @@ pub fn get_hub_all_recipients(
         .order(recipients::name.desc())
         .load::<Recipient>(conn)?;
 
+    with_fields_and_groups(conn, recipients)
+}
+
+fn with_fields_and_groups(
+    conn: &mut SqliteConnection,
+    recipients: Vec<Recipient>,
+) -> QueryResult<Vec<RecipientWithFieldsAndGroups>> {
+    use crate::schema::groups;
+
     // Load recipient fields (key-value pairs)
     let recipient_fields = RecipientField::belonging_to(&recipients)
         .select(RecipientField::as_select())
@@ -50,7 +68,7 @@ pub fn get_hub_all_recipients(
         if let Some(group) = group_map.get(&gr.group_id) {
             recipient_groups
                 .entry(gr.recipient_id)
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push(group.clone());
         }
     }
@@ -58,17 +76,256 @@ pub fn get_hub_all_recipients(
     // Combine everything into the expected structure
     Ok(recipients
         .into_iter()
-        .zip(recipient_fields.into_iter())
+        .zip(recipient_fields)
         .map(|(recipient, fields)| {
             let field_map = fields.into_iter().map(|rf| (rf.field, rf.value)).collect();
-            let groups = recipient_groups
-                .remove(&recipient.id)
-                .unwrap_or_else(Vec::new);
+            let groups = recipient_groups.remove(&recipient.id).unwrap_or_default();
             (recipient, field_map, groups)
         })
         .collect())
 }
 
+/// Rewrites the search index row of the recipient, removes it when the recipient is gone.
+pub(crate) fn index_recipient(conn: &mut SqliteConnection, recipient_id: i32) -> QueryResult<()> {
+    diesel::sql_query("DELETE FROM recipients_fts WHERE rowid = ?")
+        .bind::<Integer, _>(recipient_id)
+        .execute(conn)?;
+
+    diesel::sql_query(
+        "INSERT INTO recipients_fts (rowid, name, email, fields) \
+         SELECT id, name, email, COALESCE(( \
+             SELECT group_concat(value, ' ') FROM recipient_fields \
+             WHERE recipient_id = recipients.id \
+         ), '') \
+         FROM recipients WHERE id = ?",
+    )
+    .bind::<Integer, _>(recipient_id)
+    .execute(conn)?;
+
+    Ok(())
+}
+
+/// Turns user input into an FTS5 query matching every word by prefix.
+fn fts_match_query(query: &str) -> Option<String> {
+    let terms = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| format!("\"{}\"*", term))
+        .collect::<Vec<_>>();
+
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+/// Order of recipient search results.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecipientSort {
+    #[default]
+    NameAsc,
+    NameDesc,
+    EmailAsc,
+    EmailDesc,
+    CreatedAsc,
+    CreatedDesc,
+}
+
+impl RecipientSort {
+    pub const ALL: [RecipientSort; 6] = [
+        RecipientSort::NameAsc,
+        RecipientSort::NameDesc,
+        RecipientSort::EmailAsc,
+        RecipientSort::EmailDesc,
+        RecipientSort::CreatedAsc,
+        RecipientSort::CreatedDesc,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecipientSort::NameAsc => "name",
+            RecipientSort::NameDesc => "-name",
+            RecipientSort::EmailAsc => "email",
+            RecipientSort::EmailDesc => "-email",
+            RecipientSort::CreatedAsc => "created",
+            RecipientSort::CreatedDesc => "-created",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|sort| sort.as_str() == value)
+    }
+}
+
+#[derive(Default)]
+pub struct RecipientSearch<'a> {
+    /// Words matched by prefix against name, email and custom field values.
+    pub query: Option<&'a str>,
+    /// Only members of the group, a smart group by its rule.
+    pub group: Option<i32>,
+    /// Only subscribed (`true`) or unsubscribed (`false`) recipients.
+    pub active: Option<bool>,
+    pub sort: RecipientSort,
+}
+
+/// Members of the searched group when it is a smart group, their ids are not stored.
+fn smart_group_member_ids(
+    conn: &mut SqliteConnection,
+    hub: i32,
+    search: &RecipientSearch,
+) -> QueryResult<Option<Vec<i32>>> {
+    let Some(group) = search.group else {
+        return Ok(None);
+    };
+    let Some(rule) = get_hub_group(conn, hub, group)
+        .optional()?
+        .and_then(|group| group.rule())
+    else {
+        return Ok(None);
+    };
+
+    Ok(Some(
+        RuleContext::load(conn, hub)?
+            .members(&rule)
+            .map(|(recipient, _, _)| recipient.id)
+            .collect(),
+    ))
+}
+
+fn filtered_hub_recipients(
+    hub: i32,
+    search: &RecipientSearch,
+    smart_group_members: Option<&[i32]>,
+) -> crate::schema::recipients::BoxedQuery<'static, Sqlite> {
+    use crate::schema::{groups_recipients, recipients};
+
+    let mut query = recipients::table
+        .filter(recipients::hub_id.eq(hub))
+        .into_boxed();
+
+    if let Some(fts_query) = search.query.and_then(fts_match_query) {
+        query = query.filter(
+            sql::<Bool>(
+                "recipients.id IN (SELECT rowid FROM recipients_fts WHERE recipients_fts MATCH ",
+            )
+            .bind::<Text, _>(fts_query)
+            .sql(")"),
+        );
+    }
+    if let Some(members) = smart_group_members {
+        query = query.filter(recipients::id.eq_any(members.to_vec()));
+    } else if let Some(group) = search.group {
+        query = query.filter(
+            recipients::id.eq_any(
+                groups_recipients::table
+                    .filter(groups_recipients::group_id.eq(group))
+                    .select(groups_recipients::recipient_id),
+            ),
+        );
+    }
+    query = match search.active {
+        Some(true) => query.filter(recipients::unsubscribed_at.is_null()),
+        Some(false) => query.filter(recipients::unsubscribed_at.is_not_null()),
+        None => query,
+    };
+
+    query
+}
+
+/// One page of matching hub recipients with fields and groups, and the number of matches.
+pub fn search_hub_recipients(
+    conn: &mut SqliteConnection,
+    hub: i32,
+    search: &RecipientSearch,
+    page: i64,
+    per_page: i64,
+) -> QueryResult<(Vec<RecipientWithFieldsAndGroups>, i64)> {
+    let members = smart_group_member_ids(conn, hub, search)?;
+
+    let total = filtered_hub_recipients(hub, search, members.as_deref())
+        .count()
+        .get_result::<i64>(conn)?;
+
+    let recipients = sorted_hub_recipients(hub, search, members.as_deref())
+        .offset((page - 1).max(0) * per_page)
+        .limit(per_page)
+        .select(Recipient::as_select())
+        .load::<Recipient>(conn)?;
+
+    Ok((with_fields_and_groups(conn, recipients)?, total))
+}
+
+/// Every matching hub recipient with fields and groups, for exports.
+pub fn get_hub_filtered_recipients(
+    conn: &mut SqliteConnection,
+    hub: i32,
+    search: &RecipientSearch,
+) -> QueryResult<Vec<RecipientWithFieldsAndGroups>> {
+    let members = smart_group_member_ids(conn, hub, search)?;
+
+    let recipients = sorted_hub_recipients(hub, search, members.as_deref())
+        .select(Recipient::as_select())
+        .load::<Recipient>(conn)?;
+
+    with_fields_and_groups(conn, recipients)
+}
+
+fn sorted_hub_recipients(
+    hub: i32,
+    search: &RecipientSearch,
+    smart_group_members: Option<&[i32]>,
+) -> crate::schema::recipients::BoxedQuery<'static, Sqlite> {
+    use crate::schema::recipients;
+
+    let query = filtered_hub_recipients(hub, search, smart_group_members);
+    match search.sort {
+        RecipientSort::NameAsc => query.order((recipients::name.asc(), recipients::id.asc())),
+        RecipientSort::NameDesc => query.order((recipients::name.desc(), recipients::id.desc())),
+        RecipientSort::EmailAsc => query.order((recipients::email.asc(), recipients::id.asc())),
+        RecipientSort::EmailDesc => query.order((recipients::email.desc(), recipients::id.desc())),
+        RecipientSort::CreatedAsc => {
+            query.order((recipients::created_at.asc(), recipients::id.asc()))
+        }
+        RecipientSort::CreatedDesc => {
+            query.order((recipients::created_at.desc(), recipients::id.desc()))
+        }
+    }
+}
+
+/// Hub groups by name with the number of members, smart groups are counted with the rule
+/// context loaded on first use.
+pub fn get_hub_groups_with_counts(
+    conn: &mut SqliteConnection,
+    hub: i32,
+    rule_context: &mut Option<RuleContext>,
+) -> QueryResult<Vec<(Group, i64)>> {
+    use crate::schema::{groups, groups_recipients};
+    use diesel::dsl::count;
+
+    let mut groups = groups::table
+        .filter(groups::hub_id.eq(hub))
+        .left_join(groups_recipients::table.on(groups::id.eq(groups_recipients::group_id)))
+        .group_by(groups::id)
+        .order(groups::name.asc())
+        .select((
+            Group::as_select(),
+            count(groups_recipients::recipient_id.nullable()),
+        ))
+        .load::<(Group, i64)>(conn)?;
+
+    // Smart groups are counted by their rules
+    if groups.iter().any(|(group, _)| group.rules.is_some()) {
+        let context = RuleContext::get_or_load(rule_context, conn, hub)?;
+        for (group, num_members) in groups.iter_mut() {
+            if group.rules.is_some() {
+                *num_members = match group.rule() {
+                    Some(rule) => context.members(&rule).count() as i64,
+                    None => 0,
+                };
+            }
+        }
+    }
+
+    Ok(groups)
+}
+
 pub fn get_hub_group_recipients(
     conn: &mut SqliteConnection,
     hub: i32,
@@ -111,6 +368,22 @@ pub fn get_hub_group_recipients(
         })
         .collect();
     result.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+
+    if result.iter().any(|(group, _)| group.rules.is_some()) {
+        let context = RuleContext::load(conn, hub)?;
+        for (group, recipients) in result.iter_mut() {
+            if group.rules.is_some() {
+                *recipients = match group.rule() {
+                    Some(rule) => context
+                        .members(&rule)
+                        .map(|(recipient, _, _)| recipient.clone())
+                        .collect(),
+                    None => vec![],
+                };
+            }
+        }
+    }
+
     Ok(result)
 }
 
@@ -119,7 +392,7 @@ pub fn create_recipient(
     hub: i32,
     name: &str,
     email: &str,
-) -> QueryResult<usize> {
+) -> QueryResult<Recipient> {
     use crate::schema::recipients;
 
     let new_recipient = NewRecipient {
@@ -128,39 +401,163 @@ pub fn create_recipient(
         email,
     };
 
-    diesel::insert_into(recipients::table)
+    let recipient = diesel::insert_into(recipients::table)
         .values(new_recipient)
-        .execute(conn)
+        .returning(Recipient::as_returning())
+        .get_result(conn)?;
+
+    index_recipient(conn, recipient.id)?;
+
+    Ok(recipient)
 }
 
-pub fn delete_recipient(conn: &mut SqliteConnection, recipient: i32) -> QueryResult<usize> {
-    use crate::schema::recipients;
+/// Deletes a hub recipient together with its fields and group memberships.
+pub fn delete_recipient(
+    conn: &mut SqliteConnection,
+    hub: i32,
+    recipient: i32,
+) -> QueryResult<usize> {
+    use crate::schema::{groups_recipients, recipient_fields, recipients};
+
+    conn.transaction(|conn| {
+        let recipient: i32 = recipients::table
+            .filter(recipients::id.eq(recipient))
+            .filter(recipients::hub_id.eq(hub))
+            .select(recipients::id)
+            .first(conn)?;
 
-    diesel::delete(recipients::table.filter(recipients::id.eq(recipient))).execute(conn)
+        diesel::delete(
+            groups_recipients::table.filter(groups_recipients::recipient_id.eq(recipient)),
+        )
+        .execute(conn)?;
+        diesel::delete(
+            recipient_fields::table.filter(recipient_fields::recipient_id.eq(recipient)),
+        )
+        .execute(conn)?;
+        let deleted =
+            diesel::delete(recipients::table.filter(recipients::id.eq(recipient))).execute(conn)?;
+
+        index_recipient(conn, recipient)?;
+
+        Ok(deleted)
+    })
 }
 
-pub fn create_group(conn: &mut SqliteConnection, hub: i32, name: &str) -> QueryResult<usize> {
+pub fn create_group(conn: &mut SqliteConnection, hub: i32, name: &str) -> QueryResult<Group> {
     use crate::schema::groups;
 
     let new_group = NewGroup { hub_id: hub, name };
 
     diesel::insert_into(groups::table)
         .values(new_group)
-        .execute(conn)
+        .returning(Group::as_returning())
+        .get_result(conn)
+}
+
+/// Creates a smart group, its members are the recipients matching the rules.
+pub fn create_smart_group(
+    conn: &mut SqliteConnection,
+    hub: i32,
+    name: &str,
+    rules: &str,
+) -> QueryResult<Group> {
+    use crate::schema::groups;
+
+    diesel::insert_into(groups::table)
+        .values((
+            groups::hub_id.eq(hub),
+            groups::name.eq(name),
+            groups::rules.eq(rules),
+        ))
+        .returning(Group::as_returning())
+        .get_result(conn)
+}
+
+pub fn get_hub_group(conn: &mut SqliteConnection, hub: i32, group: i32) -> QueryResult<Group> {
+    use crate::schema::groups;
+
+    groups::table
+        .filter(groups::id.eq(group))
+        .filter(groups::hub_id.eq(hub))
+        .select(Group::as_select())
+        .first(conn)
 }
 
-pub fn delete_group(conn: &mut SqliteConnection, group: i32) -> QueryResult<usize> {
+/// Replaces the rules of a smart group.
+pub fn update_group_rules(
+    conn: &mut SqliteConnection,
+    hub: i32,
+    group: i32,
+    rules: &str,
+) -> QueryResult<usize> {
     use crate::schema::groups;
 
-    diesel::delete(groups::table.filter(groups::id.eq(group))).execute(conn)
+    diesel::update(
+        groups::table
+            .filter(groups::id.eq(group))
+            .filter(groups::hub_id.eq(hub))
+            .filter(groups::rules.is_not_null()),
+    )
+    .set((
+        groups::rules.eq(rules),
+        groups::updated_at.eq(chrono::Utc::now().naive_utc()),
+    ))
+    .execute(conn)
+}
+
+pub fn update_group(
+    conn: &mut SqliteConnection,
+    hub: i32,
+    group: i32,
+    name: &str,
+) -> QueryResult<usize> {
+    use crate::schema::groups;
+
+    diesel::update(
+        groups::table
+            .filter(groups::id.eq(group))
+            .filter(groups::hub_id.eq(hub)),
+    )
+    .set((
+        groups::name.eq(name),
+        groups::updated_at.eq(chrono::Utc::now().naive_utc()),
+    ))
+    .execute(conn)
+}
+
+/// Deletes a hub group, the recipients themselves are kept.
+pub fn delete_group(conn: &mut SqliteConnection, hub: i32, group: i32) -> QueryResult<usize> {
+    use crate::schema::{groups, groups_recipients};
+
+    conn.transaction(|conn| {
+        let group: i32 = groups::table
+            .filter(groups::id.eq(group))
+            .filter(groups::hub_id.eq(hub))
+            .select(groups::id)
+            .first(conn)?;
+
+        diesel::delete(groups_recipients::table.filter(groups_recipients::group_id.eq(group)))
+            .execute(conn)?;
+        diesel::delete(groups::table.filter(groups::id.eq(group))).execute(conn)
+    })
 }
 
+/// Adds the recipient to a group with stored members, a smart group is left as is and 0 is
+/// returned.
 pub fn assign_recipient_to_group(
     conn: &mut SqliteConnection,
     recipient_id: i32,
     group_id: i32,
 ) -> QueryResult<usize> {
-    use crate::schema::groups_recipients;
+    use crate::schema::{groups, groups_recipients};
+
+    let is_smart = groups::table
+        .filter(groups::id.eq(group_id))
+        .select(groups::rules.is_not_null())
+        .first::<bool>(conn)?;
+    if is_smart {
+        return Ok(0);
+    }
 
     let new_assignment = GroupRecipient {
         recipient_id,
@@ -204,6 +601,12 @@ pub fn clean_all_recipients_and_groups(
         .select(recipients::id)
         .load::<i32>(conn)?;
 
+    diesel::sql_query(
+        "DELETE FROM recipients_fts WHERE rowid IN (SELECT id FROM recipients WHERE hub_id = ?)",
+    )
+    .bind::<Integer, _>(hub)
+    .execute(conn)?;
+
     // delete all groups_recipients
     diesel::delete(
         groups_recipients::table.filter(groups_recipients::recipient_id.eq_any(&recipient_ids)),
@@ -220,107 +623,6 @@ pub fn clean_all_recipients_and_groups(
     diesel::delete(groups::table.filter(groups::hub_id.eq(hub))).execute(conn)
 }
 
-#[derive(Debug, Deserialize)]
-struct RecipientCSV {
-    name: String,
-    email: String,
-    groups: Vec<String>,
-    optional_fields: HashMap<String, String>,
-}
-
-fn parse_recipients_csv(csv: &str) -> Result<Vec<RecipientCSV>, Box<dyn std::error::Error>> {
-    let mut rdr = csv::Reader::from_reader(csv.as_bytes());
-
-    let headers = rdr.headers()?.clone();
-    let mut recipients = Vec::new();
-
-    for result in rdr.records() {
-        let record = result?;
-        let mut optional_fields = HashMap::new();
-
-        let mut name = String::new();
-        let mut email = String::new();
-        let mut groups = Vec::new();
-
-        for (i, field) in record.iter().enumerate() {
-            match headers.get(i) {
-                Some("name") => name = field.to_string(),
-                Some("email") => email = field.to_string(),
-                Some("groups") => {
-                    groups = field
-                        .split(',')
-                        .map(|s| s.trim().to_string())
-                        .filter(|s| !s.is_empty())
-                        .collect();
-                }
-                Some(header) => {
-                    if field.len() == 0 {
-                        continue;
-                    }
-                    optional_fields.insert(header.to_string(), field.to_string());
-                }
-                None => continue,
-            }
-        }
-
-        recipients.push(RecipientCSV {
-            name,
-            email,
-            groups,
-            optional_fields,
-        });
-    }
-
-    Ok(recipients)
-}
-
-pub fn update_recipients_from_csv(
-    conn: &mut SqliteConnection,
-    hub_id: i32,
-    csv: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let recipients = parse_recipients_csv(csv)?;
-
-    for recipient in recipients {
-        conn.transaction::<_, Error, _>(|conn| {
-            insert_or_update_recipient_with_groups(conn, hub_id, recipient)
-        })?;
-    }
-    Ok(())
-}
-
-fn get_or_insert_group(
-    conn: &mut SqliteConnection,
-    group_name: &str,
-    hub_id: i32,
-) -> Result<i32, Error> {
-    use crate::schema::groups;
-
-    if let Some(group_id) = groups::table
-        .filter(groups::name.eq(group_name))
-        .select(groups::id)
-        .first::<i32>(conn)
-        .optional()?
-    {
-        return Ok(group_id);
-    }
-
-    let new_group = NewGroup {
-        name: group_name,
-        hub_id,
-    };
-
-    diesel::insert_into(groups::table)
-        .values(&new_group)
-        .execute(conn)?;
-
-    groups::table
-        .filter(groups::name.eq(group_name))
-        .filter(groups::hub_id.eq(hub_id))
-        .select(groups::id)
-        .first::<i32>(conn)
-}
-
 pub fn update_recipient_custom_fields(
     conn: &mut SqliteConnection,
     recipient_id: i32,
@@ -346,71 +648,6 @@ pub fn update_recipient_custom_fields(
     Ok(())
 }
 
-fn insert_or_update_recipient_with_groups(
-    conn: &mut SqliteConnection,
-    hub_id: i32,
-    csv: RecipientCSV,
-) -> Result<(), Error> {
-    use crate::schema::groups_recipients;
-    use crate::schema::recipients;
-
-    let existing_recipient = recipients::table
-        .filter(recipients::email.eq(&csv.email))
-        .select((recipients::id, recipients::name))
-        .first::<(i32, String)>(conn)
-        .optional()?;
-
-    let recipient_id = match existing_recipient {
-        Some((existing_id, existing_name)) => {
-            if existing_name != csv.name {
-                diesel::update(recipients::table.filter(recipients::id.eq(existing_id)))
-                    .set(recipients::name.eq(&csv.name))
-                    .execute(conn)?;
-            }
-            existing_id
-        }
-        None => {
-            let new_recipient = NewRecipient {
-                name: &csv.name,
-                email: &csv.email,
-                hub_id,
-            };
-
-            diesel::insert_into(recipients::table)
-                .values(&new_recipient)
-                .execute(conn)?;
-
-            recipients::table
-                .filter(recipients::email.eq(&csv.email))
-                .select(recipients::id)
-                .first::<i32>(conn)?
-        }
-    };
-
-    diesel::delete(
-        groups_recipients::table.filter(groups_recipients::recipient_id.eq(recipient_id)),
-    )
-    .execute(conn)?;
-
-    for group_name in &csv.groups {
-        let group_id = get_or_insert_group(conn, group_name, hub_id)?;
-
-        // Insert new group-recipient relation
-        let new_group_recipient = GroupRecipient {
-            group_id,
-            recipient_id,
-        };
-
-        diesel::insert_into(groups_recipients::table)
-            .values(&new_group_recipient)
-            .execute(conn)?;
-    }
-
-    update_recipient_custom_fields(conn, recipient_id, csv.optional_fields)?;
-
-    Ok(())
-}
-
 pub fn get_hub_all_groups(conn: &mut SqliteConnection, hub: i32) -> QueryResult<Vec<Group>> {
     use crate::schema::groups;
 
@@ -444,6 +681,80 @@ pub fn get_recipient(conn: &mut SqliteConnection, recipient_id: i32) -> QueryRes
         .first::<Recipient>(conn)
 }
 
+/// Addresses written the same but for the case, see the `recipient_duplicate_emails` view.
+pub fn get_hub_duplicate_emails(
+    conn: &mut SqliteConnection,
+    hub: i32,
+) -> QueryResult<Vec<DuplicateEmail>> {
+    diesel::sql_query(
+        "SELECT address, num_recipients, recipient_ids FROM recipient_duplicate_emails \
+         WHERE hub_id = ? ORDER BY address",
+    )
+    .bind::<Integer, _>(hub)
+    .load(conn)
+}
+
+/// Finds a hub recipient by email address, ignoring case.
+pub fn get_hub_recipient_by_email(
+    conn: &mut SqliteConnection,
+    hub: i32,
+    email: &str,
+) -> QueryResult<Option<Recipient>> {
+    use crate::schema::recipients;
+
+    recipients::table
+        .filter(recipients::hub_id.eq(hub))
+        .filter(lower(recipients::email).eq(lower(email)))
+        .first::<Recipient>(conn)
+        .optional()
+}
+
+/// Marks the recipient as unsubscribed, keeping the date of an earlier unsubscribe.
+///
+/// The unsubscribe is attributed to the last campaign the recipient received and reported to
+/// the hub webhooks.
+pub fn unsubscribe_recipient(conn: &mut SqliteConnection, recipient_id: i32) -> QueryResult<usize> {
+    use crate::schema::{email_recipients, emails, recipients};
+
+    conn.transaction(|conn| {
+        let updated = diesel::update(
+            recipients::table
+                .filter(recipients::id.eq(recipient_id))
+                .filter(recipients::unsubscribed_at.is_null()),
+        )
+        .set(recipients::unsubscribed_at.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)?;
+
+        if updated == 0 {
+            return Ok(0);
+        }
+
+        let recipient = get_recipient(conn, recipient_id)?;
+        let last_campaign_recipient = email_recipients::table
+            .inner_join(emails::table)
+            .filter(emails::hub_id.eq(recipient.hub_id))
+            .filter(emails::is_transactional.eq(false))
+            .filter(email_recipients::address.eq(&recipient.email))
+            .filter(email_recipients::is_sent.eq(true))
+            .order(emails::created_at.desc())
+            .select(email_recipients::id)
+            .first::<i32>(conn)
+            .optional()?;
+
+        if let Some(email_recipient_id) = last_campaign_recipient {
+            diesel::update(
+                email_recipients::table.filter(email_recipients::id.eq(email_recipient_id)),
+            )
+            .set(email_recipients::unsubscribed.eq(true))
+            .execute(conn)?;
+        }
+
+        queue_recipient_event(conn, &recipient, WebhookEvent::Unsubscribed)?;
+
+        Ok(updated)
+    })
+}
+
 pub fn get_recipient_fields(
     conn: &mut SqliteConnection,
     recipient_id: i32,
@@ -470,6 +781,7 @@ pub fn get_recipient_group_ids(
     Ok(result)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn save_recipient(
     conn: &mut SqliteConnection,
     recipient_id: i32,
@@ -484,21 +796,27 @@ pub fn save_recipient(
     use crate::schema::groups_recipients;
     use crate::schema::recipients;
 
-    let unsubscribed_at = match active {
-        true => None,
-        false => Some(chrono::Utc::now().naive_utc()),
-    };
+    let target = recipients::table.filter(recipients::id.eq(recipient_id));
 
-    diesel::update(recipients::table.filter(recipients::id.eq(recipient_id)))
-        .set((
-            recipients::name.eq(name),
-            recipients::email.eq(email),
-            recipients::unsubscribed_at.eq(unsubscribed_at),
-        ))
+    diesel::update(target)
+        .set((recipients::name.eq(name), recipients::email.eq(email)))
         .execute(conn)?;
 
+    match active {
+        true => {
+            diesel::update(target)
+                .set(recipients::unsubscribed_at.eq(None::<chrono::NaiveDateTime>))
+                .execute(conn)?;
+        }
+        false => {
+            unsubscribe_recipient(conn, recipient_id)?;
+        }
+    }
+
+    // Members of smart groups are not stored
     let groups = groups::table
         .filter(groups::id.eq_any(groups))
+        .filter(groups::rules.is_null())
         .select(groups::id)
         .load::<i32>(conn)?;
 
@@ -527,5 +845,7 @@ pub fn save_recipient(
 
     update_recipient_custom_fields(conn, recipient_id, fields)?;
 
+    index_recipient(conn, recipient_id)?;
+
     Ok(())
 }
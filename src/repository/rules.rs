@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::sql_types::{Integer, Nullable, Text, Timestamp};
+
+use crate::repository::recipient::{RecipientWithFieldsAndGroups, get_hub_all_recipients};
+use crate::rules::{RecipientFacts, Rule};
+
+#[derive(QueryableByName)]
+struct Engagement {
+    #[diesel(sql_type = Text)]
+    address: String,
+    #[diesel(sql_type = Nullable<Timestamp>)]
+    last_opened: Option<NaiveDateTime>,
+    #[diesel(sql_type = Nullable<Timestamp>)]
+    last_replied: Option<NaiveDateTime>,
+}
+
+/// Hub recipients with everything smart group rules check, loaded once for any number of rules.
+pub struct RuleContext {
+    recipients: Vec<RecipientWithFieldsAndGroups>,
+    /// Last open and reply by lowercased address.
+    engagement: HashMap<String, (Option<NaiveDateTime>, Option<NaiveDateTime>)>,
+    now: NaiveDateTime,
+}
+
+impl RuleContext {
+    /// The context loaded on first use, so a request evaluates any number of rules on one load.
+    pub fn get_or_load<'a>(
+        context: &'a mut Option<Self>,
+        conn: &mut SqliteConnection,
+        hub: i32,
+    ) -> QueryResult<&'a Self> {
+        match context {
+            Some(context) => Ok(context),
+            None => Ok(context.insert(Self::load(conn, hub)?)),
+        }
+    }
+
+    pub fn load(conn: &mut SqliteConnection, hub: i32) -> QueryResult<Self> {
+        let mut recipients = get_hub_all_recipients(conn, hub)?;
+        recipients.sort_by(|(a, _, _), (b, _, _)| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+
+        let engagement = diesel::sql_query(
+            "SELECT lower(email_recipients.address) AS address, \
+             MAX(email_recipients.opened_at) AS last_opened, \
+             MAX(email_recipients.replied_at) AS last_replied \
+             FROM email_recipients JOIN emails ON emails.id = email_recipients.email_id \
+             WHERE emails.hub_id = ? GROUP BY lower(email_recipients.address)",
+        )
+        .bind::<Integer, _>(hub)
+        .load::<Engagement>(conn)?
+        .into_iter()
+        .map(|row| (row.address, (row.last_opened, row.last_replied)))
+        .collect();
+
+        Ok(Self {
+            recipients,
+            engagement,
+            now: chrono::Utc::now().naive_utc(),
+        })
+    }
+
+    fn matches(
+        &self,
+        rule: &Rule,
+        (recipient, fields, groups): &RecipientWithFieldsAndGroups,
+    ) -> bool {
+        let (last_opened, last_replied) = self
+            .engagement
+            .get(&recipient.email.to_lowercase())
+            .copied()
+            .unwrap_or_default();
+
+        rule.matches(&RecipientFacts {
+            recipient,
+            fields,
+            groups,
+            last_opened,
+            last_replied,
+            now: self.now,
+        })
+    }
+
+    /// Matching recipients by name.
+    pub fn members<'a>(
+        &'a self,
+        rule: &'a Rule,
+    ) -> impl Iterator<Item = &'a RecipientWithFieldsAndGroups> {
+        self.recipients
+            .iter()
+            .filter(move |recipient| self.matches(rule, recipient))
+    }
+}
+
+/// One page of the recipients matching a smart group rule by name, and their number.
+pub fn get_smart_group_members(
+    context: &RuleContext,
+    rule: &Rule,
+    page: i64,
+    per_page: i64,
+) -> (Vec<RecipientWithFieldsAndGroups>, i64) {
+    let members = context
+        .members(rule)
+        .skip(((page - 1).max(0) * per_page) as usize)
+        .take(per_page as usize)
+        .cloned()
+        .collect();
+
+    (members, context.members(rule).count() as i64)
+}
@@ -0,0 +1,195 @@
+use diesel::prelude::*;
+use diesel::query_builder::{BoxedSqlQuery, SqlQuery};
+use diesel::sql_types::{BigInt, Integer, Timestamp};
+use diesel::sqlite::Sqlite;
+
+use crate::models::stats::{CampaignStats, GroupStats, PeriodStats, StatCounts, SubjectStats};
+
+/// Counters over the joined `emails` and `email_recipients` rows, see [`StatCounts`].
+const COUNTS: &str = "\
+    COUNT(email_recipients.id) AS num_recipients, \
+    COALESCE(SUM(email_recipients.is_sent), 0) AS num_sent, \
+    COALESCE(SUM(emails.is_sent AND NOT email_recipients.is_sent), 0) AS num_bounced, \
+    COALESCE(SUM(email_recipients.opened), 0) AS num_opened, \
+    COALESCE(SUM(email_recipients.replied), 0) AS num_replied, \
+    COALESCE(SUM(email_recipients.unsubscribed), 0) AS num_unsubscribed";
+
+/// Campaigns of the hub, transactional messages are not counted.
+const CAMPAIGNS: &str = "\
+    FROM emails \
+    LEFT JOIN email_recipients ON email_recipients.email_id = emails.id \
+    WHERE emails.hub_id = ? AND NOT emails.is_transactional";
+
+/// Size of the buckets of the statistics over time.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum StatsInterval {
+    Day,
+    #[default]
+    Week,
+    Month,
+}
+
+impl StatsInterval {
+    pub const ALL: [StatsInterval; 3] = [
+        StatsInterval::Day,
+        StatsInterval::Week,
+        StatsInterval::Month,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StatsInterval::Day => "day",
+            StatsInterval::Week => "week",
+            StatsInterval::Month => "month",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|interval| interval.as_str() == value)
+    }
+
+    fn period_format(&self) -> &'static str {
+        match self {
+            StatsInterval::Day => "%Y-%m-%d",
+            StatsInterval::Week => "%Y-W%W",
+            StatsInterval::Month => "%Y-%m",
+        }
+    }
+}
+
+/// Campaigns taken into account, by creation date.
+#[derive(Clone, Copy, Default)]
+pub struct StatsFilter {
+    pub since: Option<chrono::NaiveDateTime>,
+    pub until: Option<chrono::NaiveDateTime>,
+}
+
+fn with_period(
+    mut query: BoxedSqlQuery<'static, Sqlite, SqlQuery>,
+    filter: &StatsFilter,
+) -> BoxedSqlQuery<'static, Sqlite, SqlQuery> {
+    if let Some(since) = filter.since {
+        query = query
+            .sql(" AND emails.created_at >= ?")
+            .bind::<Timestamp, _>(since);
+    }
+    if let Some(until) = filter.until {
+        query = query
+            .sql(" AND emails.created_at < ?")
+            .bind::<Timestamp, _>(until);
+    }
+
+    query
+}
+
+fn campaigns_query(
+    select: &str,
+    hub: i32,
+    filter: &StatsFilter,
+) -> BoxedSqlQuery<'static, Sqlite, SqlQuery> {
+    let query = diesel::sql_query(format!("SELECT {select} {CAMPAIGNS}"))
+        .into_boxed::<Sqlite>()
+        .bind::<Integer, _>(hub);
+
+    with_period(query, filter)
+}
+
+/// Totals over all campaigns of the hub.
+pub fn get_hub_stats_totals(
+    conn: &mut SqliteConnection,
+    hub: i32,
+    filter: &StatsFilter,
+) -> QueryResult<StatCounts> {
+    campaigns_query(COUNTS, hub, filter).get_result(conn)
+}
+
+/// Latest campaigns of the hub with their results.
+pub fn get_hub_campaign_stats(
+    conn: &mut SqliteConnection,
+    hub: i32,
+    filter: &StatsFilter,
+    limit: i64,
+) -> QueryResult<Vec<CampaignStats>> {
+    campaigns_query(
+        &format!("emails.id, emails.created_at, emails.subject, {COUNTS}"),
+        hub,
+        filter,
+    )
+    .sql(" GROUP BY emails.id ORDER BY emails.created_at DESC, emails.id DESC LIMIT ?")
+    .bind::<BigInt, _>(limit)
+    .load(conn)
+}
+
+/// Campaign results grouped by the creation period, oldest first.
+pub fn get_hub_period_stats(
+    conn: &mut SqliteConnection,
+    hub: i32,
+    filter: &StatsFilter,
+    interval: StatsInterval,
+) -> QueryResult<Vec<PeriodStats>> {
+    let period = format!(
+        "strftime('{}', emails.created_at)",
+        interval.period_format()
+    );
+
+    campaigns_query(
+        &format!("{period} AS period, COUNT(DISTINCT emails.id) AS num_campaigns, {COUNTS}"),
+        hub,
+        filter,
+    )
+    .sql(" GROUP BY period ORDER BY period")
+    .load(conn)
+}
+
+/// Campaign results of the members of every hub group.
+///
+/// Recipients are matched to groups by their current membership.
+pub fn get_hub_group_stats(
+    conn: &mut SqliteConnection,
+    hub: i32,
+    filter: &StatsFilter,
+) -> QueryResult<Vec<GroupStats>> {
+    let query = diesel::sql_query(format!(
+        "SELECT groups.id, groups.name, {COUNTS} \
+        FROM groups \
+        JOIN groups_recipients ON groups_recipients.group_id = groups.id \
+        JOIN recipients ON recipients.id = groups_recipients.recipient_id \
+        JOIN email_recipients ON email_recipients.address = recipients.email \
+        JOIN emails ON emails.id = email_recipients.email_id AND emails.hub_id = groups.hub_id \
+        WHERE groups.hub_id = ? AND NOT emails.is_transactional"
+    ))
+    .into_boxed::<Sqlite>()
+    .bind::<Integer, _>(hub);
+
+    with_period(query, filter)
+        .sql(" GROUP BY groups.id ORDER BY groups.name")
+        .load(conn)
+}
+
+/// Subjects of sent campaigns with the best open rate, ties broken by the reply rate.
+pub fn get_hub_top_subjects(
+    conn: &mut SqliteConnection,
+    hub: i32,
+    filter: &StatsFilter,
+    limit: i64,
+) -> QueryResult<Vec<SubjectStats>> {
+    campaigns_query(
+        &format!("emails.subject, COUNT(DISTINCT emails.id) AS num_campaigns, {COUNTS}"),
+        hub,
+        filter,
+    )
+    .sql(
+        // The aggregates are spelled out, the aliases would resolve to the `emails` counters
+        " AND emails.is_sent \
+        GROUP BY emails.subject \
+        HAVING SUM(email_recipients.is_sent) > 0 \
+        ORDER BY 1.0 * SUM(email_recipients.opened) / SUM(email_recipients.is_sent) DESC, \
+            1.0 * SUM(email_recipients.replied) / SUM(email_recipients.is_sent) DESC, \
+            SUM(email_recipients.is_sent) DESC \
+        LIMIT ?",
+    )
+    .bind::<BigInt, _>(limit)
+    .load(conn)
+}
@@ -0,0 +1,87 @@
+use diesel::prelude::*;
+
+use crate::models::template::{MessageTemplate, NewMessageTemplate};
+
+pub fn get_hub_templates(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+) -> QueryResult<Vec<MessageTemplate>> {
+    use crate::schema::message_templates;
+
+    message_templates::table
+        .filter(message_templates::hub_id.eq(hub_id))
+        .order(message_templates::name.asc())
+        .select(MessageTemplate::as_select())
+        .load(conn)
+}
+
+pub fn get_hub_template(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    template_id: i32,
+) -> QueryResult<MessageTemplate> {
+    use crate::schema::message_templates;
+
+    message_templates::table
+        .filter(message_templates::id.eq(template_id))
+        .filter(message_templates::hub_id.eq(hub_id))
+        .select(MessageTemplate::as_select())
+        .first(conn)
+}
+
+pub fn create_template(
+    conn: &mut SqliteConnection,
+    template: &NewMessageTemplate,
+) -> QueryResult<MessageTemplate> {
+    use crate::schema::message_templates;
+
+    diesel::insert_into(message_templates::table)
+        .values(template)
+        .returning(MessageTemplate::as_returning())
+        .get_result(conn)
+}
+
+pub fn update_template(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    template_id: i32,
+    name: &str,
+    subject: &str,
+    body: &str,
+) -> QueryResult<MessageTemplate> {
+    use crate::schema::message_templates;
+
+    diesel::update(
+        message_templates::table
+            .filter(message_templates::id.eq(template_id))
+            .filter(message_templates::hub_id.eq(hub_id)),
+    )
+    .set((
+        message_templates::name.eq(name),
+        message_templates::subject.eq(subject),
+        message_templates::body.eq(body),
+        message_templates::updated_at.eq(chrono::Utc::now().naive_utc()),
+    ))
+    .returning(MessageTemplate::as_returning())
+    .get_result(conn)
+}
+
+pub fn delete_template(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    template_id: i32,
+) -> QueryResult<usize> {
+    use crate::schema::message_templates;
+
+    let deleted = diesel::delete(
+        message_templates::table
+            .filter(message_templates::id.eq(template_id))
+            .filter(message_templates::hub_id.eq(hub_id)),
+    )
+    .execute(conn)?;
+
+    match deleted {
+        0 => Err(diesel::result::Error::NotFound),
+        deleted => Ok(deleted),
+    }
+}
@@ -0,0 +1,229 @@
+use diesel::prelude::*;
+use serde_json::json;
+
+use crate::models::email::EmailRecipient;
+use crate::models::recipient::Recipient;
+use crate::models::webhook::{
+    DELIVERY_DELIVERED, DELIVERY_FAILED, DELIVERY_PENDING, NewWebhook, NewWebhookDelivery, Webhook,
+    WebhookDelivery, WebhookEvent,
+};
+
+pub fn create_webhook(conn: &mut SqliteConnection, webhook: &NewWebhook) -> QueryResult<Webhook> {
+    use crate::schema::webhooks;
+
+    diesel::insert_into(webhooks::table)
+        .values(webhook)
+        .returning(Webhook::as_returning())
+        .get_result(conn)
+}
+
+pub fn get_hub_webhooks(conn: &mut SqliteConnection, hub_id: i32) -> QueryResult<Vec<Webhook>> {
+    use crate::schema::webhooks;
+
+    webhooks::table
+        .filter(webhooks::hub_id.eq(hub_id))
+        .order(webhooks::created_at.desc())
+        .select(Webhook::as_select())
+        .load(conn)
+}
+
+/// Removes the webhook of the hub together with its delivery log.
+pub fn delete_webhook(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    webhook_id: i32,
+) -> QueryResult<usize> {
+    use crate::schema::{webhook_deliveries, webhooks};
+
+    conn.transaction(|conn| {
+        let webhook_id = webhooks::table
+            .filter(webhooks::id.eq(webhook_id))
+            .filter(webhooks::hub_id.eq(hub_id))
+            .select(webhooks::id)
+            .first::<i32>(conn)?;
+
+        diesel::delete(
+            webhook_deliveries::table.filter(webhook_deliveries::webhook_id.eq(webhook_id)),
+        )
+        .execute(conn)?;
+
+        diesel::delete(webhooks::table.filter(webhooks::id.eq(webhook_id))).execute(conn)
+    })
+}
+
+/// Queues a delivery of the event for every active webhook of the hub subscribed to it.
+pub fn queue_webhook_event(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    event: WebhookEvent,
+    data: serde_json::Value,
+) -> QueryResult<usize> {
+    use crate::schema::{webhook_deliveries, webhooks};
+
+    let webhooks = webhooks::table
+        .filter(webhooks::hub_id.eq(hub_id))
+        .filter(webhooks::is_active.eq(true))
+        .select(Webhook::as_select())
+        .load(conn)?
+        .into_iter()
+        .filter(|webhook| webhook.get_events().contains(&event))
+        .collect::<Vec<_>>();
+
+    if webhooks.is_empty() {
+        return Ok(0);
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let payload = json!({
+        "event": event,
+        "created_at": now.and_utc().to_rfc3339(),
+        "data": data,
+    })
+    .to_string();
+
+    let deliveries = webhooks
+        .iter()
+        .map(|webhook| NewWebhookDelivery {
+            webhook_id: webhook.id,
+            event: event.as_str(),
+            payload: &payload,
+            status: DELIVERY_PENDING,
+            attempts: 0,
+            next_attempt_at: now,
+            created_at: now,
+        })
+        .collect::<Vec<_>>();
+
+    diesel::insert_into(webhook_deliveries::table)
+        .values(&deliveries)
+        .execute(conn)
+}
+
+/// Queues an event about a single campaign recipient.
+pub fn queue_email_recipient_event(
+    conn: &mut SqliteConnection,
+    recipient: &EmailRecipient,
+    event: WebhookEvent,
+) -> QueryResult<usize> {
+    use crate::schema::emails;
+
+    let hub_id = emails::table
+        .filter(emails::id.eq(recipient.email_id))
+        .select(emails::hub_id)
+        .first::<i32>(conn)?;
+
+    queue_webhook_event(
+        conn,
+        hub_id,
+        event,
+        json!({
+            "hub_id": hub_id,
+            "email_id": recipient.email_id,
+            "email_recipient_id": recipient.id,
+            "address": recipient.address,
+        }),
+    )
+}
+
+/// Queues an event about a hub recipient.
+pub fn queue_recipient_event(
+    conn: &mut SqliteConnection,
+    recipient: &Recipient,
+    event: WebhookEvent,
+) -> QueryResult<usize> {
+    queue_webhook_event(
+        conn,
+        recipient.hub_id,
+        event,
+        json!({
+            "hub_id": recipient.hub_id,
+            "recipient_id": recipient.id,
+            "name": recipient.name,
+            "email": recipient.email,
+        }),
+    )
+}
+
+/// Pending deliveries whose next attempt is due, oldest first.
+pub fn get_due_webhook_deliveries(
+    conn: &mut SqliteConnection,
+    limit: i64,
+) -> QueryResult<Vec<(WebhookDelivery, Webhook)>> {
+    use crate::schema::{webhook_deliveries, webhooks};
+
+    webhook_deliveries::table
+        .inner_join(webhooks::table)
+        .filter(webhook_deliveries::status.eq(DELIVERY_PENDING))
+        .filter(webhook_deliveries::next_attempt_at.le(chrono::Utc::now().naive_utc()))
+        .order(webhook_deliveries::next_attempt_at.asc())
+        .limit(limit)
+        .select((WebhookDelivery::as_select(), Webhook::as_select()))
+        .load(conn)
+}
+
+pub fn set_webhook_delivery_delivered(
+    conn: &mut SqliteConnection,
+    delivery_id: i32,
+    attempts: i32,
+    response_status: i32,
+) -> QueryResult<usize> {
+    use crate::schema::webhook_deliveries;
+
+    diesel::update(webhook_deliveries::table.filter(webhook_deliveries::id.eq(delivery_id)))
+        .set((
+            webhook_deliveries::status.eq(DELIVERY_DELIVERED),
+            webhook_deliveries::attempts.eq(attempts),
+            webhook_deliveries::response_status.eq(response_status),
+            webhook_deliveries::last_error.eq(None::<String>),
+            webhook_deliveries::delivered_at.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(conn)
+}
+
+/// Records a failed attempt, the delivery is retried at `next_attempt_at` or given up when `None`.
+pub fn set_webhook_delivery_error(
+    conn: &mut SqliteConnection,
+    delivery_id: i32,
+    attempts: i32,
+    response_status: Option<i32>,
+    last_error: &str,
+    next_attempt_at: Option<chrono::NaiveDateTime>,
+) -> QueryResult<usize> {
+    use crate::schema::webhook_deliveries;
+
+    let target = webhook_deliveries::table.filter(webhook_deliveries::id.eq(delivery_id));
+    let result = (
+        webhook_deliveries::attempts.eq(attempts),
+        webhook_deliveries::response_status.eq(response_status),
+        webhook_deliveries::last_error.eq(last_error),
+    );
+
+    match next_attempt_at {
+        Some(next_attempt_at) => diesel::update(target)
+            .set((
+                result,
+                webhook_deliveries::next_attempt_at.eq(next_attempt_at),
+            ))
+            .execute(conn),
+        None => diesel::update(target)
+            .set((result, webhook_deliveries::status.eq(DELIVERY_FAILED)))
+            .execute(conn),
+    }
+}
+
+/// Latest deliveries of the hub webhooks for the delivery log.
+pub fn get_hub_webhook_deliveries(
+    conn: &mut SqliteConnection,
+    hub_id: i32,
+    limit: i64,
+) -> QueryResult<Vec<(WebhookDelivery, String)>> {
+    use crate::schema::{webhook_deliveries, webhooks};
+
+    webhook_deliveries::table
+        .inner_join(webhooks::table)
+        .filter(webhooks::hub_id.eq(hub_id))
+        .order(webhook_deliveries::id.desc())
+        .limit(limit)
+        .select((WebhookDelivery::as_select(), webhooks::url))
+        .load(conn)
+}
@@ -0,0 +1,252 @@
+use std::error::Error;
+
+use actix_web::{HttpResponse, delete, get, post, web};
+use diesel::result::Error as DieselError;
+use log::error;
+use serde::Serialize;
+
+use crate::db::DbPool;
+use crate::forms::api::{AudiencePayload, EmailListQuery, EmailPayload};
+use crate::models::api_key::ApiScope;
+use crate::models::config::ServerConfig;
+use crate::models::email::{Email, EmailRecipient, EmailSummary};
+use crate::repository::audience::{AudienceSummary, resolve_audience};
+use crate::repository::email::{
+    EmailFilter, create_email, get_email, get_email_recipients, get_hub_email_summaries,
+    remove_email, reset_email_sent_and_opened_status,
+};
+use crate::routes::api::{ApiError, ApiUser, db_connection};
+use crate::utils::send_zmq_email_id;
+
+/// Email without the attachment content, with the recipient results.
+#[derive(Serialize)]
+pub struct EmailResponse {
+    pub id: i32,
+    pub subject: Option<String>,
+    pub message: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub is_sent: bool,
+    pub attachment_name: Option<String>,
+    pub attachment_mime: Option<String>,
+    pub num_recipients: usize,
+    pub num_sent: i32,
+    pub num_opened: i32,
+    pub num_replied: i32,
+    pub recipients: Vec<EmailRecipient>,
+    /// Skipped and excluded addresses of a just created email.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audience: Option<AudienceSummary>,
+}
+
+impl EmailResponse {
+    fn new(email: Email, recipients: Vec<EmailRecipient>) -> Self {
+        Self {
+            id: email.id,
+            subject: email.subject,
+            message: email.message,
+            created_at: email.created_at,
+            is_sent: email.is_sent,
+            attachment_name: email.attachment_name,
+            attachment_mime: email.attachment_mime,
+            num_recipients: recipients.len(),
+            num_sent: email.num_sent,
+            num_opened: email.num_opened,
+            num_replied: email.num_replied,
+            recipients,
+            audience: None,
+        }
+    }
+}
+
+/// Campaign of the hub, transactional messages are only served under `/messages`.
+fn hub_email(
+    conn: &mut diesel::SqliteConnection,
+    hub_id: i32,
+    email_id: i32,
+) -> Result<Email, ApiError> {
+    match get_email(conn, email_id)? {
+        email if email.hub_id == hub_id && !email.is_transactional => Ok(email),
+        _ => Err(ApiError::NotFound),
+    }
+}
+
+/// Campaign of the list, without the message and the recipient results.
+#[derive(Serialize)]
+pub struct EmailListItem {
+    #[serde(flatten)]
+    pub email: EmailSummary,
+    pub num_recipients: i64,
+}
+
+#[derive(Serialize)]
+pub struct EmailListResponse {
+    pub items: Vec<EmailListItem>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+fn create_email_error(err: Box<dyn Error>) -> ApiError {
+    match err.downcast::<DieselError>() {
+        Ok(err) => ApiError::from(*err),
+        Err(err) => ApiError::BadRequest(format!("invalid recipients: {}", err)),
+    }
+}
+
+pub(crate) fn queue_email(email_id: i32, server_config: &ServerConfig) -> Result<(), ApiError> {
+    send_zmq_email_id(email_id, server_config).map_err(|err| {
+        error!("Cannot queue email {}: {}", email_id, err);
+        ApiError::Internal("cannot queue email for sending".to_string())
+    })
+}
+
+#[get("/emails")]
+pub async fn api_emails(
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+    query: web::Query<EmailListQuery>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Read)?;
+
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(50).clamp(1, 200);
+
+    let mut conn = db_connection(&pool)?;
+
+    let (emails, total) = get_hub_email_summaries(
+        &mut conn,
+        user.hub_id,
+        &EmailFilter::default(),
+        page,
+        per_page,
+    )?;
+
+    Ok(HttpResponse::Ok().json(EmailListResponse {
+        items: emails
+            .into_iter()
+            .map(|(email, num_recipients)| EmailListItem {
+                email,
+                num_recipients,
+            })
+            .collect(),
+        total,
+        page,
+        per_page,
+    }))
+}
+
+#[post("/emails")]
+pub async fn api_emails_create(
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+    server_config: web::Data<ServerConfig>,
+    web::Json(payload): web::Json<EmailPayload>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Send)?;
+
+    if payload.message.trim().is_empty() {
+        return Err(ApiError::BadRequest("message is required".to_string()));
+    }
+    if payload.recipients.is_empty() {
+        return Err(ApiError::BadRequest("recipients are required".to_string()));
+    }
+
+    let mut conn = db_connection(&pool)?;
+
+    let (email, audience) = create_email(
+        &mut conn,
+        payload.subject.as_deref(),
+        &payload.message,
+        &payload.recipients,
+        &payload.exclude,
+        None,
+        None,
+        None,
+        user.hub_id,
+    )
+    .map_err(create_email_error)?;
+
+    queue_email(email.id, &server_config)?;
+
+    let recipients = get_email_recipients(&mut conn, email.id)?;
+
+    Ok(HttpResponse::Created().json(EmailResponse {
+        audience: Some(audience),
+        ..EmailResponse::new(email, recipients)
+    }))
+}
+
+/// Who an email with these recipients would go to, without creating it.
+#[post("/emails/preview")]
+pub async fn api_emails_preview(
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+    web::Json(payload): web::Json<AudiencePayload>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Send)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    let audience = resolve_audience(
+        &mut conn,
+        user.hub_id,
+        &payload.recipients,
+        &payload.exclude,
+    )
+    .map_err(create_email_error)?;
+
+    Ok(HttpResponse::Ok().json(audience.summary))
+}
+
+#[get("/emails/{email_id}")]
+pub async fn api_emails_get(
+    email_id: web::Path<i32>,
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Read)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    let email = hub_email(&mut conn, user.hub_id, email_id.into_inner())?;
+    let recipients = get_email_recipients(&mut conn, email.id)?;
+
+    Ok(HttpResponse::Ok().json(EmailResponse::new(email, recipients)))
+}
+
+#[post("/emails/{email_id}/retry")]
+pub async fn api_emails_retry(
+    email_id: web::Path<i32>,
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+    server_config: web::Data<ServerConfig>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Send)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    let email = hub_email(&mut conn, user.hub_id, email_id.into_inner())?;
+
+    queue_email(email.id, &server_config)?;
+    reset_email_sent_and_opened_status(&mut conn, email.id)?;
+
+    let email = get_email(&mut conn, email.id)?;
+    let recipients = get_email_recipients(&mut conn, email.id)?;
+
+    Ok(HttpResponse::Accepted().json(EmailResponse::new(email, recipients)))
+}
+
+#[delete("/emails/{email_id}")]
+pub async fn api_emails_delete(
+    email_id: web::Path<i32>,
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Send)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    remove_email(&mut conn, email_id.into_inner(), user.hub_id)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
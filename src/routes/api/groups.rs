@@ -0,0 +1,135 @@
+use actix_web::{HttpResponse, delete, get, post, put, web};
+use serde::Serialize;
+
+use crate::db::DbPool;
+use crate::forms::api::GroupPayload;
+use crate::models::api_key::ApiScope;
+use crate::models::recipient::{Group, Recipient};
+use crate::repository::recipient::{
+    create_group, delete_group, get_hub_group_recipients, update_group,
+};
+use crate::routes::api::{ApiError, ApiUser, db_connection};
+
+#[derive(Serialize)]
+pub struct GroupResponse {
+    #[serde(flatten)]
+    pub group: Group,
+    pub recipients: Vec<i32>,
+}
+
+impl From<(Group, Vec<Recipient>)> for GroupResponse {
+    fn from((group, recipients): (Group, Vec<Recipient>)) -> Self {
+        Self {
+            group,
+            recipients: recipients.iter().map(|recipient| recipient.id).collect(),
+        }
+    }
+}
+
+fn validate_payload(payload: &GroupPayload) -> Result<&str, ApiError> {
+    match payload.name.trim() {
+        "" => Err(ApiError::BadRequest("name is required".to_string())),
+        name => Ok(name),
+    }
+}
+
+fn load_group(
+    conn: &mut diesel::SqliteConnection,
+    hub_id: i32,
+    group_id: i32,
+) -> Result<GroupResponse, ApiError> {
+    get_hub_group_recipients(conn, hub_id)?
+        .into_iter()
+        .find(|(group, _)| group.id == group_id)
+        .map(GroupResponse::from)
+        .ok_or(ApiError::NotFound)
+}
+
+#[get("/groups")]
+pub async fn api_groups(user: ApiUser, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Read)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    let groups = get_hub_group_recipients(&mut conn, user.hub_id)?
+        .into_iter()
+        .map(GroupResponse::from)
+        .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(groups))
+}
+
+#[post("/groups")]
+pub async fn api_groups_create(
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+    web::Json(payload): web::Json<GroupPayload>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Recipients)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    let group = create_group(&mut conn, user.hub_id, validate_payload(&payload)?)?;
+
+    Ok(HttpResponse::Created().json(GroupResponse {
+        group,
+        recipients: vec![],
+    }))
+}
+
+#[get("/groups/{group_id}")]
+pub async fn api_groups_get(
+    group_id: web::Path<i32>,
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Read)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    let group = load_group(&mut conn, user.hub_id, group_id.into_inner())?;
+
+    Ok(HttpResponse::Ok().json(group))
+}
+
+#[put("/groups/{group_id}")]
+pub async fn api_groups_update(
+    group_id: web::Path<i32>,
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+    web::Json(payload): web::Json<GroupPayload>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Recipients)?;
+
+    let mut conn = db_connection(&pool)?;
+    let group_id = group_id.into_inner();
+
+    if update_group(
+        &mut conn,
+        user.hub_id,
+        group_id,
+        validate_payload(&payload)?,
+    )? == 0
+    {
+        return Err(ApiError::NotFound);
+    }
+
+    let group = load_group(&mut conn, user.hub_id, group_id)?;
+
+    Ok(HttpResponse::Ok().json(group))
+}
+
+#[delete("/groups/{group_id}")]
+pub async fn api_groups_delete(
+    group_id: web::Path<i32>,
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Recipients)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    delete_group(&mut conn, user.hub_id, group_id.into_inner())?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,112 @@
+use actix_web::{HttpResponse, get, post, web};
+use serde::Serialize;
+
+use crate::db::DbPool;
+use crate::forms::api::MessagePayload;
+use crate::models::api_key::ApiScope;
+use crate::models::config::ServerConfig;
+use crate::models::email::{Email, EmailRecipient};
+use crate::models::recipient::normalize_email;
+use crate::models::template::describe_error;
+use crate::repository::email::{create_transactional_email, get_email, get_email_recipients};
+use crate::repository::template::get_hub_template;
+use crate::routes::api::emails::queue_email;
+use crate::routes::api::{ApiError, ApiUser, db_connection};
+
+/// Delivery state of a transactional message.
+#[derive(Serialize)]
+pub struct MessageResponse {
+    pub id: i32,
+    pub to: String,
+    pub subject: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    /// `queued` until the worker picks the message up, then `sent` or `failed`.
+    pub status: &'static str,
+    pub updated_at: chrono::NaiveDateTime,
+    pub opened: bool,
+    pub replied: bool,
+}
+
+impl MessageResponse {
+    fn new(email: Email, recipient: EmailRecipient) -> Self {
+        let status = match (email.is_sent, recipient.is_sent) {
+            (false, _) => "queued",
+            (true, true) => "sent",
+            (true, false) => "failed",
+        };
+
+        Self {
+            id: email.id,
+            to: recipient.address,
+            subject: email.subject,
+            created_at: email.created_at,
+            status,
+            updated_at: recipient.updated_at,
+            opened: recipient.opened,
+            replied: recipient.replied,
+        }
+    }
+}
+
+#[post("/messages")]
+pub async fn api_messages_send(
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+    server_config: web::Data<ServerConfig>,
+    web::Json(payload): web::Json<MessagePayload>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Send)?;
+
+    let to = normalize_email(&payload.to)
+        .ok_or_else(|| ApiError::BadRequest("to is invalid".to_string()))?;
+    if !payload.data.is_object() {
+        return Err(ApiError::BadRequest("data must be an object".to_string()));
+    }
+
+    let mut conn = db_connection(&pool)?;
+
+    let template = match get_hub_template(&mut conn, user.hub_id, payload.template_id) {
+        Ok(template) => template,
+        Err(diesel::result::Error::NotFound) => {
+            return Err(ApiError::BadRequest(format!(
+                "unknown template {}",
+                payload.template_id
+            )));
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let (subject, body) = template.render(&payload.data).map_err(|err| {
+        ApiError::BadRequest(format!("cannot render template: {}", describe_error(&err)))
+    })?;
+
+    let (email, recipient) =
+        create_transactional_email(&mut conn, user.hub_id, &subject, &body, &to)?;
+
+    queue_email(email.id, &server_config)?;
+
+    Ok(HttpResponse::Accepted().json(MessageResponse::new(email, recipient)))
+}
+
+#[get("/messages/{message_id}")]
+pub async fn api_messages_get(
+    message_id: web::Path<i32>,
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Read)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    let email = get_email(&mut conn, message_id.into_inner())?;
+    if email.hub_id != user.hub_id || !email.is_transactional {
+        return Err(ApiError::NotFound);
+    }
+
+    let recipient = get_email_recipients(&mut conn, email.id)?
+        .into_iter()
+        .next()
+        .ok_or(ApiError::NotFound)?;
+
+    Ok(HttpResponse::Ok().json(MessageResponse::new(email, recipient)))
+}
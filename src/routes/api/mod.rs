@@ -0,0 +1,187 @@
+use std::future::ready;
+
+use actix_web::http::{StatusCode, header};
+use actix_web::{FromRequest, HttpRequest, HttpResponse, ResponseError, dev::Payload, web};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use futures_util::future::LocalBoxFuture;
+use log::error;
+use serde::Serialize;
+
+use crate::db::{DbConnection, DbPool, get_db_connection};
+use crate::models::api_key::{ApiKey, ApiScope};
+use crate::models::auth::AuthenticatedUser;
+use crate::repository::api_key::{get_active_api_key, set_api_key_used};
+
+pub mod emails;
+pub mod groups;
+pub mod messages;
+pub mod recipients;
+pub mod stats;
+pub mod templates;
+
+/// Errors returned by the JSON API as `{"error": {"code": ..., "message": ...}}`.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("authentication required")]
+    Unauthorized,
+    #[error("insufficient permissions")]
+    Forbidden,
+    #[error("resource not found")]
+    NotFound,
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    Internal(String),
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse<'a> {
+    error: ErrorBody<'a>,
+}
+
+impl ApiError {
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::Forbidden => "forbidden",
+            ApiError::NotFound => "not_found",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden => StatusCode::FORBIDDEN,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorResponse {
+            error: ErrorBody {
+                code: self.code(),
+                message: self.to_string(),
+            },
+        })
+    }
+}
+
+impl From<DieselError> for ApiError {
+    fn from(err: DieselError) -> Self {
+        match err {
+            DieselError::NotFound => ApiError::NotFound,
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
+                ApiError::Conflict(info.message().to_string())
+            }
+            DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, info) => {
+                ApiError::Conflict(info.message().to_string())
+            }
+            err => {
+                error!("Database error: {}", err);
+                ApiError::Internal("database error".to_string())
+            }
+        }
+    }
+}
+
+/// Hub the API request acts on, resolved from a Bearer API key or the logged in user.
+pub struct ApiUser {
+    pub hub_id: i32,
+    pub scopes: Vec<ApiScope>,
+}
+
+impl ApiUser {
+    pub fn require(&self, scope: ApiScope) -> Result<(), ApiError> {
+        match self.scopes.contains(&scope) {
+            true => Ok(()),
+            false => Err(ApiError::Forbidden),
+        }
+    }
+}
+
+fn authenticate_api_key(pool: &DbPool, token: &str) -> Result<ApiUser, ApiError> {
+    let (prefix, secret) = ApiKey::parse_token(token).ok_or(ApiError::Unauthorized)?;
+
+    let mut conn = db_connection(pool)?;
+    let api_key = get_active_api_key(&mut conn, prefix)?.ok_or(ApiError::Unauthorized)?;
+
+    if !bcrypt::verify(secret, &api_key.key_hash).unwrap_or(false) {
+        return Err(ApiError::Unauthorized);
+    }
+
+    if let Err(err) = set_api_key_used(&mut conn, api_key.id) {
+        error!("Cannot update API key {} usage: {}", api_key.id, err);
+    }
+
+    Ok(ApiUser {
+        hub_id: api_key.hub_id,
+        scopes: api_key.get_scopes(),
+    })
+}
+
+impl FromRequest for ApiUser {
+    type Error = ApiError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::to_string);
+
+        // Without a key the browser session is used, emailers get every scope
+        let Some(token) = token else {
+            let user = AuthenticatedUser::from_request(req, payload).into_inner();
+            return Box::pin(ready(match user {
+                Ok(user) if user.roles.iter().any(|r| r == "emailer") => Ok(ApiUser {
+                    hub_id: user.hub_id,
+                    scopes: ApiScope::ALL.to_vec(),
+                }),
+                Ok(_) => Err(ApiError::Forbidden),
+                Err(_) => Err(ApiError::Unauthorized),
+            }));
+        };
+
+        let pool = req.app_data::<web::Data<DbPool>>().cloned();
+
+        Box::pin(async move {
+            let pool =
+                pool.ok_or_else(|| ApiError::Internal("database unavailable".to_string()))?;
+
+            // bcrypt is slow on purpose, keep it off the async workers
+            web::block(move || authenticate_api_key(&pool, &token))
+                .await
+                .map_err(|err| ApiError::Internal(err.to_string()))?
+        })
+    }
+}
+
+fn db_connection(pool: &DbPool) -> Result<DbConnection, ApiError> {
+    get_db_connection(pool).ok_or_else(|| ApiError::Internal("database unavailable".to_string()))
+}
+
+/// Handler for malformed JSON bodies, registered with `web::JsonConfig`.
+pub fn json_error_handler(
+    err: actix_web::error::JsonPayloadError,
+    _req: &HttpRequest,
+) -> actix_web::Error {
+    ApiError::BadRequest(err.to_string()).into()
+}
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+
+use actix_web::{HttpResponse, delete, get, post, put, web};
+use diesel::Connection;
+use serde::Serialize;
+
+use crate::db::DbPool;
+use crate::forms::api::{RecipientPayload, RecipientSearchQuery};
+use crate::models::api_key::ApiScope;
+use crate::models::recipient::{Recipient, normalize_email};
+use crate::repository::recipient::{
+    RecipientSearch, RecipientSort, RecipientWithFieldsAndGroups, create_recipient,
+    delete_recipient, get_hub_all_groups, get_hub_all_recipients, get_hub_recipient_by_email,
+    get_recipient, get_recipient_fields, get_recipient_group_ids, save_recipient,
+    search_hub_recipients,
+};
+use crate::routes::api::{ApiError, ApiUser, db_connection};
+
+#[derive(Serialize)]
+pub struct RecipientResponse {
+    #[serde(flatten)]
+    pub recipient: Recipient,
+    pub active: bool,
+    pub fields: HashMap<String, String>,
+    pub groups: Vec<i32>,
+}
+
+impl From<RecipientWithFieldsAndGroups> for RecipientResponse {
+    fn from((recipient, fields, groups): RecipientWithFieldsAndGroups) -> Self {
+        let mut groups = groups.into_iter().map(|group| group.id).collect::<Vec<_>>();
+        groups.sort_unstable();
+        Self {
+            active: recipient.unsubscribed_at.is_none(),
+            recipient,
+            fields,
+            groups,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RecipientSearchResponse {
+    pub items: Vec<RecipientResponse>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+fn load_recipient(
+    conn: &mut diesel::SqliteConnection,
+    hub_id: i32,
+    recipient_id: i32,
+) -> Result<RecipientResponse, ApiError> {
+    let recipient = get_recipient(conn, recipient_id)?;
+    if recipient.hub_id != hub_id {
+        return Err(ApiError::NotFound);
+    }
+
+    let fields = get_recipient_fields(conn, recipient_id)?
+        .into_iter()
+        .map(|field| (field.field, field.value))
+        .collect();
+    let mut groups = get_recipient_group_ids(conn, recipient_id)?
+        .into_iter()
+        .collect::<Vec<_>>();
+    groups.sort_unstable();
+
+    Ok(RecipientResponse {
+        active: recipient.unsubscribed_at.is_none(),
+        recipient,
+        fields,
+        groups,
+    })
+}
+
+/// Checks the payload, replacing the address with its normalized form.
+fn validate_payload(
+    conn: &mut diesel::SqliteConnection,
+    hub_id: i32,
+    payload: &mut RecipientPayload,
+    recipient_id: Option<i32>,
+) -> Result<(), ApiError> {
+    if payload.name.trim().is_empty() {
+        return Err(ApiError::BadRequest("name is required".to_string()));
+    }
+    payload.email = normalize_email(&payload.email)
+        .ok_or_else(|| ApiError::BadRequest("email is invalid".to_string()))?;
+    if let Some(other) = get_hub_recipient_by_email(conn, hub_id, &payload.email)?
+        && Some(other.id) != recipient_id
+    {
+        return Err(ApiError::Conflict(format!(
+            "recipient {} already has this email",
+            other.id
+        )));
+    }
+
+    let hub_groups = get_hub_all_groups(conn, hub_id)?;
+    if let Some(group) = payload
+        .groups
+        .iter()
+        .find(|group| !hub_groups.iter().any(|hub_group| hub_group.id == **group))
+    {
+        return Err(ApiError::BadRequest(format!("unknown group {}", group)));
+    }
+    if let Some(group) = hub_groups
+        .iter()
+        .find(|hub_group| hub_group.rules.is_some() && payload.groups.contains(&hub_group.id))
+    {
+        return Err(ApiError::BadRequest(format!(
+            "group {} is a smart group, its members are defined by its rules",
+            group.id
+        )));
+    }
+
+    Ok(())
+}
+
+fn save_payload(
+    conn: &mut diesel::SqliteConnection,
+    recipient_id: i32,
+    payload: &RecipientPayload,
+) -> Result<(), ApiError> {
+    let (fields, values): (Vec<&str>, Vec<&str>) = payload
+        .fields
+        .iter()
+        .map(|(field, value)| (field.as_str(), value.as_str()))
+        .unzip();
+
+    save_recipient(
+        conn,
+        recipient_id,
+        payload.name.trim(),
+        &payload.email,
+        payload.active,
+        &payload.groups,
+        &fields,
+        &values,
+    )?;
+
+    Ok(())
+}
+
+#[get("/recipients")]
+pub async fn api_recipients(
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Read)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    let recipients = get_hub_all_recipients(&mut conn, user.hub_id)?
+        .into_iter()
+        .map(RecipientResponse::from)
+        .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(recipients))
+}
+
+#[get("/recipients/search")]
+pub async fn api_recipients_search(
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+    query: web::Query<RecipientSearchQuery>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Read)?;
+
+    let sort = match query.sort.as_deref() {
+        Some(sort) => RecipientSort::parse(sort)
+            .ok_or_else(|| ApiError::BadRequest(format!("unknown sort {}", sort)))?,
+        None => RecipientSort::default(),
+    };
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(50).clamp(1, 200);
+
+    let mut conn = db_connection(&pool)?;
+
+    let search = RecipientSearch {
+        query: query.q.as_deref(),
+        group: query.group,
+        active: query.active,
+        sort,
+    };
+    let (recipients, total) =
+        search_hub_recipients(&mut conn, user.hub_id, &search, page, per_page)?;
+
+    Ok(HttpResponse::Ok().json(RecipientSearchResponse {
+        items: recipients
+            .into_iter()
+            .map(RecipientResponse::from)
+            .collect(),
+        total,
+        page,
+        per_page,
+    }))
+}
+
+#[post("/recipients")]
+pub async fn api_recipients_create(
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+    web::Json(mut payload): web::Json<RecipientPayload>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Recipients)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    validate_payload(&mut conn, user.hub_id, &mut payload, None)?;
+
+    let recipient = conn.transaction::<_, ApiError, _>(|conn| {
+        let recipient = create_recipient(conn, user.hub_id, payload.name.trim(), &payload.email)?;
+        save_payload(conn, recipient.id, &payload)?;
+        load_recipient(conn, user.hub_id, recipient.id)
+    })?;
+
+    Ok(HttpResponse::Created().json(recipient))
+}
+
+#[get("/recipients/{recipient_id}")]
+pub async fn api_recipients_get(
+    recipient_id: web::Path<i32>,
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Read)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    let recipient = load_recipient(&mut conn, user.hub_id, recipient_id.into_inner())?;
+
+    Ok(HttpResponse::Ok().json(recipient))
+}
+
+#[put("/recipients/{recipient_id}")]
+pub async fn api_recipients_update(
+    recipient_id: web::Path<i32>,
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+    web::Json(mut payload): web::Json<RecipientPayload>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Recipients)?;
+
+    let mut conn = db_connection(&pool)?;
+    let recipient_id = recipient_id.into_inner();
+
+    // Checks that the recipient belongs to the hub before changing it
+    load_recipient(&mut conn, user.hub_id, recipient_id)?;
+    validate_payload(&mut conn, user.hub_id, &mut payload, Some(recipient_id))?;
+
+    let recipient = conn.transaction::<_, ApiError, _>(|conn| {
+        save_payload(conn, recipient_id, &payload)?;
+        load_recipient(conn, user.hub_id, recipient_id)
+    })?;
+
+    Ok(HttpResponse::Ok().json(recipient))
+}
+
+#[delete("/recipients/{recipient_id}")]
+pub async fn api_recipients_delete(
+    recipient_id: web::Path<i32>,
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Recipients)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    delete_recipient(&mut conn, user.hub_id, recipient_id.into_inner())?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,70 @@
+use actix_web::{HttpResponse, get, web};
+use serde::Serialize;
+
+use crate::db::DbPool;
+use crate::forms::api::StatsQuery;
+use crate::models::api_key::ApiScope;
+use crate::models::stats::{CampaignStats, GroupStats, PeriodStats, StatCounts, SubjectStats};
+use crate::repository::stats::{
+    StatsFilter, StatsInterval, get_hub_campaign_stats, get_hub_group_stats, get_hub_period_stats,
+    get_hub_stats_totals, get_hub_top_subjects,
+};
+use crate::routes::api::{ApiError, ApiUser, db_connection};
+
+/// Latest campaigns listed in the statistics.
+pub(crate) const STATS_CAMPAIGNS: i64 = 50;
+/// Best subjects listed in the statistics.
+pub(crate) const STATS_SUBJECTS: i64 = 10;
+
+#[derive(Serialize)]
+pub struct StatsResponse {
+    pub totals: StatCounts,
+    pub timeline: Vec<PeriodStats>,
+    pub campaigns: Vec<CampaignStats>,
+    pub groups: Vec<GroupStats>,
+    pub subjects: Vec<SubjectStats>,
+}
+
+fn parse_date(name: &str, value: &Option<String>) -> Result<Option<chrono::NaiveDate>, ApiError> {
+    match value
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+    {
+        Some(value) => chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .map(Some)
+            .map_err(|_| ApiError::BadRequest(format!("invalid {} date {}", name, value))),
+        None => Ok(None),
+    }
+}
+
+#[get("/stats")]
+pub async fn api_stats(
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+    query: web::Query<StatsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Read)?;
+
+    let interval = match query.interval.as_deref() {
+        Some(interval) => StatsInterval::parse(interval)
+            .ok_or_else(|| ApiError::BadRequest(format!("unknown interval {}", interval)))?,
+        None => StatsInterval::default(),
+    };
+    let filter = StatsFilter {
+        since: parse_date("since", &query.since)?.and_then(|date| date.and_hms_opt(0, 0, 0)),
+        until: parse_date("until", &query.until)?
+            .and_then(|date| date.succ_opt())
+            .and_then(|date| date.and_hms_opt(0, 0, 0)),
+    };
+
+    let mut conn = db_connection(&pool)?;
+
+    Ok(HttpResponse::Ok().json(StatsResponse {
+        totals: get_hub_stats_totals(&mut conn, user.hub_id, &filter)?,
+        timeline: get_hub_period_stats(&mut conn, user.hub_id, &filter, interval)?,
+        campaigns: get_hub_campaign_stats(&mut conn, user.hub_id, &filter, STATS_CAMPAIGNS)?,
+        groups: get_hub_group_stats(&mut conn, user.hub_id, &filter)?,
+        subjects: get_hub_top_subjects(&mut conn, user.hub_id, &filter, STATS_SUBJECTS)?,
+    }))
+}
@@ -0,0 +1,117 @@
+use actix_web::{HttpResponse, delete, get, post, put, web};
+
+use crate::db::DbPool;
+use crate::forms::api::TemplatePayload;
+use crate::models::api_key::ApiScope;
+use crate::models::template::{MessageTemplate, NewMessageTemplate, describe_error};
+use crate::repository::template::{
+    create_template, delete_template, get_hub_template, get_hub_templates, update_template,
+};
+use crate::routes::api::{ApiError, ApiUser, db_connection};
+
+fn validate_payload(payload: &TemplatePayload) -> Result<(), ApiError> {
+    if payload.name.trim().is_empty() {
+        return Err(ApiError::BadRequest("name is required".to_string()));
+    }
+    if payload.body.trim().is_empty() {
+        return Err(ApiError::BadRequest("body is required".to_string()));
+    }
+
+    MessageTemplate::validate(&payload.subject, &payload.body)
+        .map_err(|err| ApiError::BadRequest(format!("invalid template: {}", describe_error(&err))))
+}
+
+#[get("/templates")]
+pub async fn api_templates(
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Read)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    let templates = get_hub_templates(&mut conn, user.hub_id)?;
+
+    Ok(HttpResponse::Ok().json(templates))
+}
+
+#[post("/templates")]
+pub async fn api_templates_create(
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+    web::Json(payload): web::Json<TemplatePayload>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Send)?;
+    validate_payload(&payload)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    let now = chrono::Utc::now().naive_utc();
+    let template = create_template(
+        &mut conn,
+        &NewMessageTemplate {
+            hub_id: user.hub_id,
+            name: payload.name.trim(),
+            subject: &payload.subject,
+            body: &payload.body,
+            created_at: now,
+            updated_at: now,
+        },
+    )?;
+
+    Ok(HttpResponse::Created().json(template))
+}
+
+#[get("/templates/{template_id}")]
+pub async fn api_templates_get(
+    template_id: web::Path<i32>,
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Read)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    let template = get_hub_template(&mut conn, user.hub_id, template_id.into_inner())?;
+
+    Ok(HttpResponse::Ok().json(template))
+}
+
+#[put("/templates/{template_id}")]
+pub async fn api_templates_update(
+    template_id: web::Path<i32>,
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+    web::Json(payload): web::Json<TemplatePayload>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Send)?;
+    validate_payload(&payload)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    let template = update_template(
+        &mut conn,
+        user.hub_id,
+        template_id.into_inner(),
+        payload.name.trim(),
+        &payload.subject,
+        &payload.body,
+    )?;
+
+    Ok(HttpResponse::Ok().json(template))
+}
+
+#[delete("/templates/{template_id}")]
+pub async fn api_templates_delete(
+    template_id: web::Path<i32>,
+    user: ApiUser,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    user.require(ApiScope::Send)?;
+
+    let mut conn = db_connection(&pool)?;
+
+    delete_template(&mut conn, user.hub_id, template_id.into_inner())?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -3,14 +3,23 @@ use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
 use tera::Context;
 
 use crate::db::{DbPool, get_db_connection};
-use crate::forms::groups::{AddGroupForm, AssignGroupRecipientForm, DeleteGroupForm};
+use crate::forms::groups::{
+    AddGroupForm, AssignGroupRecipientForm, DeleteGroupForm, GroupRulesForm, PreviewGroupRulesForm,
+};
 use crate::models::auth::AuthenticatedUser;
 use crate::models::config::ServerConfig;
 use crate::repository::recipient::{
-    assign_recipient_to_group, create_group, delete_group, get_hub_all_recipients,
-    get_hub_all_recipients_fields, get_hub_group_recipients, unassign_recipient_from_group,
+    RecipientSearch, assign_recipient_to_group, create_group, create_smart_group, delete_group,
+    get_hub_group, get_hub_groups_with_counts, search_hub_recipients,
+    unassign_recipient_from_group, update_group_rules,
 };
+use crate::repository::rules::{RuleContext, get_smart_group_members};
+use crate::routes::recipients::{PageParams, RECIPIENTS_PER_PAGE};
 use crate::routes::{alert_level_to_str, ensure_role, redirect, render_template};
+use crate::rules::Rule;
+
+/// Matching recipients listed under the count of a smart group preview.
+const GROUP_RULES_PREVIEW_MEMBERS: usize = 10;
 
 #[get("/groups")]
 pub async fn groups(
@@ -38,19 +47,155 @@ pub async fn groups(
     context.insert("current_page", "groups");
     context.insert("home_url", &server_config.auth_service_url);
 
-    if let Ok(recipients) = get_hub_all_recipients(&mut conn, user.hub_id) {
-        context.insert("recipients", &recipients);
-    }
-    if let Ok(groups) = get_hub_group_recipients(&mut conn, user.hub_id) {
+    // Smart groups come with their first page of members, evaluated on the rule context
+    // of the counts rather than loading the hub again for every group
+    let mut rule_context = None;
+    if let Ok(groups) = get_hub_groups_with_counts(&mut conn, user.hub_id, &mut rule_context) {
+        let groups = groups
+            .into_iter()
+            .map(|(group, num_members)| {
+                let members = match (group.rule(), &rule_context) {
+                    (Some(rule), Some(rule_context)) => {
+                        get_smart_group_members(rule_context, &rule, 1, RECIPIENTS_PER_PAGE).0
+                    }
+                    _ => vec![],
+                };
+                let pages = (num_members + RECIPIENTS_PER_PAGE - 1) / RECIPIENTS_PER_PAGE;
+                (group, num_members, members, pages)
+            })
+            .collect::<Vec<_>>();
         context.insert("groups", &groups);
     }
-    if let Ok(custom_fields) = get_hub_all_recipients_fields(&mut conn, user.hub_id) {
-        context.insert("custom_fields", &custom_fields);
-    }
 
     render_template("groups/groups.html", &context)
 }
 
+#[get("/groups/{group_id}/members")]
+pub async fn groups_members(
+    group_id: web::Path<i32>,
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+    page: web::Query<PageParams>,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "emailer", Some("/na")) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let group_id = group_id.into_inner();
+    let page = page.page.unwrap_or(1).max(1);
+    let search = RecipientSearch {
+        group: Some(group_id),
+        ..Default::default()
+    };
+
+    let mut context = Context::new();
+    context.insert("group_id", &group_id);
+    context.insert("page", &page);
+
+    let group = match get_hub_group(&mut conn, user.hub_id, group_id) {
+        Ok(group) => group,
+        Err(_) => return HttpResponse::NotFound().finish(),
+    };
+    context.insert("is_smart", &group.rules.is_some());
+
+    let members = match group.rules {
+        Some(_) => {
+            RuleContext::load(&mut conn, user.hub_id).map(|rule_context| match group.rule() {
+                Some(rule) => {
+                    get_smart_group_members(&rule_context, &rule, page, RECIPIENTS_PER_PAGE)
+                }
+                None => (vec![], 0),
+            })
+        }
+        None => search_hub_recipients(&mut conn, user.hub_id, &search, page, RECIPIENTS_PER_PAGE),
+    };
+    if let Ok((members, total)) = members {
+        context.insert("members", &members);
+        context.insert(
+            "pages",
+            &((total + RECIPIENTS_PER_PAGE - 1) / RECIPIENTS_PER_PAGE),
+        );
+    }
+
+    render_template("groups/members.html", &context)
+}
+
+#[post("/groups/rules/preview")]
+pub async fn groups_rules_preview(
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+    web::Form(form): web::Form<PreviewGroupRulesForm>,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "emailer", Some("/na")) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let mut context = Context::new();
+
+    match Rule::parse(&form.rules) {
+        Ok(rule) => {
+            if let Ok(rule_context) = RuleContext::load(&mut conn, user.hub_id) {
+                let members = rule_context.members(&rule).collect::<Vec<_>>();
+                context.insert("num_members", &members.len());
+                context.insert(
+                    "members",
+                    &members
+                        .iter()
+                        .take(GROUP_RULES_PREVIEW_MEMBERS)
+                        .map(|(recipient, _, _)| recipient)
+                        .collect::<Vec<_>>(),
+                );
+            }
+        }
+        Err(err) if !form.rules.trim().is_empty() => context.insert("error", &err),
+        Err(_) => {}
+    }
+
+    render_template("groups/rules_preview.html", &context)
+}
+
+#[post("/groups/rules")]
+pub async fn groups_rules(
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+    web::Form(form): web::Form<GroupRulesForm>,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "emailer", Some("/na")) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    if let Err(err) = Rule::parse(&form.rules) {
+        FlashMessage::error(format!("Ошибка в условии группы: {}", err)).send();
+        return redirect("/groups");
+    }
+
+    match update_group_rules(&mut conn, user.hub_id, form.id, form.rules.trim()) {
+        Ok(_) => {
+            FlashMessage::success("Условие группы сохранено.").send();
+        }
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при сохранении условия группы: {}", err)).send();
+        }
+    }
+
+    redirect("/groups")
+}
+
 #[post("/groups/add")]
 pub async fn groups_add(
     user: AuthenticatedUser,
@@ -66,7 +211,19 @@ pub async fn groups_add(
         None => return HttpResponse::InternalServerError().finish(),
     };
 
-    match create_group(&mut conn, user.hub_id, &form.name) {
+    let rules = form.rules.trim();
+    if !rules.is_empty()
+        && let Err(err) = Rule::parse(rules)
+    {
+        FlashMessage::error(format!("Ошибка в условии группы: {}", err)).send();
+        return redirect("/groups");
+    }
+
+    let result = match rules {
+        "" => create_group(&mut conn, user.hub_id, &form.name),
+        rules => create_smart_group(&mut conn, user.hub_id, &form.name, rules),
+    };
+    match result {
         Ok(_) => {
             FlashMessage::success("Группа успешно добавлена.").send();
         }
@@ -93,7 +250,7 @@ pub async fn groups_delete(
         None => return HttpResponse::InternalServerError().finish(),
     };
 
-    match delete_group(&mut conn, form.id) {
+    match delete_group(&mut conn, user.hub_id, form.id) {
         Ok(_) => {
             FlashMessage::success("Группа удалена.").send();
         }
@@ -121,6 +278,9 @@ pub async fn groups_assign(
     };
 
     match assign_recipient_to_group(&mut conn, form.recipient_id, form.group_id) {
+        Ok(0) => {
+            FlashMessage::error("Участники умной группы определяются условием.").send();
+        }
         Ok(_) => {
             FlashMessage::success("Группа назначена получателю.").send();
         }
@@ -1,3 +1,4 @@
+use std::collections::BTreeSet;
 use std::error::Error;
 
 use actix_identity::Identity;
@@ -5,32 +6,81 @@ use actix_multipart::form::MultipartForm;
 use actix_web::{HttpResponse, Responder, get, post, web};
 use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
 use log::error;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tera::Context;
 
 use crate::db::{DbPool, get_db_connection};
-use crate::forms::main::{DeleteEmailForm, SendEmailForm};
+use crate::export::ExportTable;
+use crate::forms::main::{AudiencePreviewPayload, DeleteEmailForm, SendEmailForm};
 use crate::models::auth::AuthenticatedUser;
 use crate::models::config::ServerConfig;
+use crate::models::webhook::WebhookEvent;
+use crate::repository::audience::{AudienceSummary, resolve_audience};
 use crate::repository::email::{
-    create_email, get_email, get_email_recipient, get_email_recipients,
-    get_hub_all_emails_with_recipients, remove_email, reset_email_sent_and_opened_status,
-    set_email_recipient_opened_status, update_email_num_opened,
+    EmailFilter, EmailStatusFilter, create_email, get_email, get_email_recipient,
+    get_email_recipient_replies, get_email_recipients, get_email_recipients_with_fields,
+    get_hub_email_summaries, remove_email, reset_email_sent_and_opened_status,
+    set_email_recipient_clicked_status, set_email_recipient_opened_status, update_email_num_opened,
 };
-use crate::repository::recipient::{
-    get_hub_all_groups, get_hub_all_recipients, get_hub_all_recipients_fields,
+use crate::repository::hub::get_hub;
+use crate::repository::recipient::get_hub_all_groups;
+use crate::repository::webhook::queue_email_recipient_event;
+use crate::routes::{
+    ExportParams, alert_level_to_str, ensure_role, export_response, redirect, render_template,
 };
-use crate::routes::{alert_level_to_str, ensure_role, redirect, render_template};
 use crate::utils::{read_attachment_file, send_zmq_email_id};
 
+/// Campaigns shown on one page of the index.
+const EMAILS_PER_PAGE: i64 = 20;
+
+/// Latest campaigns offered as exclusions in the compose form.
+const EXCLUDE_RECENT_EMAILS: i64 = 20;
+
+#[derive(Deserialize)]
+struct ClickQueryParams {
+    url: String,
+}
+
 #[derive(Deserialize)]
 struct IndexQueryParams {
     retry: Option<i32>,
+    page: Option<i64>,
+}
+
+/// Campaign list filters, kept in the query string across pages.
+#[derive(Deserialize, Serialize)]
+struct EmailListParams {
+    since: Option<String>,
+    until: Option<String>,
+    status: Option<String>,
+    q: Option<String>,
+}
+
+impl EmailListParams {
+    fn date(value: &Option<String>) -> Option<chrono::NaiveDate> {
+        chrono::NaiveDate::parse_from_str(value.as_deref()?, "%Y-%m-%d").ok()
+    }
+
+    fn to_filter(&self) -> EmailFilter<'_> {
+        EmailFilter {
+            since: Self::date(&self.since).and_then(|date| date.and_hms_opt(0, 0, 0)),
+            until: Self::date(&self.until)
+                .and_then(|date| date.succ_opt())
+                .and_then(|date| date.and_hms_opt(0, 0, 0)),
+            status: self.status.as_deref().and_then(EmailStatusFilter::parse),
+            subject: self
+                .q
+                .as_deref()
+                .map(str::trim)
+                .filter(|subject| !subject.is_empty()),
+        }
+    }
 }
 
 #[get("/")]
 pub async fn index(
     params: web::Query<IndexQueryParams>,
+    list_params: web::Query<EmailListParams>,
     user: AuthenticatedUser,
     pool: web::Data<DbPool>,
     flash_messages: IncomingFlashMessages,
@@ -65,22 +115,182 @@ pub async fn index(
     context.insert("retry_recipients", &retry_recipients);
     context.insert("home_url", &server_config.auth_service_url);
 
-    if let Ok(recipients) = get_hub_all_recipients(&mut conn, user.hub_id) {
-        context.insert("recipients", &recipients);
-    }
     if let Ok(groups) = get_hub_all_groups(&mut conn, user.hub_id) {
         context.insert("groups", &groups);
     }
-    if let Ok(emails) = get_hub_all_emails_with_recipients(&mut conn, user.hub_id) {
-        context.insert("emails", &emails);
+    if let Ok((recent_emails, _)) = get_hub_email_summaries(
+        &mut conn,
+        user.hub_id,
+        &EmailFilter::default(),
+        1,
+        EXCLUDE_RECENT_EMAILS,
+    ) {
+        context.insert("recent_emails", &recent_emails);
     }
-    if let Ok(custom_fields) = get_hub_all_recipients_fields(&mut conn, user.hub_id) {
-        context.insert("custom_fields", &custom_fields);
+
+    let page = params.page.unwrap_or(1).max(1);
+    if let Ok((emails, total)) = get_hub_email_summaries(
+        &mut conn,
+        user.hub_id,
+        &list_params.to_filter(),
+        page,
+        EMAILS_PER_PAGE,
+    ) {
+        context.insert("emails", &emails);
+        context.insert("page", &page);
+        context.insert("pages", &((total + EMAILS_PER_PAGE - 1) / EMAILS_PER_PAGE));
+        context.insert("total_emails", &total);
     }
+    context.insert("filter", &*list_params);
+    context.insert(
+        "filter_query",
+        &serde_html_form::to_string(&*list_params).unwrap_or_default(),
+    );
 
     render_template("main/index.html", &context)
 }
 
+#[get("/email/{email_id}")]
+pub async fn email_details(
+    email_id: web::Path<i32>,
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "emailer", Some("/na")) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let email = match get_email(&mut conn, email_id.into_inner()) {
+        Ok(email) if email.hub_id == user.hub_id => email,
+        Ok(_) | Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(err) => {
+            error!("Failed to get email: {}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let recipients = match get_email_recipients(&mut conn, email.id) {
+        Ok(recipients) => recipients,
+        Err(err) => {
+            error!("Failed to get email recipients: {}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let mut context = Context::new();
+    context.insert("message", &email.message);
+    context.insert("recipients", &recipients);
+
+    render_template("main/email_details.html", &context)
+}
+
+fn yes_no(value: bool) -> String {
+    match value {
+        true => "да".to_string(),
+        false => "нет".to_string(),
+    }
+}
+
+fn format_time(value: Option<chrono::NaiveDateTime>) -> String {
+    value
+        .map(|value| value.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+#[get("/email/{email_id}/export")]
+pub async fn export_email(
+    email_id: web::Path<i32>,
+    params: web::Query<ExportParams>,
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "emailer", Some("/na")) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let email = match get_email(&mut conn, email_id.into_inner()) {
+        Ok(email) if email.hub_id == user.hub_id => email,
+        Ok(_) | Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(err) => {
+            error!("Failed to get email: {}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let recipients = match get_email_recipients_with_fields(&mut conn, &email) {
+        Ok(recipients) => recipients,
+        Err(err) => {
+            error!("Failed to get email recipients: {}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let fields = recipients
+        .iter()
+        .flat_map(|(_, _, fields)| fields.keys().cloned())
+        .collect::<BTreeSet<_>>();
+
+    let mut headers = vec!["Адрес".to_string(), "Имя".to_string()];
+    headers.extend(fields.iter().cloned());
+    headers.extend(
+        [
+            "Отправлено",
+            "Дата отправки",
+            "Открыто",
+            "Дата открытия",
+            "Ответил",
+            "Дата ответа",
+            "Дата перехода",
+            "Отписался",
+        ]
+        .map(String::from),
+    );
+
+    let mut table = ExportTable::new(headers);
+    for (email_recipient, recipient, recipient_fields) in recipients {
+        let mut row = vec![
+            email_recipient.address,
+            recipient
+                .map(|recipient| recipient.name)
+                .unwrap_or_default(),
+        ];
+        row.extend(
+            fields
+                .iter()
+                .map(|field| recipient_fields.get(field).cloned().unwrap_or_default()),
+        );
+        row.extend([
+            yes_no(email_recipient.is_sent),
+            format_time(email_recipient.sent_at),
+            yes_no(email_recipient.opened),
+            format_time(email_recipient.opened_at),
+            yes_no(email_recipient.replied),
+            format_time(email_recipient.replied_at),
+            format_time(email_recipient.clicked_at),
+            yes_no(email_recipient.unsubscribed),
+        ]);
+        table.rows.push(row);
+    }
+
+    export_response(
+        &table,
+        params.format(),
+        "Получатели",
+        &format!("email-{}", email.id),
+        "/",
+    )
+}
+
 #[post("/send_email")]
 pub async fn send_email(
     user: AuthenticatedUser,
@@ -114,18 +324,27 @@ pub async fn send_email(
         } else {
             (None, None, None)
         };
+    let exclude = form
+        .exclude
+        .as_ref()
+        .map(|exclude| exclude.0.clone())
+        .unwrap_or_default();
     match create_email(
         &mut conn,
         form.subject.0.as_deref(),
         &form.message,
         &form.recipients,
+        &exclude,
         attachment.as_deref(),
         attchment_name.as_deref(),
         attachement_mime.as_deref(),
         user.hub_id,
     ) {
-        Ok(email) => match send_zmq_email_id(email.id, &zmq_config) {
-            Ok(_) => HttpResponse::Ok().body("Сообщение создано."),
+        Ok((email, summary)) => match send_zmq_email_id(email.id, &zmq_config) {
+            Ok(_) => HttpResponse::Ok().body(format!(
+                "Сообщение создано. {}",
+                audience_summary_text(&summary)
+            )),
             Err(err) => HttpResponse::Ok().body(format!(
                 "Ошибка при добавлении сообщения в очередь: {}",
                 err
@@ -135,6 +354,57 @@ pub async fn send_email(
     }
 }
 
+/// Audience counts as one sentence, zero counts are left out.
+fn audience_summary_text(summary: &AudienceSummary) -> String {
+    let mut text = format!("Получателей: {}", summary.num_recipients);
+    for (label, count) in [
+        ("пропущено", summary.skipped.len()),
+        ("исключено", summary.num_excluded),
+        ("повторов", summary.num_duplicates),
+    ] {
+        if count > 0 {
+            text.push_str(&format!(", {}: {}", label, count));
+        }
+    }
+    text.push('.');
+    text
+}
+
+#[post("/send_email/preview")]
+pub async fn send_email_preview(
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+    web::Json(payload): web::Json<AudiencePreviewPayload>,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "emailer", Some("/na")) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let mut context = Context::new();
+
+    if !payload.recipients.is_empty() {
+        match resolve_audience(
+            &mut conn,
+            user.hub_id,
+            &payload.recipients,
+            &payload.exclude,
+        ) {
+            Ok(audience) => {
+                context.insert("summary_text", &audience_summary_text(&audience.summary));
+                context.insert("summary", &audience.summary);
+            }
+            Err(err) => context.insert("error", &err.to_string()),
+        }
+    }
+
+    render_template("main/audience_summary.html", &context)
+}
+
 #[post("/delete_email")]
 pub async fn delete_email(
     user: AuthenticatedUser,
@@ -238,9 +508,128 @@ pub async fn track_email(recipient_id: web::Path<i32>, pool: web::Data<DbPool>)
         return HttpResponse::InternalServerError().finish();
     }
 
+    // Only the first open is reported, mail clients load the pixel on every view
+    if !recipient.opened
+        && let Err(err) = queue_email_recipient_event(&mut conn, &recipient, WebhookEvent::Opened)
+    {
+        error!("Failed to queue opened webhook: {}", err);
+    }
+
     redirect("/assets/placeholder.png")
 }
 
+/// Records a click on a link rewritten by the worker and redirects to the original.
+#[get("/click/{recipient_id}")]
+pub async fn click_email(
+    recipient_id: web::Path<i32>,
+    params: web::Query<ClickQueryParams>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let recipient = match get_email_recipient(&mut conn, recipient_id.into_inner()) {
+        Ok(recipient) => recipient,
+        Err(err) => {
+            error!("Failed to get email recipient: {}", err);
+            return HttpResponse::NotFound().finish();
+        }
+    };
+    let email = match get_email(&mut conn, recipient.email_id) {
+        Ok(email) => email,
+        Err(err) => {
+            error!("Failed to get email: {}", err);
+            return HttpResponse::NotFound().finish();
+        }
+    };
+    let template = match get_hub(&mut conn, email.hub_id) {
+        Ok(hub) => hub.email_template.unwrap_or_default(),
+        Err(err) => {
+            error!("Failed to get hub: {}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    // Only links of the message are followed, the tracker is not an open redirect
+    let href = format!("href=\"{}\"", params.url);
+    if !email.message.contains(&href) && !template.contains(&href) {
+        return HttpResponse::NotFound().finish();
+    }
+
+    if set_email_recipient_clicked_status(&mut conn, recipient.id).is_err() {
+        error!("Failed to update recipient status");
+    } else if update_email_num_opened(&mut conn, recipient.email_id).is_err() {
+        error!("Failed to update email num_opened");
+    }
+
+    // Only the first click is reported, like the first open
+    if recipient.clicked_at.is_none()
+        && let Err(err) = queue_email_recipient_event(&mut conn, &recipient, WebhookEvent::Clicked)
+    {
+        error!("Failed to queue clicked webhook: {}", err);
+    }
+
+    redirect(&params.url.replace("&amp;", "&"))
+}
+
+#[get("/conversation/{email_recipient_id}")]
+pub async fn conversation(
+    email_recipient_id: web::Path<i32>,
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+    flash_messages: IncomingFlashMessages,
+    server_config: web::Data<ServerConfig>,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "emailer", Some("/na")) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let recipient = match get_email_recipient(&mut conn, email_recipient_id.into_inner()) {
+        Ok(recipient) => recipient,
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при поиске получателя: {}", err)).send();
+            return redirect("/");
+        }
+    };
+
+    let email = match get_email(&mut conn, recipient.email_id) {
+        Ok(email) if email.hub_id == user.hub_id => email,
+        Ok(_) => {
+            FlashMessage::error("Вы не можете просматривать эту переписку.").send();
+            return redirect("/");
+        }
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при поиске сообщения: {}", err)).send();
+            return redirect("/");
+        }
+    };
+
+    let alerts = flash_messages
+        .iter()
+        .map(|f| (f.content(), alert_level_to_str(&f.level())))
+        .collect::<Vec<_>>();
+    let mut context = Context::new();
+    context.insert("alerts", &alerts);
+    context.insert("current_user", &user);
+    context.insert("current_page", "index");
+    context.insert("home_url", &server_config.auth_service_url);
+    context.insert("email", &email);
+    context.insert("recipient", &recipient);
+
+    if let Ok(replies) = get_email_recipient_replies(&mut conn, recipient.id) {
+        context.insert("replies", &replies);
+    }
+
+    render_template("main/conversation.html", &context)
+}
+
 #[post("/logout")]
 pub async fn logout(user: Identity) -> impl Responder {
     user.logout();
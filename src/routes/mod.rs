@@ -3,14 +3,18 @@ use actix_web::http::header;
 use actix_web_flash_messages::{FlashMessage, Level};
 use lazy_static::lazy_static;
 use log::error;
+use serde::Deserialize;
 use tera::{Context, Tera};
 
+use crate::export::{ExportFormat, ExportTable};
 use crate::models::auth::AuthenticatedUser;
 
+pub mod api;
 pub mod groups;
 pub mod main;
 pub mod recipients;
 pub mod settings;
+pub mod stats;
 
 lazy_static! {
     pub static ref TEMPLATES: Tera = {
@@ -24,7 +28,6 @@ lazy_static! {
     };
 }
 
-
 fn alert_level_to_str(level: &Level) -> &'static str {
     match level {
         Level::Error => "danger",
@@ -53,6 +56,46 @@ fn ensure_role(
     }
 }
 
+#[derive(Deserialize)]
+pub(crate) struct ExportParams {
+    /// `csv` or `xlsx`, CSV when missing or unknown.
+    format: Option<String>,
+}
+
+impl ExportParams {
+    fn format(&self) -> ExportFormat {
+        self.format
+            .as_deref()
+            .and_then(ExportFormat::parse)
+            .unwrap_or_default()
+    }
+}
+
+/// Sends the table as a file download, or flashes the error and goes back to `redirect_url`.
+fn export_response(
+    table: &ExportTable,
+    format: ExportFormat,
+    sheet: &str,
+    file_name: &str,
+    redirect_url: &str,
+) -> HttpResponse {
+    match table.write(format, sheet) {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(format.content_type())
+            .insert_header(header::ContentDisposition::attachment(format!(
+                "{}.{}",
+                file_name,
+                format.extension()
+            )))
+            .body(body),
+        Err(e) => {
+            error!("Failed to export {}: {}", file_name, e);
+            FlashMessage::error(format!("Ошибка при выгрузке: {}", e)).send();
+            redirect(redirect_url)
+        }
+    }
+}
+
 fn render_template(template: &str, context: &Context) -> HttpResponse {
     HttpResponse::Ok().body(TEMPLATES.render(template, context).unwrap_or_else(|e| {
         error!("Failed to render template {}': {}", template, e);
@@ -1,22 +1,104 @@
+use std::collections::BTreeSet;
 use std::io::Read;
 
 use actix_multipart::form::MultipartForm;
 use actix_web::{HttpResponse, Responder, get, post, web};
 use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use serde::{Deserialize, Serialize};
 use tera::Context;
 
-use crate::db::{DbPool, get_db_connection};
+use crate::db::{DbConnection, DbPool, get_db_connection};
+use crate::export::{ExportFormat, ExportTable};
 use crate::forms::recipients::{
-    AddRecipientForm, DeleteRecipientForm, SaveRecipientForm, UploadRecipientsForm,
+    AddRecipientForm, DeleteRecipientForm, ImportOptionsForm, MergeRecipientsForm,
+    SaveRecipientForm, UploadRecipientsForm,
+};
+use crate::import::{
+    ColumnTarget, ImportDelimiter, ImportEncoding, ImportMode, ImportOptions, ImportStatus,
+    ParsedImport, RECIPIENT_CSV_COLUMNS, join_group_names, parse_recipients_file,
 };
 use crate::models::auth::AuthenticatedUser;
 use crate::models::config::ServerConfig;
+use crate::models::recipient::{RecipientImport, normalize_email};
+use crate::repository::duplicate::{get_hub_duplicate_sets, merge_recipients};
+use crate::repository::email::get_recipient_email_history;
+use crate::repository::import::{
+    create_recipient_import, delete_recipient_import, get_recipient_import, import_recipients,
+    preview_recipients_import, update_recipient_import_options,
+};
 use crate::repository::recipient::{
-    clean_all_recipients_and_groups, create_recipient, delete_recipient, get_hub_all_groups,
-    get_hub_all_recipients, get_recipient, get_recipient_fields, get_recipient_group_ids,
-    save_recipient, update_recipients_from_csv,
+    RecipientSearch, RecipientSort, clean_all_recipients_and_groups, create_recipient,
+    delete_recipient, get_hub_all_groups, get_hub_duplicate_emails, get_hub_filtered_recipients,
+    get_hub_recipient_by_email, get_recipient, get_recipient_fields, get_recipient_group_ids,
+    save_recipient, search_hub_recipients,
 };
-use crate::routes::{alert_level_to_str, ensure_role, redirect, render_template};
+use crate::routes::{alert_level_to_str, ensure_role, export_response, redirect, render_template};
+
+/// Recipients shown on one page of the list.
+pub(crate) const RECIPIENTS_PER_PAGE: i64 = 50;
+
+/// Valid rows shown before the import is confirmed, invalid rows are always listed.
+const IMPORT_PREVIEW_ROWS: usize = 100;
+
+#[derive(Deserialize)]
+pub(crate) struct PageParams {
+    pub page: Option<i64>,
+}
+
+/// Recipient list filters, kept in the query string across pages.
+#[derive(Deserialize, Serialize)]
+struct RecipientListParams {
+    q: Option<String>,
+    group: Option<String>,
+    /// `true` for subscribed and `false` for unsubscribed recipients only.
+    active: Option<String>,
+    sort: Option<String>,
+}
+
+impl RecipientListParams {
+    fn to_search(&self) -> RecipientSearch<'_> {
+        RecipientSearch {
+            query: self.q.as_deref(),
+            group: self.group.as_deref().and_then(|group| group.parse().ok()),
+            active: self
+                .active
+                .as_deref()
+                .and_then(|active| active.parse().ok()),
+            sort: self
+                .sort
+                .as_deref()
+                .and_then(RecipientSort::parse)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Inserts one page of the hub recipients matching the filters into the context.
+fn insert_recipient_list(
+    conn: &mut DbConnection,
+    context: &mut Context,
+    hub_id: i32,
+    params: &RecipientListParams,
+    page: Option<i64>,
+) {
+    let page = page.unwrap_or(1).max(1);
+
+    if let Ok((items, total)) =
+        search_hub_recipients(conn, hub_id, &params.to_search(), page, RECIPIENTS_PER_PAGE)
+    {
+        context.insert("recipients", &items);
+        context.insert("total_recipients", &total);
+        context.insert("page", &page);
+        context.insert(
+            "pages",
+            &((total + RECIPIENTS_PER_PAGE - 1) / RECIPIENTS_PER_PAGE),
+        );
+    }
+    context.insert(
+        "list_query",
+        &serde_html_form::to_string(params).unwrap_or_default(),
+    );
+}
 
 #[get("/recipients")]
 pub async fn recipients(
@@ -24,6 +106,8 @@ pub async fn recipients(
     flash_messages: IncomingFlashMessages,
     pool: web::Data<DbPool>,
     server_config: web::Data<ServerConfig>,
+    params: web::Query<RecipientListParams>,
+    page: web::Query<PageParams>,
 ) -> impl Responder {
     if let Err(response) = ensure_role(&user, "emailer", Some("/na")) {
         return response;
@@ -44,13 +128,125 @@ pub async fn recipients(
     context.insert("current_page", "recipients");
     context.insert("home_url", &server_config.auth_service_url);
 
-    if let Ok(recipients) = get_hub_all_recipients(&mut conn, user.hub_id) {
-        context.insert("recipients", &recipients);
+    if let Ok(groups) = get_hub_all_groups(&mut conn, user.hub_id) {
+        context.insert("groups", &groups);
     }
+    if let Ok(duplicates) = get_hub_duplicate_emails(&mut conn, user.hub_id) {
+        context.insert("duplicates", &duplicates);
+    }
+    context.insert("filter", &*params);
+    insert_recipient_list(&mut conn, &mut context, user.hub_id, &params, page.page);
 
     render_template("recipients/recipients.html", &context)
 }
 
+#[get("/recipients/list")]
+pub async fn recipients_list(
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+    params: web::Query<RecipientListParams>,
+    page: web::Query<PageParams>,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "emailer", Some("/na")) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let mut context = Context::new();
+    insert_recipient_list(&mut conn, &mut context, user.hub_id, &params, page.page);
+
+    render_template("recipients/list.html", &context)
+}
+
+/// Downloads the matching recipients in the format accepted by the CSV upload.
+#[get("/recipients/export")]
+pub async fn recipients_export(
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+    params: web::Query<RecipientListParams>,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "emailer", Some("/na")) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let items = match get_hub_filtered_recipients(&mut conn, user.hub_id, &params.to_search()) {
+        Ok(items) => items,
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при выгрузке получателей: {}", err)).send();
+            return redirect("/recipients");
+        }
+    };
+
+    let fields = items
+        .iter()
+        .flat_map(|(_, fields, _)| fields.keys().cloned())
+        .filter(|field| !RECIPIENT_CSV_COLUMNS.contains(&field.as_str()))
+        .collect::<BTreeSet<_>>();
+
+    let mut headers = RECIPIENT_CSV_COLUMNS.map(String::from).to_vec();
+    headers.extend(fields.iter().cloned());
+
+    let mut table = ExportTable::new(headers);
+    for (recipient, recipient_fields, groups) in items {
+        let mut groups = groups
+            .into_iter()
+            .map(|group| group.name)
+            .collect::<Vec<_>>();
+        groups.sort();
+
+        let mut row = vec![recipient.name, recipient.email, join_group_names(&groups)];
+        row.extend(
+            fields
+                .iter()
+                .map(|field| recipient_fields.get(field).cloned().unwrap_or_default()),
+        );
+        table.rows.push(row);
+    }
+
+    export_response(
+        &table,
+        ExportFormat::Csv,
+        "Получатели",
+        "recipients",
+        "/recipients",
+    )
+}
+
+/// Normalized address, unless it is invalid or belongs to another hub recipient, which is
+/// flashed.
+fn check_recipient_email(
+    conn: &mut DbConnection,
+    hub_id: i32,
+    email: &str,
+    recipient_id: Option<i32>,
+) -> Option<String> {
+    let Some(email) = normalize_email(email) else {
+        FlashMessage::error(format!("Некорректный адрес {}.", email.trim())).send();
+        return None;
+    };
+
+    match get_hub_recipient_by_email(conn, hub_id, &email) {
+        Ok(Some(other)) if Some(other.id) != recipient_id => {
+            FlashMessage::error(format!("Получатель с адресом {} уже есть.", other.email)).send();
+            None
+        }
+        Ok(_) => Some(email),
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при проверке адреса: {}", err)).send();
+            None
+        }
+    }
+}
+
 #[post("/recipients/add")]
 pub async fn recipients_add(
     user: AuthenticatedUser,
@@ -66,7 +262,12 @@ pub async fn recipients_add(
         None => return HttpResponse::InternalServerError().finish(),
     };
 
-    match create_recipient(&mut conn, user.hub_id, &form.name, &form.email) {
+    let email = match check_recipient_email(&mut conn, user.hub_id, &form.email, None) {
+        Some(email) => email,
+        None => return redirect("/recipients"),
+    };
+
+    match create_recipient(&mut conn, user.hub_id, form.name.trim(), &email) {
         Ok(_) => {
             FlashMessage::success("Получатель успешно добавлен.").send();
         }
@@ -93,7 +294,7 @@ pub async fn recipients_delete(
         None => return HttpResponse::InternalServerError().finish(),
     };
 
-    match delete_recipient(&mut conn, form.id) {
+    match delete_recipient(&mut conn, user.hub_id, form.id) {
         Ok(_) => {
             FlashMessage::success("Получатель удален.").send();
         }
@@ -143,25 +344,368 @@ pub async fn recipients_upload(
         None => return HttpResponse::InternalServerError().finish(),
     };
 
-    let mut csv_content = String::new();
+    let mut content = Vec::new();
+
+    if let Err(err) = form.csv.file.read_to_end(&mut content) {
+        FlashMessage::error(format!("Ошибка при чтении файла: {}", err)).send();
+        return redirect("/recipients");
+    }
+
+    let options = ImportOptions {
+        encoding: form
+            .encoding
+            .and_then(|encoding| ImportEncoding::parse(&encoding.0)),
+        delimiter: form
+            .delimiter
+            .and_then(|delimiter| ImportDelimiter::parse(&delimiter.0)),
+        mode: form
+            .mode
+            .and_then(|mode| ImportMode::parse(&mode.0))
+            .unwrap_or_default(),
+        assign_group: form
+            .assign_group
+            .map(|group| group.0.trim().to_string())
+            .filter(|group| !group.is_empty()),
+        ..Default::default()
+    };
+
+    if let Err(err) = parse_recipients_file(&content, &options) {
+        FlashMessage::error(format!("Ошибка при загрузке файла: {}", err)).send();
+        return redirect("/recipients");
+    }
+
+    let file_name = form.csv.file_name.unwrap_or_default();
+
+    match create_recipient_import(&mut conn, user.hub_id, &file_name, &content, &options) {
+        Ok(import) => redirect(&format!("/recipients/import/{}", import.id)),
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при загрузке файла: {}", err)).send();
+            redirect("/recipients")
+        }
+    }
+}
+
+/// Loads and parses the stored upload, flashing the reason when it cannot be used.
+fn load_import(
+    conn: &mut DbConnection,
+    hub_id: i32,
+    import_id: i32,
+) -> Option<(RecipientImport, ParsedImport)> {
+    let import = match get_recipient_import(conn, hub_id, import_id) {
+        Ok(import) => import,
+        Err(_) => {
+            FlashMessage::error("Загрузка не найдена, загрузите файл ещё раз.").send();
+            return None;
+        }
+    };
 
-    match form.csv.file.read_to_string(&mut csv_content) {
-        Ok(_) => match update_recipients_from_csv(&mut conn, user.hub_id, &csv_content) {
-            Ok(_) => {
-                FlashMessage::success("Файл успешно загружен.").send();
-            }
-            Err(err) => {
-                FlashMessage::error(format!("Ошибка при загрузке файла: {}", err)).send();
-            }
-        },
+    match parse_recipients_file(&import.content, &ImportOptions::of(&import)) {
+        Ok(parsed) => Some((import, parsed)),
         Err(err) => {
-            FlashMessage::error(format!("Ошибка при чтении файла: {}", err)).send();
+            FlashMessage::error(format!("Ошибка при загрузке файла: {}", err)).send();
+            None
         }
     }
+}
+
+#[get("/recipients/import/{import_id}")]
+pub async fn recipients_import_preview(
+    import_id: web::Path<i32>,
+    user: AuthenticatedUser,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<DbPool>,
+    server_config: web::Data<ServerConfig>,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "emailer", Some("/na")) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let (import, parsed) = match load_import(&mut conn, user.hub_id, *import_id) {
+        Some(loaded) => loaded,
+        None => return redirect("/recipients"),
+    };
+
+    let alerts = flash_messages
+        .iter()
+        .map(|f| (f.content(), alert_level_to_str(&f.level())))
+        .collect::<Vec<_>>();
+    let mut context = Context::new();
+    context.insert("alerts", &alerts);
+    context.insert("current_user", &user);
+    context.insert("current_page", "recipients");
+    context.insert("home_url", &server_config.auth_service_url);
+
+    let options = ImportOptions::of(&import);
+    context.insert(
+        "encoding",
+        &parsed.encoding.map(|encoding| encoding.label()),
+    );
+    context.insert(
+        "encoding_choice",
+        options
+            .encoding
+            .map_or("auto", |encoding| encoding.as_str()),
+    );
+    context.insert(
+        "encodings",
+        &ImportEncoding::ALL
+            .map(|encoding| (encoding.as_str(), encoding.label()))
+            .to_vec(),
+    );
+    context.insert(
+        "delimiter",
+        &parsed.delimiter.map(|delimiter| delimiter.label()),
+    );
+    context.insert(
+        "delimiter_choice",
+        options
+            .delimiter
+            .map_or("auto", |delimiter| delimiter.as_str()),
+    );
+    context.insert(
+        "delimiters",
+        &ImportDelimiter::ALL
+            .map(|delimiter| (delimiter.as_str(), delimiter.label()))
+            .to_vec(),
+    );
+    context.insert("sheet", &parsed.sheet);
+    context.insert("sheets", &parsed.sheets);
+    context.insert("columns", &parsed.columns);
+    context.insert(
+        "targets",
+        &ColumnTarget::ALL
+            .map(|target| (target.as_str(), target.label()))
+            .to_vec(),
+    );
+    context.insert("has_email_column", &parsed.has_column(ColumnTarget::Email));
+    context.insert("mode", options.mode.as_str());
+    context.insert(
+        "modes",
+        &ImportMode::ALL
+            .map(|mode| (mode.as_str(), mode.label()))
+            .to_vec(),
+    );
+    context.insert("assign_group", &options.assign_group);
+    if let Ok(groups) = get_hub_all_groups(&mut conn, user.hub_id) {
+        context.insert("groups", &groups);
+    }
+
+    let preview = match preview_recipients_import(&mut conn, user.hub_id, parsed.rows, &options) {
+        Ok(preview) => preview,
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при проверке файла: {}", err)).send();
+            return redirect("/recipients");
+        }
+    };
+
+    let (invalid_rows, valid_rows): (Vec<_>, Vec<_>) = preview
+        .rows
+        .into_iter()
+        .partition(|row| row.status == ImportStatus::Invalid);
+
+    context.insert("import_id", &import.id);
+    context.insert("file_name", &import.file_name);
+    context.insert("summary", &preview.summary);
+    context.insert("invalid_rows", &invalid_rows);
+    context.insert(
+        "rows",
+        &valid_rows
+            .iter()
+            .take(IMPORT_PREVIEW_ROWS)
+            .collect::<Vec<_>>(),
+    );
+
+    render_template("recipients/import.html", &context)
+}
+
+#[post("/recipients/import/{import_id}/options")]
+pub async fn recipients_import_options(
+    import_id: web::Path<i32>,
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+    form: web::Bytes,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "emailer", Some("/na")) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let preview_url = format!("/recipients/import/{}", import_id);
+
+    let form: ImportOptionsForm = match serde_html_form::from_bytes(&form) {
+        Ok(form) => form,
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при обработке формы: {}", err)).send();
+            return redirect(&preview_url);
+        }
+    };
+
+    let options = ImportOptions {
+        encoding: ImportEncoding::parse(&form.encoding),
+        delimiter: ImportDelimiter::parse(&form.delimiter),
+        sheet: form.sheet.filter(|sheet| !sheet.is_empty()),
+        columns: form
+            .header
+            .into_iter()
+            .zip(form.target)
+            .filter_map(|(header, target)| Some((header, ColumnTarget::parse(&target)?)))
+            .collect(),
+        mode: ImportMode::parse(&form.mode).unwrap_or_default(),
+        assign_group: Some(form.assign_group.trim().to_string()).filter(|group| !group.is_empty()),
+    };
+
+    if let Err(err) = update_recipient_import_options(&mut conn, user.hub_id, *import_id, &options)
+    {
+        FlashMessage::error(format!("Ошибка при сохранении настроек: {}", err)).send();
+    }
+
+    redirect(&preview_url)
+}
+
+#[post("/recipients/import/{import_id}")]
+pub async fn recipients_import(
+    import_id: web::Path<i32>,
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "emailer", Some("/na")) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let (import, parsed) = match load_import(&mut conn, user.hub_id, *import_id) {
+        Some(loaded) => loaded,
+        None => return redirect("/recipients"),
+    };
+
+    match import_recipients(
+        &mut conn,
+        user.hub_id,
+        parsed.rows,
+        &ImportOptions::of(&import),
+    ) {
+        Ok(summary) => {
+            let _ = delete_recipient_import(&mut conn, user.hub_id, import.id);
+            FlashMessage::success(format!(
+                "Файл загружен: добавлено {}, обновлено {}, без изменений {}, пропущено {}.",
+                summary.num_new, summary.num_updated, summary.num_unchanged, summary.num_invalid
+            ))
+            .send();
+        }
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при загрузке файла: {}", err)).send();
+        }
+    }
+
+    redirect("/recipients")
+}
+
+#[post("/recipients/import/{import_id}/cancel")]
+pub async fn recipients_import_cancel(
+    import_id: web::Path<i32>,
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "emailer", Some("/na")) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    if let Err(err) = delete_recipient_import(&mut conn, user.hub_id, *import_id) {
+        FlashMessage::error(format!("Ошибка при отмене загрузки: {}", err)).send();
+    }
 
     redirect("/recipients")
 }
 
+#[get("/recipients/duplicates")]
+pub async fn recipients_duplicates(
+    user: AuthenticatedUser,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<DbPool>,
+    server_config: web::Data<ServerConfig>,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "emailer", Some("/na")) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let alerts = flash_messages
+        .iter()
+        .map(|f| (f.content(), alert_level_to_str(&f.level())))
+        .collect::<Vec<_>>();
+    let mut context = Context::new();
+    context.insert("alerts", &alerts);
+    context.insert("current_user", &user);
+    context.insert("current_page", "recipients");
+    context.insert("home_url", &server_config.auth_service_url);
+
+    if let Ok(sets) = get_hub_duplicate_sets(&mut conn, user.hub_id) {
+        context.insert("duplicate_sets", &sets);
+    }
+
+    render_template("recipients/duplicates.html", &context)
+}
+
+#[post("/recipients/merge")]
+pub async fn recipients_merge(
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+    form: web::Bytes,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "emailer", Some("/na")) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let form: MergeRecipientsForm = match serde_html_form::from_bytes(&form) {
+        Ok(form) => form,
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при обработке формы: {}", err)).send();
+            return redirect("/recipients/duplicates");
+        }
+    };
+
+    match merge_recipients(&mut conn, user.hub_id, form.keep, &form.ids) {
+        Ok(0) => {
+            FlashMessage::warning("Не выбраны получатели для объединения.").send();
+        }
+        Ok(num_merged) => {
+            FlashMessage::success(format!("Объединено получателей: {}.", num_merged + 1)).send();
+        }
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при объединении получателей: {}", err)).send();
+        }
+    }
+
+    redirect("/recipients/duplicates")
+}
+
 #[post("/recipients/modal/{recipient_id}")]
 pub async fn recipients_modal(
     recipient_id: web::Path<i32>,
@@ -183,6 +727,11 @@ pub async fn recipients_modal(
     let recipient_id = recipient_id.into_inner();
 
     if let Ok(recipient) = get_recipient(&mut conn, recipient_id) {
+        // The history shows the campaigns of the hub, other hubs must not see it
+        if recipient.hub_id != user.hub_id {
+            return HttpResponse::NotFound().finish();
+        }
+
         context.insert("recipient", &recipient);
 
         if let Ok(fields) = get_recipient_fields(&mut conn, recipient_id) {
@@ -194,6 +743,11 @@ pub async fn recipients_modal(
         if let Ok(groups) = get_hub_all_groups(&mut conn, recipient.hub_id) {
             context.insert("groups", &groups);
         }
+        if let Ok(history) =
+            get_recipient_email_history(&mut conn, recipient.hub_id, &recipient.email)
+        {
+            context.insert("history", &history);
+        }
     }
     context.insert("home_url", &server_config.auth_service_url);
 
@@ -223,13 +777,18 @@ pub async fn recipients_save(
         }
     };
 
+    let email = match check_recipient_email(&mut conn, user.hub_id, &form.email, Some(form.id)) {
+        Some(email) => email,
+        None => return redirect("/recipients"),
+    };
+
     let fields = form.field.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
     let values = form.value.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
     match save_recipient(
         &mut conn,
         form.id,
-        &form.name,
-        &form.email,
+        form.name.trim(),
+        &email,
         form.active,
         &form.groups,
         &fields,
@@ -2,13 +2,60 @@ use actix_web::{HttpResponse, Responder, get, post, web};
 use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
 use tera::Context;
 
-use crate::db::{DbPool, get_db_connection};
-use crate::forms::settings::SaveHubForm;
+use crate::db::{DbConnection, DbPool, get_db_connection};
+use crate::forms::settings::{
+    AddApiKeyForm, AddWebhookForm, DeleteWebhookForm, RevokeApiKeyForm, SaveHubForm,
+};
+use crate::models::api_key::{ApiKey, ApiScope, NewApiKey};
 use crate::models::auth::AuthenticatedUser;
 use crate::models::config::ServerConfig;
 use crate::models::hub::Hub;
+use crate::models::webhook::{NewWebhook, Webhook, WebhookEvent};
+use crate::repository::api_key::{create_api_key, get_hub_api_keys, revoke_api_key};
 use crate::repository::hub::{get_hub, update_hub};
+use crate::repository::webhook::{
+    create_webhook, delete_webhook, get_hub_webhook_deliveries, get_hub_webhooks,
+};
 use crate::routes::{alert_level_to_str, ensure_role, redirect, render_template};
+use crate::utils::list_imap_folders;
+
+fn render_settings(
+    conn: &mut DbConnection,
+    user: &AuthenticatedUser,
+    alerts: &[(&str, &str)],
+    server_config: &ServerConfig,
+    new_api_key: Option<&str>,
+) -> HttpResponse {
+    let mut context = Context::new();
+    context.insert("alerts", alerts);
+    context.insert("current_user", user);
+    context.insert("current_page", "settings");
+
+    let hub = match get_hub(conn, user.hub_id) {
+        Ok(hub) => hub,
+        Err(_) => Hub::new(user.hub_id),
+    };
+
+    context.insert("current_hub", &hub);
+    context.insert("imap_folders", &hub.get_imap_folders().join("\n"));
+    context.insert("home_url", &server_config.auth_service_url);
+    context.insert("api_scopes", &ApiScope::ALL);
+    context.insert("new_api_key", &new_api_key);
+
+    if let Ok(api_keys) = get_hub_api_keys(conn, user.hub_id) {
+        context.insert("api_keys", &api_keys);
+    }
+
+    context.insert("webhook_events", &WebhookEvent::ALL);
+    if let Ok(webhooks) = get_hub_webhooks(conn, user.hub_id) {
+        context.insert("webhooks", &webhooks);
+    }
+    if let Ok(deliveries) = get_hub_webhook_deliveries(conn, user.hub_id, 50) {
+        context.insert("webhook_deliveries", &deliveries);
+    }
+
+    render_template("settings/settings.html", &context)
+}
 
 #[get("/settings")]
 pub async fn settings(
@@ -30,20 +77,8 @@ pub async fn settings(
         .iter()
         .map(|f| (f.content(), alert_level_to_str(&f.level())))
         .collect::<Vec<_>>();
-    let mut context = Context::new();
-    context.insert("alerts", &alerts);
-    context.insert("current_user", &user);
-    context.insert("current_page", "settings");
 
-    let hub = match get_hub(&mut conn, user.hub_id) {
-        Ok(hub) => hub,
-        Err(_) => Hub::new(user.hub_id),
-    };
-
-    context.insert("current_hub", &hub);
-    context.insert("home_url", &server_config.auth_service_url);
-
-    render_template("settings/settings.html", &context)
+    render_settings(&mut conn, &user, &alerts, &server_config, None)
 }
 
 #[post("/settings/save")]
@@ -61,7 +96,11 @@ pub async fn settings_save(
         None => return HttpResponse::InternalServerError().finish(),
     };
 
-    match update_hub(&mut conn, &form.into()) {
+    // The form only edits the hub of the user, whatever id it carries
+    let mut hub: Hub = form.into();
+    hub.id = user.hub_id;
+
+    match update_hub(&mut conn, &hub) {
         Ok(_) => {
             FlashMessage::success("Хаб сохранён.").send();
         }
@@ -71,3 +110,216 @@ pub async fn settings_save(
     };
     redirect("/settings")
 }
+
+#[get("/settings/imap_folders")]
+pub async fn settings_imap_folders(
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "admin", None) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let mut context = Context::new();
+
+    match get_hub(&mut conn, user.hub_id) {
+        Ok(hub) => match web::block(move || list_imap_folders(&hub)).await {
+            Ok(Ok(folders)) => context.insert("folders", &folders),
+            Ok(Err(err)) => context.insert("error", &err.to_string()),
+            Err(err) => context.insert("error", &err.to_string()),
+        },
+        Err(err) => context.insert("error", &err.to_string()),
+    }
+
+    render_template("settings/imap_folders.html", &context)
+}
+
+#[post("/settings/api_keys/add")]
+pub async fn settings_api_keys_add(
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+    server_config: web::Data<ServerConfig>,
+    form: web::Bytes,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "admin", None) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let form: AddApiKeyForm = match serde_html_form::from_bytes(&form) {
+        Ok(form) => form,
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при обработке формы: {}", err)).send();
+            return redirect("/settings");
+        }
+    };
+
+    let scopes = form
+        .scopes
+        .iter()
+        .filter_map(|scope| ApiScope::parse(scope))
+        .map(|scope| scope.as_str())
+        .collect::<Vec<_>>();
+    if form.name.trim().is_empty() || scopes.is_empty() {
+        FlashMessage::error("Укажите название ключа и хотя бы одно право.").send();
+        return redirect("/settings");
+    }
+
+    let (prefix, secret, token) = ApiKey::generate();
+    let key_hash = match bcrypt::hash(&secret, bcrypt::DEFAULT_COST) {
+        Ok(key_hash) => key_hash,
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при создании ключа: {}", err)).send();
+            return redirect("/settings");
+        }
+    };
+
+    let new_api_key = NewApiKey {
+        hub_id: user.hub_id,
+        name: form.name.trim(),
+        prefix: &prefix,
+        key_hash: &key_hash,
+        scopes: &scopes.join(","),
+        created_at: chrono::Utc::now().naive_utc(),
+    };
+
+    // The key is shown once on this page and never stored in plain text
+    match create_api_key(&mut conn, &new_api_key) {
+        Ok(_) => {
+            let alerts = [(
+                "Ключ создан. Скопируйте его сейчас, позже он не будет показан.",
+                "success",
+            )];
+            render_settings(&mut conn, &user, &alerts, &server_config, Some(&token))
+        }
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при создании ключа: {}", err)).send();
+            redirect("/settings")
+        }
+    }
+}
+
+#[post("/settings/api_keys/revoke")]
+pub async fn settings_api_keys_revoke(
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+    web::Form(form): web::Form<RevokeApiKeyForm>,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "admin", None) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    match revoke_api_key(&mut conn, user.hub_id, form.id) {
+        Ok(_) => {
+            FlashMessage::success("Ключ отозван.").send();
+        }
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при отзыве ключа: {}", err)).send();
+        }
+    }
+
+    redirect("/settings")
+}
+
+#[post("/settings/webhooks/add")]
+pub async fn settings_webhooks_add(
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+    form: web::Bytes,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "admin", None) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let form: AddWebhookForm = match serde_html_form::from_bytes(&form) {
+        Ok(form) => form,
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при обработке формы: {}", err)).send();
+            return redirect("/settings");
+        }
+    };
+
+    let url = form.url.trim();
+    if let Err(message) = Webhook::check_url(url) {
+        FlashMessage::error(message).send();
+        return redirect("/settings");
+    }
+
+    let events = form
+        .events
+        .iter()
+        .filter_map(|event| WebhookEvent::parse(event))
+        .map(|event| event.as_str())
+        .collect::<Vec<_>>();
+    if events.is_empty() {
+        FlashMessage::error("Выберите хотя бы одно событие.").send();
+        return redirect("/settings");
+    }
+
+    let secret = Webhook::generate_secret();
+    let new_webhook = NewWebhook {
+        hub_id: user.hub_id,
+        url,
+        secret: &secret,
+        events: &events.join(","),
+        is_active: true,
+        created_at: chrono::Utc::now().naive_utc(),
+    };
+
+    match create_webhook(&mut conn, &new_webhook) {
+        Ok(_) => {
+            FlashMessage::success("Вебхук добавлен.").send();
+        }
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при добавлении вебхука: {}", err)).send();
+        }
+    }
+
+    redirect("/settings")
+}
+
+#[post("/settings/webhooks/delete")]
+pub async fn settings_webhooks_delete(
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+    web::Form(form): web::Form<DeleteWebhookForm>,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "admin", None) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    match delete_webhook(&mut conn, user.hub_id, form.id) {
+        Ok(_) => {
+            FlashMessage::success("Вебхук удалён.").send();
+        }
+        Err(err) => {
+            FlashMessage::error(format!("Ошибка при удалении вебхука: {}", err)).send();
+        }
+    }
+
+    redirect("/settings")
+}
@@ -0,0 +1,104 @@
+use actix_web::{HttpResponse, Responder, get, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use serde::{Deserialize, Serialize};
+use tera::Context;
+
+use crate::db::{DbPool, get_db_connection};
+use crate::models::auth::AuthenticatedUser;
+use crate::models::config::ServerConfig;
+use crate::repository::stats::{
+    StatsFilter, StatsInterval, get_hub_campaign_stats, get_hub_group_stats, get_hub_stats_totals,
+    get_hub_top_subjects,
+};
+use crate::routes::api::stats::{STATS_CAMPAIGNS, STATS_SUBJECTS};
+use crate::routes::{alert_level_to_str, ensure_role, render_template};
+
+/// Statistics filters, passed on to the JSON endpoint feeding the charts.
+#[derive(Deserialize, Serialize)]
+struct StatsParams {
+    since: Option<String>,
+    until: Option<String>,
+    interval: Option<String>,
+}
+
+impl StatsParams {
+    fn date(value: &Option<String>) -> Option<chrono::NaiveDate> {
+        chrono::NaiveDate::parse_from_str(value.as_deref()?, "%Y-%m-%d").ok()
+    }
+
+    fn to_filter(&self) -> StatsFilter {
+        StatsFilter {
+            since: Self::date(&self.since).and_then(|date| date.and_hms_opt(0, 0, 0)),
+            until: Self::date(&self.until)
+                .and_then(|date| date.succ_opt())
+                .and_then(|date| date.and_hms_opt(0, 0, 0)),
+        }
+    }
+
+    /// Valid values only, so the chart request is not rejected by the API.
+    fn normalized(&self, interval: StatsInterval) -> Self {
+        Self {
+            since: Self::date(&self.since).map(|date| date.to_string()),
+            until: Self::date(&self.until).map(|date| date.to_string()),
+            interval: Some(interval.as_str().to_string()),
+        }
+    }
+}
+
+#[get("/stats")]
+pub async fn stats(
+    params: web::Query<StatsParams>,
+    user: AuthenticatedUser,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<DbPool>,
+    server_config: web::Data<ServerConfig>,
+) -> impl Responder {
+    if let Err(response) = ensure_role(&user, "emailer", Some("/na")) {
+        return response;
+    };
+
+    let mut conn = match get_db_connection(&pool) {
+        Some(conn) => conn,
+        None => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let alerts = flash_messages
+        .iter()
+        .map(|f| (f.content(), alert_level_to_str(&f.level())))
+        .collect::<Vec<_>>();
+    let mut context = Context::new();
+    context.insert("alerts", &alerts);
+    context.insert("current_user", &user);
+    context.insert("current_page", "stats");
+    context.insert("home_url", &server_config.auth_service_url);
+
+    let filter = params.to_filter();
+    let interval = params
+        .interval
+        .as_deref()
+        .and_then(StatsInterval::parse)
+        .unwrap_or_default();
+
+    if let Ok(totals) = get_hub_stats_totals(&mut conn, user.hub_id, &filter) {
+        context.insert("totals", &totals);
+    }
+    if let Ok(campaigns) = get_hub_campaign_stats(&mut conn, user.hub_id, &filter, STATS_CAMPAIGNS)
+    {
+        context.insert("campaigns", &campaigns);
+    }
+    if let Ok(groups) = get_hub_group_stats(&mut conn, user.hub_id, &filter) {
+        context.insert("groups", &groups);
+    }
+    if let Ok(subjects) = get_hub_top_subjects(&mut conn, user.hub_id, &filter, STATS_SUBJECTS) {
+        context.insert("subjects", &subjects);
+    }
+
+    let params = params.normalized(interval);
+    context.insert(
+        "filter_query",
+        &serde_html_form::to_string(&params).unwrap_or_default(),
+    );
+    context.insert("filter", &params);
+
+    render_template("stats/stats.html", &context)
+}
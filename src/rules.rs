@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
+
+use crate::models::recipient::{Group, Recipient};
+
+/// What a condition of a smart group checks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuleSubject {
+    Name,
+    Email,
+    /// Part of the address after `@`.
+    Domain,
+    /// Membership in a group with static members.
+    Group,
+    /// Days since the recipient last opened an email, never is more than any number.
+    Opened,
+    /// Days since the recipient last replied.
+    Replied,
+    /// Custom field by name, ignoring case.
+    Field(String),
+}
+
+impl RuleSubject {
+    fn parse(word: &str) -> Self {
+        match word.to_lowercase().as_str() {
+            "name" | "имя" => RuleSubject::Name,
+            "email" | "адрес" => RuleSubject::Email,
+            "domain" | "домен" => RuleSubject::Domain,
+            "group" | "группа" => RuleSubject::Group,
+            "opened" | "открыл" => RuleSubject::Opened,
+            "replied" | "ответил" => RuleSubject::Replied,
+            _ => RuleSubject::Field(word.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleOp {
+    Eq,
+    Ne,
+    /// Substring, `~`.
+    Contains,
+    NotContains,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl RuleOp {
+    fn parse(token: &str) -> Option<Self> {
+        match token {
+            "=" => Some(RuleOp::Eq),
+            "!=" => Some(RuleOp::Ne),
+            "~" => Some(RuleOp::Contains),
+            "!~" => Some(RuleOp::NotContains),
+            "<" => Some(RuleOp::Lt),
+            "<=" => Some(RuleOp::Le),
+            ">" => Some(RuleOp::Gt),
+            ">=" => Some(RuleOp::Ge),
+            _ => None,
+        }
+    }
+
+    fn compare<T: PartialOrd>(&self, left: T, right: T) -> bool {
+        match self {
+            RuleOp::Eq => left == right,
+            RuleOp::Ne => left != right,
+            RuleOp::Lt => left < right,
+            RuleOp::Le => left <= right,
+            RuleOp::Gt => left > right,
+            RuleOp::Ge => left >= right,
+            RuleOp::Contains | RuleOp::NotContains => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    pub subject: RuleSubject,
+    pub op: RuleOp,
+    pub value: String,
+}
+
+/// Membership condition of a smart group, written like `city = Москва AND segment != retail`.
+///
+/// Text comparisons ignore case, a missing field compares as an empty value. Numbers are
+/// compared as numbers when both sides are numeric. `AND`, `OR`, `NOT` (or `И`, `ИЛИ`, `НЕ`)
+/// and parentheses combine the conditions, values with spaces go in double quotes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Rule {
+    Condition(Condition),
+    Not(Box<Rule>),
+    And(Vec<Rule>),
+    Or(Vec<Rule>),
+}
+
+/// What a rule is evaluated against.
+pub struct RecipientFacts<'a> {
+    pub recipient: &'a Recipient,
+    pub fields: &'a HashMap<String, String>,
+    pub groups: &'a [Group],
+    pub last_opened: Option<NaiveDateTime>,
+    pub last_replied: Option<NaiveDateTime>,
+    pub now: NaiveDateTime,
+}
+
+impl Rule {
+    /// Parses the rule, the error is a message for the user.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let tokens = tokenize(source)?;
+        if tokens.is_empty() {
+            return Err("Условие пустое".to_string());
+        }
+
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+        };
+        let rule = parser.or()?;
+        match parser.peek() {
+            None => Ok(rule),
+            Some(token) => Err(format!("Лишнее в условии: {}", token.text())),
+        }
+    }
+
+    pub fn matches(&self, facts: &RecipientFacts) -> bool {
+        match self {
+            Rule::Condition(condition) => condition.matches(facts),
+            Rule::Not(rule) => !rule.matches(facts),
+            Rule::And(rules) => rules.iter().all(|rule| rule.matches(facts)),
+            Rule::Or(rules) => rules.iter().any(|rule| rule.matches(facts)),
+        }
+    }
+}
+
+impl Condition {
+    fn matches(&self, facts: &RecipientFacts) -> bool {
+        let text = match &self.subject {
+            RuleSubject::Name => facts.recipient.name.clone(),
+            RuleSubject::Email => facts.recipient.email.clone(),
+            RuleSubject::Domain => facts
+                .recipient
+                .email
+                .rsplit_once('@')
+                .map(|(_, domain)| domain.to_string())
+                .unwrap_or_default(),
+            RuleSubject::Field(field) => facts
+                .fields
+                .iter()
+                .find(|(name, _)| name.to_lowercase() == field.to_lowercase())
+                .map(|(_, value)| value.clone())
+                .unwrap_or_default(),
+            RuleSubject::Group => {
+                let value = self.value.to_lowercase();
+                let member = facts
+                    .groups
+                    .iter()
+                    .any(|group| group.name.to_lowercase() == value);
+                return (self.op == RuleOp::Eq) == member;
+            }
+            RuleSubject::Opened | RuleSubject::Replied => {
+                let last = match self.subject {
+                    RuleSubject::Opened => facts.last_opened,
+                    _ => facts.last_replied,
+                };
+                let days = last.map(|last| (facts.now - last).num_days());
+                let limit = self.value.parse::<i64>().unwrap_or_default();
+                return match days {
+                    Some(days) => self.op.compare(days, limit),
+                    None => matches!(self.op, RuleOp::Ne | RuleOp::Gt | RuleOp::Ge),
+                };
+            }
+        };
+
+        let text = text.trim().to_lowercase();
+        let value = self.value.to_lowercase();
+        match self.op {
+            RuleOp::Contains => text.contains(&value),
+            RuleOp::NotContains => !text.contains(&value),
+            op => match (text.parse::<f64>(), value.parse::<f64>()) {
+                (Ok(text), Ok(value)) => op.compare(text, value),
+                _ if matches!(op, RuleOp::Eq | RuleOp::Ne) => op.compare(text, value),
+                _ => false,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+impl Token {
+    fn text(&self) -> String {
+        match self {
+            Token::Word(word) => word.clone(),
+            Token::Quoted(text) => format!("\"{}\"", text),
+            Token::Op(op) => op.to_string(),
+            Token::Open => "(".to_string(),
+            Token::Close => ")".to_string(),
+        }
+    }
+
+    /// `AND`, `OR` or `NOT` in either language, quoted words are never keywords.
+    fn is_keyword(&self, english: &str, russian: &str) -> bool {
+        match self {
+            Token::Word(word) => {
+                word.eq_ignore_ascii_case(english) || word.to_uppercase() == russian
+            }
+            _ => false,
+        }
+    }
+}
+
+const OPERATORS: [&str; 8] = ["!=", "!~", "<=", ">=", "=", "~", "<", ">"];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+
+    loop {
+        rest = rest.trim_start();
+        let Some(c) = rest.chars().next() else {
+            return Ok(tokens);
+        };
+
+        if c == '(' || c == ')' {
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            rest = &rest[1..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else if c == '"' {
+            let mut text = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '"')) => break i + 2,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c)) => text.push(c),
+                        None => return Err("Не закрыта кавычка".to_string()),
+                    },
+                    Some((_, c)) => text.push(c),
+                    None => return Err("Не закрыта кавычка".to_string()),
+                }
+            };
+            tokens.push(Token::Quoted(text));
+            rest = &rest[end..];
+        } else if c == '!' {
+            return Err("Ожидалось != или !~".to_string());
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || "()\"=!~<>".contains(c))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Rule, String> {
+        let mut rules = vec![self.and()?];
+        while self
+            .peek()
+            .is_some_and(|token| token.is_keyword("OR", "ИЛИ"))
+        {
+            self.position += 1;
+            rules.push(self.and()?);
+        }
+
+        Ok(match rules.len() {
+            1 => rules.remove(0),
+            _ => Rule::Or(rules),
+        })
+    }
+
+    fn and(&mut self) -> Result<Rule, String> {
+        let mut rules = vec![self.unary()?];
+        while self
+            .peek()
+            .is_some_and(|token| token.is_keyword("AND", "И"))
+        {
+            self.position += 1;
+            rules.push(self.unary()?);
+        }
+
+        Ok(match rules.len() {
+            1 => rules.remove(0),
+            _ => Rule::And(rules),
+        })
+    }
+
+    fn unary(&mut self) -> Result<Rule, String> {
+        match self.next() {
+            Some(token) if token.is_keyword("NOT", "НЕ") => {
+                Ok(Rule::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::Open) => {
+                let rule = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(rule),
+                    _ => Err("Не закрыта скобка".to_string()),
+                }
+            }
+            Some(Token::Word(subject)) | Some(Token::Quoted(subject)) => self.condition(&subject),
+            Some(token) => Err(format!("Ожидалось поле, а не {}", token.text())),
+            None => Err("Условие не закончено".to_string()),
+        }
+    }
+
+    fn condition(&mut self, subject: &str) -> Result<Rule, String> {
+        let subject = RuleSubject::parse(subject);
+        let op = match self.next() {
+            Some(Token::Op(op)) => RuleOp::parse(op).unwrap_or(RuleOp::Eq),
+            Some(token) => return Err(format!("Ожидалось сравнение, а не {}", token.text())),
+            None => return Err("Условие не закончено".to_string()),
+        };
+        let value = match self.next() {
+            Some(Token::Word(value)) | Some(Token::Quoted(value)) => value,
+            Some(token) => return Err(format!("Ожидалось значение, а не {}", token.text())),
+            None => return Err("Условие не закончено".to_string()),
+        };
+
+        match subject {
+            RuleSubject::Group if !matches!(op, RuleOp::Eq | RuleOp::Ne) => {
+                return Err("Группу можно сравнить только через = и !=".to_string());
+            }
+            RuleSubject::Opened | RuleSubject::Replied
+                if matches!(op, RuleOp::Contains | RuleOp::NotContains) =>
+            {
+                return Err("Дни можно сравнить только через = != < <= > >=".to_string());
+            }
+            RuleSubject::Opened | RuleSubject::Replied if value.parse::<i64>().is_err() => {
+                return Err(format!("Ожидалось число дней, а не {}", value));
+            }
+            _ => {}
+        }
+
+        Ok(Rule::Condition(Condition { subject, op, value }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, 19)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+    }
+
+    fn recipient(email: &str) -> Recipient {
+        Recipient {
+            id: 1,
+            name: "Иван Петров".to_string(),
+            email: email.to_string(),
+            hub_id: 1,
+            created_at: None,
+            updated_at: None,
+            unsubscribed_at: None,
+        }
+    }
+
+    fn matches(source: &str, fields: &[(&str, &str)], last_opened: Option<NaiveDateTime>) -> bool {
+        let recipient = recipient("ivan@example.com");
+        let fields = fields
+            .iter()
+            .map(|(field, value)| (field.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        let facts = RecipientFacts {
+            recipient: &recipient,
+            fields: &fields,
+            groups: &[],
+            last_opened,
+            last_replied: None,
+            now: now(),
+        };
+        Rule::parse(source).unwrap().matches(&facts)
+    }
+
+    #[test]
+    fn russian_keywords() {
+        let fields = [("город", "Москва"), ("сегмент", "retail")];
+        assert!(matches("город = москва И сегмент = retail", &fields, None));
+        assert!(!matches(
+            "город = москва и сегмент != retail",
+            &fields,
+            None
+        ));
+        assert!(matches(
+            "город = Казань ИЛИ сегмент = retail",
+            &fields,
+            None
+        ));
+        assert!(matches("НЕ город = Казань", &fields, None));
+        assert_eq!(
+            Rule::parse("a = 1 И b = 2 ИЛИ c = 3").unwrap(),
+            Rule::parse("(a = 1 AND b = 2) OR c = 3").unwrap()
+        );
+    }
+
+    #[test]
+    fn quoted_values() {
+        let fields = [("город", "Нижний Новгород"), ("note", "say \"hi\"")];
+        assert!(matches("город = \"нижний новгород\"", &fields, None));
+        assert!(matches("note = \"say \\\"hi\\\"\"", &fields, None));
+        // A quoted keyword is a value
+        assert!(matches("город != \"AND\"", &fields, None));
+        assert!(matches("\"город\" ~ новгород", &fields, None));
+    }
+
+    #[test]
+    fn numbers_and_text() {
+        let fields = [("orders", "10"), ("city", "Москва")];
+        // 10 > 9 as numbers, "10" < "9" as text
+        assert!(matches("orders > 9", &fields, None));
+        assert!(matches("orders = 10.0", &fields, None));
+        assert!(!matches("city > 9", &fields, None));
+        assert!(!matches("city < 9", &fields, None));
+        assert!(matches("city != 9", &fields, None));
+        // A missing field is empty
+        assert!(matches("missing = \"\"", &fields, None));
+    }
+
+    #[test]
+    fn opened_without_history() {
+        assert!(matches("opened > 30", &[], None));
+        assert!(matches("opened != 0", &[], None));
+        assert!(!matches("opened < 30", &[], None));
+        assert!(!matches("opened = 0", &[], None));
+
+        let week_ago = Some(now() - chrono::Duration::days(7));
+        assert!(matches("opened < 30", &[], week_ago));
+        assert!(!matches("opened > 30", &[], week_ago));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Rule::parse("  "), Err("Условие пустое".to_string()));
+        assert_eq!(
+            Rule::parse("city = \"Москва"),
+            Err("Не закрыта кавычка".to_string())
+        );
+        assert_eq!(
+            Rule::parse("(city = Москва"),
+            Err("Не закрыта скобка".to_string())
+        );
+        assert_eq!(
+            Rule::parse("city ="),
+            Err("Условие не закончено".to_string())
+        );
+        assert_eq!(
+            Rule::parse("city Москва"),
+            Err("Ожидалось сравнение, а не Москва".to_string())
+        );
+        assert_eq!(
+            Rule::parse("city ! Москва"),
+            Err("Ожидалось != или !~".to_string())
+        );
+        assert_eq!(
+            Rule::parse("city = Москва extra"),
+            Err("Лишнее в условии: extra".to_string())
+        );
+        assert!(Rule::parse("group > vip").is_err());
+        assert!(Rule::parse("opened ~ 5").is_err());
+        assert_eq!(
+            Rule::parse("opened > week"),
+            Err("Ожидалось число дней, а не week".to_string())
+        );
+    }
+}
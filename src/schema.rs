@@ -1,5 +1,43 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_keys (id) {
+        id -> Integer,
+        hub_id -> Integer,
+        name -> Text,
+        prefix -> Text,
+        key_hash -> Text,
+        scopes -> Text,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    email_replies (id) {
+        id -> Integer,
+        email_recipient_id -> Integer,
+        message_id -> Nullable<Text>,
+        sender -> Text,
+        received_at -> Timestamp,
+        subject -> Nullable<Text>,
+        body -> Text,
+        is_auto_reply -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    email_reply_attachments (id) {
+        id -> Integer,
+        reply_id -> Integer,
+        name -> Nullable<Text>,
+        mime -> Nullable<Text>,
+        size -> Integer,
+    }
+}
+
 diesel::table! {
     email_recipients (id) {
         id -> Integer,
@@ -9,6 +47,12 @@ diesel::table! {
         updated_at -> Timestamp,
         is_sent -> Bool,
         replied -> Bool,
+        auto_replied -> Bool,
+        unsubscribed -> Bool,
+        sent_at -> Nullable<Timestamp>,
+        opened_at -> Nullable<Timestamp>,
+        replied_at -> Nullable<Timestamp>,
+        clicked_at -> Nullable<Timestamp>,
     }
 }
 
@@ -26,6 +70,7 @@ diesel::table! {
         num_opened -> Integer,
         num_replied -> Integer,
         hub_id -> Integer,
+        is_transactional -> Bool,
     }
 }
 
@@ -36,6 +81,7 @@ diesel::table! {
         hub_id -> Integer,
         created_at -> Nullable<Timestamp>,
         updated_at -> Nullable<Timestamp>,
+        rules -> Nullable<Text>,
     }
 }
 
@@ -46,6 +92,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    hub_imap_folders (hub_id, folder) {
+        hub_id -> Integer,
+        folder -> Text,
+        uid_validity -> BigInt,
+        last_uid -> BigInt,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     hubs (id) {
         id -> Integer,
@@ -59,6 +115,22 @@ diesel::table! {
         imap_server -> Nullable<Text>,
         imap_port -> Nullable<Integer>,
         email_template -> Nullable<Text>,
+        imap_folders -> Nullable<Text>,
+        imap_sent_folder -> Nullable<Text>,
+        imap_sent_all -> Bool,
+        imap_unsubscribe_folder -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    message_templates (id) {
+        id -> Integer,
+        hub_id -> Integer,
+        name -> Text,
+        subject -> Text,
+        body -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -70,6 +142,22 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    recipient_imports (id) {
+        id -> Integer,
+        hub_id -> Integer,
+        file_name -> Text,
+        content -> Binary,
+        created_at -> Timestamp,
+        encoding -> Nullable<Text>,
+        delimiter -> Nullable<Text>,
+        column_map -> Nullable<Text>,
+        sheet -> Nullable<Text>,
+        mode -> Nullable<Text>,
+        assign_group -> Nullable<Text>,
+    }
+}
+
 diesel::table! {
     recipients (id) {
         id -> Integer,
@@ -82,20 +170,64 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Integer,
+        webhook_id -> Integer,
+        event -> Text,
+        payload -> Text,
+        status -> Text,
+        attempts -> Integer,
+        next_attempt_at -> Timestamp,
+        response_status -> Nullable<Integer>,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamp,
+        delivered_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    webhooks (id) {
+        id -> Integer,
+        hub_id -> Integer,
+        url -> Text,
+        secret -> Text,
+        events -> Text,
+        is_active -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::joinable!(api_keys -> hubs (hub_id));
 diesel::joinable!(email_recipients -> emails (email_id));
+diesel::joinable!(email_replies -> email_recipients (email_recipient_id));
+diesel::joinable!(email_reply_attachments -> email_replies (reply_id));
 diesel::joinable!(emails -> hubs (hub_id));
 diesel::joinable!(groups -> hubs (hub_id));
 diesel::joinable!(groups_recipients -> groups (group_id));
 diesel::joinable!(groups_recipients -> recipients (recipient_id));
+diesel::joinable!(hub_imap_folders -> hubs (hub_id));
+diesel::joinable!(message_templates -> hubs (hub_id));
 diesel::joinable!(recipient_fields -> recipients (recipient_id));
+diesel::joinable!(recipient_imports -> hubs (hub_id));
 diesel::joinable!(recipients -> hubs (hub_id));
+diesel::joinable!(webhook_deliveries -> webhooks (webhook_id));
+diesel::joinable!(webhooks -> hubs (hub_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
     email_recipients,
+    email_replies,
+    email_reply_attachments,
     emails,
     groups,
     groups_recipients,
+    hub_imap_folders,
     hubs,
+    message_templates,
     recipient_fields,
+    recipient_imports,
     recipients,
+    webhook_deliveries,
+    webhooks,
 );
@@ -1,9 +1,15 @@
-use std::{error::Error, io::Read};
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
 
 use actix_multipart::form::tempfile::TempFile;
+use imap::extensions::idle::SetReadTimeout;
 use log::info;
+use native_tls::TlsStream;
 
 use crate::models::config::ServerConfig;
+use crate::models::hub::Hub;
 
 pub fn send_zmq_email_id(id: i32, zmq_config: &ServerConfig) -> Result<(), Box<dyn Error>> {
     let context = zmq::Context::new();
@@ -19,9 +25,9 @@ pub fn send_zmq_email_id(id: i32, zmq_config: &ServerConfig) -> Result<(), Box<d
     Ok(())
 }
 
-pub fn read_attachment_file(
-    attachment: &mut TempFile,
-) -> std::io::Result<(Option<String>, Option<String>, Option<Vec<u8>>)> {
+pub type AttachmentFile = (Option<String>, Option<String>, Option<Vec<u8>>);
+
+pub fn read_attachment_file(attachment: &mut TempFile) -> std::io::Result<AttachmentFile> {
     let mut buf = Vec::new();
     attachment.file.read_to_end(&mut buf)?; // propagate error properly
 
@@ -33,3 +39,135 @@ pub fn read_attachment_file(
 
     Ok((file_name, file_mime, Some(buf)))
 }
+
+/// Longest a single read or write may block outside IDLE, so a server that stops answering
+/// ends the session with an error instead of hanging the caller.
+pub const IMAP_IO_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// TLS stream with read and write timeouts. IDLE sets its own read timeout while waiting and
+/// clears it afterwards, clearing restores `IMAP_IO_TIMEOUT` here.
+pub struct ImapStream(TlsStream<TcpStream>);
+
+impl Read for ImapStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for ImapStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl SetReadTimeout for ImapStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> imap::error::Result<()> {
+        self.0
+            .get_ref()
+            .set_read_timeout(Some(timeout.unwrap_or(IMAP_IO_TIMEOUT)))
+            .map_err(imap::error::Error::Io)
+    }
+}
+
+pub type ImapSession = imap::Session<ImapStream>;
+
+/// Opens an IMAP session over TLS, every step is bounded by `IMAP_IO_TIMEOUT`.
+pub fn connect_imap_server(
+    server: &str,
+    port: u16,
+    login: &str,
+    password: &str,
+) -> imap::error::Result<ImapSession> {
+    let address = (server, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Cannot resolve imap server"))?;
+    let tcp = TcpStream::connect_timeout(&address, IMAP_IO_TIMEOUT)?;
+    tcp.set_read_timeout(Some(IMAP_IO_TIMEOUT))?;
+    tcp.set_write_timeout(Some(IMAP_IO_TIMEOUT))?;
+
+    let tls = native_tls::TlsConnector::builder().build()?;
+    let stream = tls.connect(server, tcp)?;
+
+    let mut client = imap::Client::new(ImapStream(stream));
+    client.read_greeting()?;
+    client.login(login, password).map_err(|e| e.0)
+}
+
+/// Opens an IMAP session with the hub credentials.
+pub fn connect_imap(hub: &Hub) -> imap::error::Result<ImapSession> {
+    let (Some(server), Some(port), Some(login), Some(password)) =
+        (&hub.imap_server, hub.imap_port, &hub.login, &hub.password)
+    else {
+        return Err(imap::error::Error::Bad(
+            "IMAP settings are incomplete".to_string(),
+        ));
+    };
+
+    connect_imap_server(server, port as u16, login, password)
+}
+
+/// Lists selectable folders of the hub mailbox as raw names paired with readable labels.
+pub fn list_imap_folders(hub: &Hub) -> imap::error::Result<Vec<(String, String)>> {
+    let mut session = connect_imap(hub)?;
+
+    let names = session.list(None, Some("*"))?;
+    let folders = names
+        .iter()
+        .filter(|name| {
+            !name
+                .attributes()
+                .contains(&imap::types::NameAttribute::NoSelect)
+        })
+        .map(|name| (name.name().to_string(), decode_imap_utf7(name.name())))
+        .collect();
+
+    session.logout()?;
+
+    Ok(folders)
+}
+
+/// Decodes a mailbox name from the modified UTF-7 used by IMAP (RFC 3501, 5.1.3).
+pub fn decode_imap_utf7(name: &str) -> String {
+    fn sextet(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as u32),
+            b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+            b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+            b'+' => Some(62),
+            b',' => Some(63),
+            _ => None,
+        }
+    }
+
+    let mut result = String::new();
+    let mut parts = name.split('&');
+    result.push_str(parts.next().unwrap_or_default());
+
+    for part in parts {
+        let (encoded, rest) = part.split_once('-').unwrap_or((part, ""));
+        if encoded.is_empty() {
+            result.push('&');
+        } else {
+            let mut units = Vec::new();
+            let (mut buffer, mut bits) = (0u32, 0);
+            for value in encoded.bytes().filter_map(sextet) {
+                buffer = (buffer << 6) | value;
+                bits += 6;
+                if bits >= 16 {
+                    bits -= 16;
+                    units.push((buffer >> bits) as u16);
+                    buffer &= (1 << bits) - 1;
+                }
+            }
+            result.extend(char::decode_utf16(units).map(|c| c.unwrap_or('\u{FFFD}')));
+        }
+        result.push_str(rest);
+    }
+
+    result
+}